@@ -10,8 +10,36 @@ export interface RawMailListItem {
     created_at: string;
     from?: RawMailbox[];
     sender?: RawMailbox;
+    reply_to?: RawAddress[];
     to: RawAddress[];
+    cc?: RawAddress[];
+    bcc?: RawAddress[];
     subject: string;
+    date?: string;
+    message_id?: string;
+    in_reply_to?: string[];
+    references?: string[];
+    envelope: RawEnvelope | null;
+}
+
+export interface RawEnvelope {
+    client_addr: string;
+    helo: string | null;
+    reverse_path: string;
+    mail_parameters: Record<string, string>;
+    recipients: RawRecipient[];
+    tls: RawTlsInfo | null;
+    auth_username: string | null;
+}
+
+export interface RawRecipient {
+    forward_path: string;
+    rcpt_parameters: Record<string, string>;
+}
+
+export interface RawTlsInfo {
+    protocol: string;
+    cipher: string;
 }
 
 export type RawAddressMailbox = { type: RawAddressType.Mailbox } & RawMailbox;
@@ -68,6 +68,10 @@ export class WebSocketApi {
                 case FromServerMessageType.NewMailAvailable:
                     this.onNewMailAvailable();
                     break;
+                case FromServerMessageType.MailDeleted:
+                    // the list has to be refreshed just like for new mail
+                    this.onNewMailAvailable();
+                    break;
                 default:
                     console.error("unknown message type", { message });
                     break;
@@ -120,10 +124,16 @@ type ToServerMessage = ListenForNewMailData;
 
 enum FromServerMessageType {
     NewMailAvailable = "NewMailAvailable",
+    MailDeleted = "MailDeleted",
 }
 
 interface NewMailAvailable {
     type: FromServerMessageType.NewMailAvailable,
 }
 
-type FromServerMessage = NewMailAvailable;
\ No newline at end of file
+interface MailDeleted {
+    type: FromServerMessageType.MailDeleted,
+    ids: number[],
+}
+
+type FromServerMessage = NewMailAvailable | MailDeleted;
\ No newline at end of file
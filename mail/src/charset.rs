@@ -0,0 +1,31 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::borrow::Cow;
+
+/// Decodes text in the given MIME charset into UTF-8. Unknown charsets are decoded
+/// as UTF-8 and invalid sequences are replaced with U+FFFD.
+///
+/// Labels are resolved according to the WHATWG Encoding Standard, which covers
+/// UTF-8, ISO-8859-x, Windows-125x, KOI8, the common CJK charsets and their usual
+/// aliases. Note that `iso-8859-1` and `us-ascii` are decoded as `windows-1252`,
+/// which is a superset of both.
+pub fn decode<'a>(charset: &str, bytes: &'a [u8]) -> Cow<'a, str> {
+    match encoding_rs::Encoding::for_label_no_replacement(charset.trim().as_bytes()) {
+        Some(encoding) => encoding.decode_without_bom_handling(bytes).0,
+        None => String::from_utf8_lossy(bytes),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_charsets() {
+        assert_eq!(decode("UTF-8", "Grüße".as_bytes()), "Grüße");
+        assert_eq!(decode("iso-8859-1", b"Gr\xfc\xdfe"), "Grüße");
+        assert_eq!(decode("ISO-8859-2", b"\xb1\xe6"), "ąć");
+        assert_eq!(decode("windows-1251", b"\xcf\xf0\xe8"), "При");
+        assert_eq!(decode("x-unknown", b"plain \xff"), "plain \u{FFFD}");
+    }
+}
@@ -0,0 +1,183 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod base64;
+mod quoted_printable;
+
+use std::fmt::Display;
+
+use nom::{
+    character::complete::space0,
+    combinator::eof,
+    sequence::{pair, terminated},
+};
+
+use crate::{
+    header::{parser::mime::token, typed::TypedHeader, HeaderName, CONTENT_TRANSFER_ENCODING},
+    Encoding, SinglePart,
+};
+
+pub use self::{base64::Base64Decoder, quoted_printable::QuotedPrintableDecoder};
+
+pub(crate) use self::quoted_printable::hex_value;
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Encoding::SevenBit => "7bit",
+            Encoding::EightBit => "8bit",
+            Encoding::Binary => "binary",
+            Encoding::QuotedPrintable => "quoted-printable",
+            Encoding::Base64 => "base64",
+        }
+    }
+
+    pub fn decoder(&self) -> Decoder {
+        let kind = match self {
+            Encoding::SevenBit | Encoding::EightBit | Encoding::Binary => DecoderKind::Identity,
+            Encoding::QuotedPrintable => DecoderKind::QuotedPrintable(Default::default()),
+            Encoding::Base64 => DecoderKind::Base64(Default::default()),
+        };
+        Decoder { kind }
+    }
+}
+
+impl Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TypedHeader for Encoding {
+    type Error = InvalidEncoding;
+    const NAME: HeaderName<'static> = CONTENT_TRANSFER_ENCODING;
+
+    fn decode(encoded: &str) -> Result<Self, Self::Error> {
+        let (_, mechanism) = terminated(token, pair(space0, eof))(encoded.as_bytes())
+            .map_err(|_| InvalidEncoding::new())?;
+        match &mechanism.to_ascii_lowercase()[..] {
+            b"7bit" => Ok(Encoding::SevenBit),
+            b"8bit" => Ok(Encoding::EightBit),
+            b"binary" => Ok(Encoding::Binary),
+            b"quoted-printable" => Ok(Encoding::QuotedPrintable),
+            b"base64" => Ok(Encoding::Base64),
+            _ => Err(InvalidEncoding::new()),
+        }
+    }
+}
+
+impl SinglePart {
+    /// Returns a decoder for this part's body.
+    pub fn decoder(&self) -> Decoder {
+        self.encoding.decoder()
+    }
+
+    /// Decodes the entire body of this part. `message` must be the same buffer that
+    /// the entity tree was parsed from.
+    pub fn decode(&self, message: &[u8]) -> Vec<u8> {
+        let body = &message[self.body.clone()];
+        let mut decoded = Vec::with_capacity(body.len());
+        let mut decoder = self.decoder();
+        decoder.decode(body, &mut decoded);
+        decoder.finish(&mut decoded);
+        decoded
+    }
+}
+
+/// A streaming Content-Transfer-Encoding decoder. Input may be split into chunks at
+/// arbitrary positions.
+pub struct Decoder {
+    kind: DecoderKind,
+}
+
+enum DecoderKind {
+    Identity,
+    Base64(Base64Decoder),
+    QuotedPrintable(QuotedPrintableDecoder),
+}
+
+impl Decoder {
+    /// Decodes a chunk of input and appends the decoded bytes to `output`.
+    pub fn decode(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        match &mut self.kind {
+            DecoderKind::Identity => output.extend_from_slice(input),
+            DecoderKind::Base64(decoder) => decoder.decode(input, output),
+            DecoderKind::QuotedPrintable(decoder) => decoder.decode(input, output),
+        }
+    }
+
+    /// Flushes any input that is still buffered by the decoder into `output`.
+    pub fn finish(self, output: &mut Vec<u8>) {
+        match self.kind {
+            DecoderKind::Identity => {}
+            DecoderKind::Base64(decoder) => decoder.finish(output),
+            DecoderKind::QuotedPrintable(decoder) => decoder.finish(output),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidEncoding {
+    _inner: (),
+}
+
+impl InvalidEncoding {
+    fn new() -> Self {
+        Self { _inner: () }
+    }
+}
+
+impl Display for InvalidEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid content-transfer-encoding header")
+    }
+}
+
+impl std::error::Error for InvalidEncoding {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Entity;
+
+    #[test]
+    fn decode_encoding_header() {
+        assert_eq!(Encoding::decode("Base64").unwrap(), Encoding::Base64);
+        assert_eq!(
+            Encoding::decode(" quoted-printable ").unwrap(),
+            Encoding::QuotedPrintable
+        );
+        assert_eq!(
+            Encoding::decode("8bit (comment)").unwrap(),
+            Encoding::EightBit
+        );
+        assert!(Encoding::decode("x-uuencode").is_err());
+    }
+
+    #[test]
+    fn decode_single_part() {
+        let message = b"Content-Type: application/octet-stream\r\n\
+            Content-Transfer-Encoding: base64\r\n\
+            \r\n\
+            AP8Q\r\n\
+            7w==\r\n";
+        let Entity::SinglePart(part) = Entity::parse(message).unwrap() else {
+            panic!("expected a single part entity");
+        };
+        assert_eq!(part.encoding, Encoding::Base64);
+        assert_eq!(part.decode(message), [0x00, 0xFF, 0x10, 0xEF]);
+    }
+
+    #[test]
+    fn decode_in_chunks() {
+        let input = b"SGVsbG8s\r\nIHdvcmxk\r\nIQ==\r\n";
+        for chunk_size in 1..input.len() {
+            let mut decoder = Encoding::Base64.decoder();
+            let mut output = Vec::new();
+            for chunk in input.chunks(chunk_size) {
+                decoder.decode(chunk, &mut output);
+            }
+            decoder.finish(&mut output);
+            assert_eq!(output, b"Hello, world!");
+        }
+    }
+}
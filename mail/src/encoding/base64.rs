@@ -0,0 +1,111 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+/// A lenient base64 decoder (RFC 2045 §6.8).
+///
+/// Characters outside of the base64 alphabet (line breaks, whitespace and garbage
+/// inserted by gateways) are ignored and padding is not required. Padding in the
+/// middle of the input ends the current quantum, so concatenated base64 blocks
+/// decode to the concatenation of their contents.
+#[derive(Default)]
+pub struct Base64Decoder {
+    quantum: u32,
+    count: u8,
+}
+
+impl Base64Decoder {
+    pub fn decode(&mut self, input: &[u8], output: &mut Vec<u8>) {
+        output.reserve(input.len() / 4 * 3);
+
+        for &ch in input {
+            if ch == b'=' {
+                self.flush(output);
+                continue;
+            }
+
+            let Some(value) = decode_char(ch) else {
+                continue;
+            };
+
+            self.quantum = (self.quantum << 6) | u32::from(value);
+            self.count += 1;
+            if self.count == 4 {
+                output.extend_from_slice(&self.quantum.to_be_bytes()[1..]);
+                self.quantum = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    pub fn finish(mut self, output: &mut Vec<u8>) {
+        self.flush(output);
+    }
+
+    /// Outputs the bytes of an incomplete quantum. A single leftover character
+    /// does not contain a full byte and is discarded.
+    fn flush(&mut self, output: &mut Vec<u8>) {
+        match self.count {
+            2 => output.push((self.quantum >> 4) as u8),
+            3 => output.extend_from_slice(&((self.quantum >> 2) as u16).to_be_bytes()),
+            _ => {}
+        }
+        self.quantum = 0;
+        self.count = 0;
+    }
+}
+
+fn decode_char(ch: u8) -> Option<u8> {
+    match ch {
+        b'A'..=b'Z' => Some(ch - b'A'),
+        b'a'..=b'z' => Some(ch - b'a' + 26),
+        b'0'..=b'9' => Some(ch - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(input: &[u8]) -> Vec<u8> {
+        let mut decoder = Base64Decoder::default();
+        let mut output = Vec::new();
+        decoder.decode(input, &mut output);
+        decoder.finish(&mut output);
+        output
+    }
+
+    #[test]
+    fn decode_padding() {
+        assert_eq!(decode(b""), b"");
+        assert_eq!(decode(b"Zg=="), b"f");
+        assert_eq!(decode(b"Zm8="), b"fo");
+        assert_eq!(decode(b"Zm9v"), b"foo");
+        assert_eq!(decode(b"Zm9vYg=="), b"foob");
+        assert_eq!(decode(b"Zm9vYmE="), b"fooba");
+        assert_eq!(decode(b"Zm9vYmFy"), b"foobar");
+    }
+
+    #[test]
+    fn decode_missing_padding() {
+        assert_eq!(decode(b"Zg"), b"f");
+        assert_eq!(decode(b"Zm8"), b"fo");
+    }
+
+    #[test]
+    fn decode_sloppy_lines() {
+        assert_eq!(decode(b"Zm9v\r\nYmFy\nIGJh\r\n  eg==\r\n"), b"foobar baz");
+        assert_eq!(decode(b"Zm9vY\r\nmFy"), b"foobar");
+    }
+
+    #[test]
+    fn decode_concatenated() {
+        assert_eq!(decode(b"Zg==Zm8="), b"ffo");
+    }
+
+    #[test]
+    fn decode_binary() {
+        assert_eq!(decode(b"AP8Q7w=="), [0x00, 0xFF, 0x10, 0xEF]);
+    }
+}
@@ -0,0 +1,148 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+/// A lenient quoted-printable decoder (RFC 2045 §6.7).
+///
+/// Input is buffered line by line so that trailing whitespace on encoded lines can be
+/// removed and soft line breaks can be detected regardless of how the input is split.
+/// Lines are not required to fit within 76 characters, soft line breaks may end in a
+/// bare LF and malformed `=` sequences are passed through literally.
+#[derive(Default)]
+pub struct QuotedPrintableDecoder {
+    line: Vec<u8>,
+}
+
+impl QuotedPrintableDecoder {
+    pub fn decode(&mut self, mut input: &[u8], output: &mut Vec<u8>) {
+        while let Some(idx) = input.iter().position(|&ch| ch == b'\n') {
+            let (line, rest) = input.split_at(idx + 1);
+            input = rest;
+
+            if self.line.is_empty() {
+                decode_line(line, output);
+            } else {
+                self.line.extend_from_slice(line);
+                decode_line(&self.line, output);
+                self.line.clear();
+            }
+        }
+        self.line.extend_from_slice(input);
+    }
+
+    pub fn finish(self, output: &mut Vec<u8>) {
+        decode_line(&self.line, output);
+    }
+}
+
+/// Decodes a single encoded line, including its line break (if any).
+fn decode_line(line: &[u8], output: &mut Vec<u8>) {
+    let (content, line_break) = split_line_break(line);
+
+    // rule 3: trailing whitespace on an encoded line must be deleted
+    let content = trim_end_whitespace(content);
+    let (content, soft_break) = match content.strip_suffix(b"=") {
+        Some(content) => (content, true),
+        None => (content, false),
+    };
+
+    let mut idx = 0;
+    while idx < content.len() {
+        let ch = content[idx];
+        if ch == b'=' {
+            let escaped = content
+                .get(idx + 1..idx + 3)
+                .and_then(|hex| Some(hex_value(hex[0])? << 4 | hex_value(hex[1])?));
+            if let Some(byte) = escaped {
+                output.push(byte);
+                idx += 3;
+                continue;
+            }
+        }
+        output.push(ch);
+        idx += 1;
+    }
+
+    if !soft_break {
+        output.extend_from_slice(line_break);
+    }
+}
+
+fn split_line_break(line: &[u8]) -> (&[u8], &[u8]) {
+    let content = line
+        .strip_suffix(b"\r\n")
+        .or_else(|| line.strip_suffix(b"\n"))
+        .unwrap_or(line);
+    line.split_at(content.len())
+}
+
+fn trim_end_whitespace(mut content: &[u8]) -> &[u8] {
+    while let Some((b' ' | b'\t', rest)) = content.split_last() {
+        content = rest;
+    }
+    content
+}
+
+/// Lowercase hex digits are not allowed, but some mailers produce them anyway.
+pub(crate) fn hex_value(ch: u8) -> Option<u8> {
+    match ch {
+        b'0'..=b'9' => Some(ch - b'0'),
+        b'A'..=b'F' => Some(ch - b'A' + 10),
+        b'a'..=b'f' => Some(ch - b'a' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(input: &[u8]) -> Vec<u8> {
+        let mut decoder = QuotedPrintableDecoder::default();
+        let mut output = Vec::new();
+        decoder.decode(input, &mut output);
+        decoder.finish(&mut output);
+        output
+    }
+
+    #[test]
+    fn decode_escapes() {
+        assert_eq!(decode(b"caf=C3=A9 =3D tea"), "café = tea".as_bytes());
+        assert_eq!(decode(b"=e2=82=ac"), "€".as_bytes());
+    }
+
+    #[test]
+    fn decode_line_breaks() {
+        assert_eq!(decode(b"hard\r\nbreak\r\n"), b"hard\r\nbreak\r\n");
+        assert_eq!(decode(b"soft=\r\nbreak"), b"softbreak");
+        assert_eq!(decode(b"bare lf=\nsoft break"), b"bare lfsoft break");
+        assert_eq!(decode(b"padded soft=  \r\nbreak"), b"padded softbreak");
+    }
+
+    #[test]
+    fn decode_trailing_whitespace() {
+        assert_eq!(
+            decode(b"trailing  \t\r\nspace=20\r\n"),
+            b"trailing\r\nspace \r\n"
+        );
+    }
+
+    #[test]
+    fn decode_malformed() {
+        assert_eq!(decode(b"1 + 1 = 2"), b"1 + 1 = 2");
+        assert_eq!(decode(b"=ZZ=4"), b"=ZZ=4");
+    }
+
+    #[test]
+    fn decode_in_chunks() {
+        let input = b"a long line that was wrapped=\r\n by the =E2=\r\n=82=AC mailer\r\nend";
+        let expected = "a long line that was wrapped by the € mailer\r\nend".as_bytes();
+        for chunk_size in 1..input.len() {
+            let mut decoder = QuotedPrintableDecoder::default();
+            let mut output = Vec::new();
+            for chunk in input.chunks(chunk_size) {
+                decoder.decode(chunk, &mut output);
+            }
+            decoder.finish(&mut output);
+            assert_eq!(output, expected);
+        }
+    }
+}
@@ -0,0 +1,357 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::ops::Range;
+
+use crate::{
+    header::typed::{ContentDisposition, ContentType},
+    Encoding, Entity, HeaderMap, InvalidEntity, MultiPart, SinglePart,
+};
+
+/// Multipart bodies nested deeper than this are treated as single parts.
+const MAX_DEPTH: usize = 32;
+
+impl Entity {
+    /// Parses a complete message (header block followed by a body). All byte
+    /// ranges in the returned entity tree are relative to `bytes`.
+    pub fn parse(bytes: &[u8]) -> Result<Entity, InvalidEntity> {
+        let (body, header) = HeaderMap::parse(bytes).map_err(|_| InvalidEntity::new())?;
+        let body = (bytes.len() - body.len())..bytes.len();
+        Ok(parse_entity(bytes, header, body, 0))
+    }
+
+    pub fn header(&self) -> &HeaderMap {
+        match self {
+            Entity::SinglePart(single) => &single.header,
+            Entity::MultiPart(multi) => &multi.header,
+        }
+    }
+
+    pub fn body(&self) -> Range<usize> {
+        match self {
+            Entity::SinglePart(single) => single.body.clone(),
+            Entity::MultiPart(multi) => multi.body.clone(),
+        }
+    }
+
+    /// Returns the entity's content type, falling back to the RFC 2045 default
+    /// of `text/plain; charset=us-ascii` if it is missing or invalid.
+    pub fn content_type(&self) -> ContentType {
+        self.header()
+            .get_typed::<ContentType>()
+            .ok()
+            .flatten()
+            .unwrap_or_default()
+    }
+
+    pub fn content_disposition(&self) -> Option<ContentDisposition> {
+        self.header()
+            .get_typed::<ContentDisposition>()
+            .ok()
+            .flatten()
+    }
+
+    /// Returns the suggested filename from the Content-Disposition header, or from
+    /// the `name` parameter of the Content-Type header used by older mailers.
+    pub fn filename(&self) -> Option<String> {
+        self.content_disposition()
+            .and_then(|cd| cd.filename().map(str::to_owned))
+            .or_else(|| self.content_type().parameter("name").map(str::to_owned))
+    }
+
+    /// Returns the body parts of a multipart entity.
+    pub fn parts(&self) -> &[Entity] {
+        match self {
+            Entity::SinglePart(_) => &[],
+            Entity::MultiPart(multi) => &multi.parts,
+        }
+    }
+
+    /// Finds a part by its part id.
+    ///
+    /// Part ids are IMAP style section numbers: the body parts of a multipart
+    /// entity are numbered from 1 and nested parts are separated by a "." (e.g.
+    /// `2.1`). The body of a message that is not multipart has the id `1`.
+    pub fn part(&self, id: &str) -> Option<&Entity> {
+        let mut entity = self;
+        for (depth, index) in id.split('.').enumerate() {
+            let index = index.parse::<usize>().ok()?.checked_sub(1)?;
+            entity = match entity {
+                Entity::MultiPart(multi) => multi.parts.get(index)?,
+                Entity::SinglePart(_) if depth == 0 && index == 0 => entity,
+                Entity::SinglePart(_) => return None,
+            };
+        }
+        Some(entity)
+    }
+
+    /// Returns the part id of the `index`th (0-based) child of the entity with
+    /// the id `parent`, where `None` is the root entity. See [`Entity::part`].
+    pub fn child_part_id(parent: Option<&str>, index: usize) -> String {
+        match parent {
+            Some(parent) => format!("{}.{}", parent, index + 1),
+            None => (index + 1).to_string(),
+        }
+    }
+}
+
+fn parse_entity(bytes: &[u8], header: HeaderMap, body: Range<usize>, depth: usize) -> Entity {
+    let boundary = header
+        .get_typed::<ContentType>()
+        .ok()
+        .flatten()
+        .filter(|content_type| content_type.is_multipart())
+        .and_then(|content_type| content_type.boundary().map(str::to_owned));
+
+    if let Some(boundary) = boundary.filter(|_| depth < MAX_DEPTH) {
+        if let Some(ranges) = split_multipart(&bytes[body.clone()], boundary.as_bytes()) {
+            let parts = ranges
+                .into_iter()
+                .map(|part| (part.start + body.start)..(part.end + body.start))
+                .map(|part| parse_part(bytes, part, depth + 1))
+                .collect();
+            return Entity::MultiPart(MultiPart {
+                header,
+                body,
+                parts,
+            });
+        }
+    }
+
+    let encoding = header
+        .get_typed::<Encoding>()
+        .ok()
+        .flatten()
+        .unwrap_or_default();
+    Entity::SinglePart(SinglePart {
+        header,
+        encoding,
+        body,
+    })
+}
+
+fn parse_part(bytes: &[u8], part: Range<usize>, depth: usize) -> Entity {
+    match HeaderMap::parse(&bytes[part.clone()]) {
+        Ok((body, header)) => {
+            let body = (part.end - body.len())..part.end;
+            parse_entity(bytes, header, body, depth)
+        }
+
+        // be lenient with broken body parts and keep everything as the body
+        Err(_) => Entity::SinglePart(SinglePart {
+            header: HeaderMap::default(),
+            encoding: Encoding::default(),
+            body: part,
+        }),
+    }
+}
+
+/// Splits a multipart body into the ranges of its body parts (RFC 2046 §5.1.1).
+/// The line break preceding a delimiter line belongs to the delimiter, so it is
+/// not included in the part. Returns `None` if no delimiter line was found.
+///
+/// A missing close-delimiter is tolerated, in which case the last body part
+/// extends to the end of `body`.
+fn split_multipart(body: &[u8], boundary: &[u8]) -> Option<Vec<Range<usize>>> {
+    let mut parts = Vec::new();
+    let mut part_start: Option<usize> = None;
+    let mut line_start = 0;
+
+    while line_start < body.len() {
+        let line_end = body[line_start..]
+            .iter()
+            .position(|&ch| ch == b'\n')
+            .map(|idx| line_start + idx + 1)
+            .unwrap_or(body.len());
+
+        if let Some(close) = delimiter_line(&body[line_start..line_end], boundary) {
+            if let Some(start) = part_start {
+                parts.push(start..trim_line_break(body, start, line_start));
+            }
+            if close {
+                return Some(parts);
+            }
+            part_start = Some(line_end);
+        }
+
+        line_start = line_end;
+    }
+
+    let start = part_start?;
+    parts.push(start..body.len());
+    Some(parts)
+}
+
+/// Checks if `line` is a delimiter line for `boundary`. Returns `Some(true)` for a
+/// close-delimiter, `Some(false)` for a regular delimiter and `None` otherwise.
+fn delimiter_line(line: &[u8], boundary: &[u8]) -> Option<bool> {
+    let rest = line.strip_prefix(b"--")?.strip_prefix(boundary)?;
+    let (close, rest) = match rest.strip_prefix(b"--") {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+
+    // only transport padding may follow the boundary
+    rest.iter()
+        .all(|&ch| matches!(ch, b' ' | b'\t' | b'\r' | b'\n'))
+        .then_some(close)
+}
+
+fn trim_line_break(body: &[u8], start: usize, mut end: usize) -> usize {
+    if end > start && body[end - 1] == b'\n' {
+        end -= 1;
+        if end > start && body[end - 1] == b'\r' {
+            end -= 1;
+        }
+    }
+    end
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn body_str<'a>(message: &'a [u8], entity: &Entity) -> &'a str {
+        std::str::from_utf8(&message[entity.body()]).unwrap()
+    }
+
+    fn parts(entity: &Entity) -> &[Entity] {
+        match entity {
+            Entity::MultiPart(multi) => &multi.parts,
+            Entity::SinglePart(_) => panic!("expected a multipart entity"),
+        }
+    }
+
+    #[test]
+    fn parse_single_part() {
+        let message = b"Subject: test\r\nContent-Type: text/plain\r\n\r\nhello\r\nworld\r\n";
+        let entity = Entity::parse(message).unwrap();
+        assert!(matches!(entity, Entity::SinglePart(_)));
+        assert_eq!(body_str(message, &entity), "hello\r\nworld\r\n");
+        assert_eq!(entity.content_type().essence(), "text/plain");
+    }
+
+    #[test]
+    fn parse_multipart_alternative() {
+        let message = b"Content-Type: multipart/alternative; boundary=\"b1\"\r\n\
+            \r\n\
+            This is the preamble.\r\n\
+            --b1\r\n\
+            Content-Type: text/plain\r\n\
+            \r\n\
+            plain body\r\n\
+            --b1 \r\n\
+            Content-Type: text/html\r\n\
+            \r\n\
+            <p>html body</p>\r\n\
+            --b1--\r\n\
+            This is the epilogue.\r\n";
+        let entity = Entity::parse(message).unwrap();
+        let parts = parts(&entity);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].content_type().essence(), "text/plain");
+        assert_eq!(body_str(message, &parts[0]), "plain body");
+        assert_eq!(parts[1].content_type().essence(), "text/html");
+        assert_eq!(body_str(message, &parts[1]), "<p>html body</p>");
+    }
+
+    #[test]
+    fn parse_multipart_nested() {
+        let message = b"Content-Type: multipart/mixed; boundary=outer\r\n\
+            \r\n\
+            --outer\r\n\
+            Content-Type: multipart/related; boundary=\"outer-inner\"\r\n\
+            \r\n\
+            --outer-inner\r\n\
+            Content-Type: text/html\r\n\
+            \r\n\
+            <img src=cid:x>\r\n\
+            --outer-inner\r\n\
+            Content-Type: image/png\r\n\
+            Content-ID: <x>\r\n\
+            \r\n\
+            PNG\r\n\
+            --outer-inner--\r\n\
+            \r\n\
+            --outer\r\n\
+            \r\n\
+            no headers\r\n\
+            --outer--\r\n";
+        let entity = Entity::parse(message).unwrap();
+        let outer = parts(&entity);
+        assert_eq!(outer.len(), 2);
+        let inner = parts(&outer[0]);
+        assert_eq!(inner.len(), 2);
+        assert_eq!(body_str(message, &inner[0]), "<img src=cid:x>");
+        assert_eq!(inner[1].content_type().essence(), "image/png");
+        assert_eq!(body_str(message, &inner[1]), "PNG");
+        assert_eq!(outer[1].content_type().essence(), "text/plain");
+        assert_eq!(body_str(message, &outer[1]), "no headers");
+    }
+
+    #[test]
+    fn parse_multipart_missing_close_delimiter() {
+        let message = b"Content-Type: multipart/mixed; boundary=b\r\n\
+            \r\n\
+            --b\r\n\
+            \r\n\
+            first\r\n\
+            --b\r\n\
+            \r\n\
+            truncated";
+        let entity = Entity::parse(message).unwrap();
+        let parts = parts(&entity);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(body_str(message, &parts[1]), "truncated");
+    }
+
+    #[test]
+    fn parse_multipart_without_delimiters() {
+        let message = b"Content-Type: multipart/mixed; boundary=b\r\n\r\nno parts here\r\n";
+        let entity = Entity::parse(message).unwrap();
+        assert!(matches!(entity, Entity::SinglePart(_)));
+        assert_eq!(body_str(message, &entity), "no parts here\r\n");
+    }
+
+    #[test]
+    fn find_parts_by_id() {
+        let message = b"Content-Type: multipart/mixed; boundary=a\r\n\
+            \r\n\
+            --a\r\n\
+            Content-Type: multipart/alternative; boundary=b\r\n\
+            \r\n\
+            --b\r\n\
+            \r\n\
+            text\r\n\
+            --b\r\n\
+            Content-Type: text/html\r\n\
+            \r\n\
+            html\r\n\
+            --b--\r\n\
+            --a\r\n\
+            Content-Type: application/pdf; name=old.pdf\r\n\
+            Content-Disposition: attachment; filename=\"report.pdf\"\r\n\
+            \r\n\
+            PDF\r\n\
+            --a--\r\n";
+        let entity = Entity::parse(message).unwrap();
+        assert_eq!(body_str(message, entity.part("1.1").unwrap()), "text");
+        assert_eq!(body_str(message, entity.part("1.2").unwrap()), "html");
+        assert_eq!(entity.part("2").unwrap().filename().unwrap(), "report.pdf");
+        assert!(entity.part("3").is_none());
+        assert!(entity.part("2.1").is_none());
+        assert!(entity.part("0").is_none());
+        assert!(entity.part("").is_none());
+
+        let single = Entity::parse(b"Subject: x\r\n\r\nbody").unwrap();
+        assert_eq!(
+            body_str(b"Subject: x\r\n\r\nbody", single.part("1").unwrap()),
+            "body"
+        );
+        assert!(single.part("1.1").is_none());
+    }
+
+    #[test]
+    fn parse_invalid_header() {
+        assert!(Entity::parse(b"not a header\r\n").is_err());
+    }
+}
@@ -1,7 +1,8 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+pub mod encoded_word;
 mod name;
-mod parser;
+pub(crate) mod parser;
 pub mod parts;
 pub mod typed;
 
@@ -14,10 +15,12 @@ use std::{
 
 use self::typed::TypedHeader;
 
-#[derive(Default, Serialize, Deserialize)]
+/// An ordered multimap of header fields. Fields keep the order they appeared in
+/// and repeated fields (e.g. `Received`) are all retained.
+#[derive(Default, Clone, Serialize)]
+#[serde(transparent)]
 pub struct HeaderMap {
-    #[serde(flatten)]
-    inner: HashMap<HeaderName<'static>, String>,
+    fields: Vec<HeaderField>,
 }
 
 impl HeaderMap {
@@ -25,41 +28,208 @@ impl HeaderMap {
         parser::headers(bytes)
     }
 
+    /// Replaces all values of a header with `value`.
     pub fn insert<H, V>(&mut self, header: H, value: V)
     where
         H: TryInto<HeaderName<'static>>,
         H::Error: Debug,
         V: Into<String>,
     {
-        self.inner.insert(
-            header.try_into().expect("invalid header name"),
-            value.into(),
-        );
+        let name = header.try_into().expect("invalid header name");
+        self.remove(&name);
+        self.fields.push(HeaderField::new(name, value.into(), None));
+    }
+
+    /// Adds a value for a header, keeping any existing values.
+    pub fn append<H, V>(&mut self, header: H, value: V)
+    where
+        H: TryInto<HeaderName<'static>>,
+        H::Error: Debug,
+        V: Into<String>,
+    {
+        let name = header.try_into().expect("invalid header name");
+        self.fields.push(HeaderField::new(name, value.into(), None));
+    }
+
+    pub(crate) fn append_raw(
+        &mut self,
+        name: HeaderName<'static>,
+        value: String,
+        raw: String,
+        raw_bytes: Option<Vec<u8>>,
+    ) {
+        let mut field = HeaderField::new(name, value, Some(raw));
+        field.raw_bytes = raw_bytes;
+        self.fields.push(field);
+    }
+
+    /// Removes all values of a header.
+    pub fn remove<'s, K>(&mut self, key: K)
+    where
+        K: Into<HeaderName<'s>>,
+    {
+        let key = key.into();
+        self.fields.retain(|field| field.name != key);
     }
 
+    /// Returns the first value of a header.
     pub fn get<'s, K>(&'s self, key: K) -> Option<&'s str>
     where
         K: Into<HeaderName<'s>>,
     {
-        self.inner.get(&key.into()).map(|s| s.as_str())
+        self.get_field(key).map(HeaderField::value)
+    }
+
+    /// Returns the first value of a header as it was received, including folding
+    /// whitespace. See [`HeaderField::raw`].
+    pub fn get_raw<'s, K>(&'s self, key: K) -> Option<&'s str>
+    where
+        K: Into<HeaderName<'s>>,
+    {
+        self.get_field(key).map(HeaderField::raw)
+    }
+
+    /// Returns all values of a header in the order they appeared.
+    pub fn get_all<'s, K>(&'s self, key: K) -> impl 's + Iterator<Item = &'s str>
+    where
+        K: Into<HeaderName<'s>>,
+    {
+        let key = key.into();
+        self.fields
+            .iter()
+            .filter(move |field| field.name == key)
+            .map(HeaderField::value)
     }
 
     pub fn get_typed<T: TypedHeader>(&self) -> Result<Option<T>, T::Error> {
         self.get(T::NAME).map(|value| T::decode(value)).transpose()
     }
 
-    pub fn iter(&self) -> impl '_ + Iterator<Item = (HeaderName, &String)> {
-        self.inner.iter().map(|(k, v)| (HeaderName::from(k), v))
+    /// Decodes every value of a header.
+    pub fn get_all_typed<T: TypedHeader>(&self) -> Result<Vec<T>, T::Error> {
+        self.get_all(T::NAME)
+            .map(|value| T::decode(value))
+            .collect()
+    }
+
+    pub fn contains<'s, K>(&'s self, key: K) -> bool
+    where
+        K: Into<HeaderName<'s>>,
+    {
+        self.get_field(key).is_some()
+    }
+
+    fn get_field<'s, K>(&'s self, key: K) -> Option<&'s HeaderField>
+    where
+        K: Into<HeaderName<'s>>,
+    {
+        let key = key.into();
+        self.fields.iter().find(|field| field.name == key)
+    }
+
+    /// Iterates over all fields in their original order.
+    pub fn fields(&self) -> impl '_ + Iterator<Item = &HeaderField> {
+        self.fields.iter()
+    }
+
+    pub fn iter(&self) -> impl '_ + Iterator<Item = (HeaderName<'_>, &String)> {
+        self.fields
+            .iter()
+            .map(|field| (HeaderName::from(&field.name), &field.value))
     }
 
-    pub fn iter_mut(&mut self) -> impl '_ + Iterator<Item = (HeaderName, &mut String)> {
-        self.inner.iter_mut().map(|(k, v)| (HeaderName::from(k), v))
+    pub fn iter_mut(&mut self) -> impl '_ + Iterator<Item = (HeaderName<'_>, &mut String)> {
+        self.fields
+            .iter_mut()
+            .map(|field| (HeaderName::from(&field.name), &mut field.value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+impl<'de> Deserialize<'de> for HeaderMap {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Fields(Vec<HeaderField>),
+            /// Header maps used to be stored as a single JSON object.
+            Legacy(HashMap<HeaderName<'static>, String>),
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::Fields(fields) => Ok(HeaderMap { fields }),
+            Repr::Legacy(map) => Ok(HeaderMap {
+                fields: map
+                    .into_iter()
+                    .map(|(name, value)| HeaderField::new(name, value, None))
+                    .collect(),
+            }),
+        }
     }
 }
 
 impl Debug for HeaderMap {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        Debug::fmt(&self.inner, f)
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HeaderField {
+    name: HeaderName<'static>,
+    value: String,
+    /// Only kept if it differs from `value`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    raw: Option<String>,
+    /// The raw value as received, only kept if it isn't valid UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    raw_bytes: Option<Vec<u8>>,
+}
+
+impl HeaderField {
+    fn new(name: HeaderName<'static>, value: String, raw: Option<String>) -> Self {
+        let raw = raw.filter(|raw| *raw != value);
+        HeaderField {
+            name,
+            value,
+            raw,
+            raw_bytes: None,
+        }
+    }
+
+    pub fn name(&self) -> &HeaderName<'static> {
+        &self.name
+    }
+
+    /// The unfolded value with surrounding whitespace removed.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// The value as it was received, including any folding. Only the whitespace
+    /// between the colon and the value and the final CRLF are removed.
+    ///
+    /// Values that aren't valid UTF-8 are decoded as Windows-1252, see
+    /// [`HeaderField::raw_bytes`] for the exact bytes.
+    pub fn raw(&self) -> &str {
+        self.raw.as_deref().unwrap_or(&self.value)
+    }
+
+    /// The raw value as bytes, exactly as received even if it isn't valid UTF-8.
+    pub fn raw_bytes(&self) -> &[u8] {
+        self.raw_bytes
+            .as_deref()
+            .unwrap_or_else(|| self.raw().as_bytes())
     }
 }
 
@@ -92,8 +262,8 @@ known_headers! {
     const RESENT_TO = "resent-to",
     const RESENT_CC = "resent-cc",
     const RESENT_BCC = "resent-bcc",
-    const RESENT_MSG_ID = "resent-msg-id",
-    const ORIG_DATE = "orig-date",
+    const RESENT_MSG_ID = "resent-message-id",
+    const ORIG_DATE = "date",
     const FROM = "from",
     const SENDER = "sender",
     const REPLY_TO = "reply-to",
@@ -106,4 +276,108 @@ known_headers! {
     const SUBJECT = "subject",
     const COMMENTS = "comments",
     const KEYWORDS = "keywords",
+    const MIME_VERSION = "mime-version",
+    const CONTENT_TYPE = "content-type",
+    const CONTENT_TRANSFER_ENCODING = "content-transfer-encoding",
+    const CONTENT_ID = "content-id",
+    const CONTENT_DESCRIPTION = "content-description",
+    const CONTENT_DISPOSITION = "content-disposition",
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const RECEIVED: HeaderName = HeaderName::from_static("received");
+
+    const HEADERS: &[u8] = b"Received: from a.example.com\r\n\
+        \tby b.example.com; Tue, 1 Jul 2003 10:52:37 +0200\r\n\
+        To: first@example.com\r\n\
+        Received: from c.example.com by a.example.com\r\n\
+        X-Custom:   padded\r\n\
+        to: second@example.com\r\n\
+        \r\n";
+
+    #[test]
+    fn parse_repeated_headers() {
+        let (rest, map) = HeaderMap::parse(HEADERS).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(map.len(), 5);
+        assert_eq!(map.get(TO), Some("first@example.com"));
+        assert_eq!(
+            map.get_all(TO).collect::<Vec<_>>(),
+            ["first@example.com", "second@example.com"]
+        );
+        assert_eq!(map.get_all(RECEIVED).count(), 2);
+    }
+
+    #[test]
+    fn parse_preserves_order_and_raw_values() {
+        let (_, map) = HeaderMap::parse(HEADERS).unwrap();
+        let names = map.fields().map(|f| f.name().as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["Received", "To", "Received", "X-Custom", "to"]);
+
+        assert_eq!(
+            map.get(RECEIVED),
+            Some("from a.example.com by b.example.com; Tue, 1 Jul 2003 10:52:37 +0200")
+        );
+        assert_eq!(
+            map.get_raw(RECEIVED),
+            Some("from a.example.com\r\n\tby b.example.com; Tue, 1 Jul 2003 10:52:37 +0200")
+        );
+        assert_eq!(
+            map.get_raw(HeaderName::from_static("x-custom")),
+            Some("padded")
+        );
+    }
+
+    #[test]
+    fn insert_replaces_all_values() {
+        let (_, mut map) = HeaderMap::parse(HEADERS).unwrap();
+        map.insert("To", "third@example.com");
+        assert_eq!(map.get_all(TO).collect::<Vec<_>>(), ["third@example.com"]);
+        map.append("To", "fourth@example.com");
+        assert_eq!(map.get_all(TO).count(), 2);
+    }
+
+    #[test]
+    fn deserialize_legacy_map() {
+        let map: HeaderMap = serde_json::from_str(r#"{"subject":"hello"}"#).unwrap();
+        assert_eq!(map.get(SUBJECT), Some("hello"));
+
+        let (_, map) = HeaderMap::parse(HEADERS).unwrap();
+        let json = serde_json::to_string(&map).unwrap();
+        let map: HeaderMap = serde_json::from_str(&json).unwrap();
+        assert_eq!(map.len(), 5);
+        assert_eq!(
+            map.get_raw(RECEIVED).map(|r| r.contains("\r\n\t")),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn parse_utf8_values() {
+        let headers = "Subject: Grüße\r\nFrom: Jöhn <jöhn@bücher.example>\r\n\r\n";
+        let (_, map) = HeaderMap::parse(headers.as_bytes()).unwrap();
+        assert_eq!(map.get(SUBJECT), Some("Grüße"));
+        let from = map.get_typed::<typed::From>().unwrap().unwrap();
+        let json = serde_json::to_string(&from).unwrap();
+        assert!(
+            json.contains(r#""address":"jöhn@bücher.example""#),
+            "{json}"
+        );
+        assert_eq!(map.fields().next().unwrap().raw_bytes(), "Grüße".as_bytes());
+    }
+
+    #[test]
+    fn parse_legacy_8bit_values() {
+        let (_, map) = HeaderMap::parse(b"Subject: Gr\xfc\xdfe\r\n \xa9 2003\r\n\r\n").unwrap();
+        assert_eq!(map.get(SUBJECT), Some("Grüße © 2003"));
+        let raw = b"Gr\xfc\xdfe\r\n \xa9 2003";
+        assert_eq!(map.fields().next().unwrap().raw_bytes(), raw);
+
+        let json = serde_json::to_string(&map).unwrap();
+        let map: HeaderMap = serde_json::from_str(&json).unwrap();
+        assert_eq!(map.fields().next().unwrap().raw_bytes(), raw);
+    }
 }
@@ -0,0 +1,180 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::borrow::Cow;
+
+use crate::{
+    charset,
+    encoding::{hex_value, Base64Decoder},
+};
+
+/// Decodes all RFC 2047 encoded-words in a header value.
+///
+/// Whitespace between two adjacent encoded-words is removed, and adjacent words
+/// in the same charset are decoded together so that multibyte characters split
+/// across words survive. Anything that does not look like a valid encoded-word
+/// is left as it is.
+pub fn decode(input: &str) -> Cow<'_, str> {
+    if !input.contains("=?") {
+        return Cow::Borrowed(input);
+    }
+
+    let mut output = String::with_capacity(input.len());
+    let mut pending: Option<EncodedWord> = None;
+    let mut rest = input;
+
+    while let Some(start) = rest.find("=?") {
+        let (before, candidate) = rest.split_at(start);
+        match encoded_word(candidate) {
+            Some((word, remaining)) => {
+                let adjacent =
+                    pending.is_some() && before.bytes().all(|ch| ch == b' ' || ch == b'\t');
+                if !adjacent {
+                    flush(&mut pending, &mut output);
+                    output.push_str(before);
+                }
+
+                match &mut pending {
+                    Some(p) if p.charset.eq_ignore_ascii_case(&word.charset) => {
+                        p.bytes.extend(word.bytes)
+                    }
+                    _ => {
+                        flush(&mut pending, &mut output);
+                        pending = Some(word);
+                    }
+                }
+                rest = remaining;
+            }
+
+            None => {
+                flush(&mut pending, &mut output);
+                output.push_str(&rest[..start + 2]);
+                rest = &rest[start + 2..];
+            }
+        }
+    }
+
+    flush(&mut pending, &mut output);
+    output.push_str(rest);
+    Cow::Owned(output)
+}
+
+struct EncodedWord {
+    charset: String,
+    bytes: Vec<u8>,
+}
+
+fn flush(pending: &mut Option<EncodedWord>, output: &mut String) {
+    if let Some(word) = pending.take() {
+        output.push_str(&charset::decode(&word.charset, &word.bytes));
+    }
+}
+
+/// encoded-word = "=?" charset ["*" language] "?" encoding "?" encoded-text "?="
+fn encoded_word(i: &str) -> Option<(EncodedWord, &str)> {
+    let i = i.strip_prefix("=?")?;
+    let (charset, i) = i.split_once('?')?;
+    let (encoding, i) = i.split_once('?')?;
+    let (text, rest) = i.split_once("?=")?;
+
+    // RFC 2231 §5: the charset may be followed by a language tag
+    let charset = charset.split('*').next().unwrap_or_default();
+    if charset.is_empty() || text.contains(|ch: char| ch.is_ascii_whitespace() || ch == '?') {
+        return None;
+    }
+
+    let bytes = match encoding {
+        "B" | "b" => {
+            let mut bytes = Vec::with_capacity(text.len() / 4 * 3);
+            let mut decoder = Base64Decoder::default();
+            decoder.decode(text.as_bytes(), &mut bytes);
+            decoder.finish(&mut bytes);
+            bytes
+        }
+        "Q" | "q" => decode_q(text.as_bytes()),
+        _ => return None,
+    };
+
+    let word = EncodedWord {
+        charset: charset.to_owned(),
+        bytes,
+    };
+    Some((word, rest))
+}
+
+/// The "Q" encoding is similar to quoted-printable, but "_" represents a space.
+fn decode_q(text: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut idx = 0;
+    while idx < text.len() {
+        let escaped = (text[idx] == b'=')
+            .then(|| text.get(idx + 1..idx + 3))
+            .flatten()
+            .and_then(|hex| Some(hex_value(hex[0])? << 4 | hex_value(hex[1])?));
+
+        match (text[idx], escaped) {
+            (_, Some(byte)) => {
+                bytes.push(byte);
+                idx += 3;
+                continue;
+            }
+            (b'_', _) => bytes.push(b' '),
+            (ch, _) => bytes.push(ch),
+        }
+        idx += 1;
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_plain() {
+        assert!(matches!(decode("no encoded words"), Cow::Borrowed(_)));
+        assert_eq!(decode("a =? b ?= c"), "a =? b ?= c");
+    }
+
+    #[test]
+    fn decode_b() {
+        assert_eq!(decode("=?UTF-8?B?SGVsbG8sIOS4lueVjA==?="), "Hello, 世界");
+    }
+
+    #[test]
+    fn decode_q() {
+        assert_eq!(
+            decode("=?ISO-8859-1?Q?Andr=E9_Pirard?= <andre@example.com>"),
+            "André Pirard <andre@example.com>"
+        );
+        assert_eq!(decode("=?windows-1252?q?=80_100?="), "€ 100");
+        // invalid escapes are kept as they are
+        assert_eq!(decode("=?UTF-8?Q?=+A=-1=4?="), "=+A=-1=4");
+    }
+
+    #[test]
+    fn decode_adjacent_words() {
+        assert_eq!(decode("=?UTF-8?Q?a?= =?UTF-8?Q?b?="), "ab");
+        assert_eq!(decode("=?UTF-8?Q?a?=  =?ISO-8859-1?Q?b?="), "ab");
+        assert_eq!(decode("=?UTF-8?Q?a?= b =?UTF-8?Q?c?="), "a b c");
+        assert_eq!(decode("x =?UTF-8?Q?a_?= y"), "x a  y");
+    }
+
+    #[test]
+    fn decode_split_multibyte_character() {
+        // "é" is split across two encoded-words
+        assert_eq!(decode("=?UTF-8?B?Y2Fmw6k=?="), "café");
+        assert_eq!(decode("=?UTF-8?Q?caf=C3?= =?UTF-8?Q?=A9?="), "café");
+    }
+
+    #[test]
+    fn decode_language() {
+        assert_eq!(decode("=?US-ASCII*EN?Q?Keith_Moore?="), "Keith Moore");
+    }
+
+    #[test]
+    fn decode_invalid_words() {
+        assert_eq!(decode("=?UTF-8?X?abc?="), "=?UTF-8?X?abc?=");
+        assert_eq!(decode("=?UTF-8?Q?a b?="), "=?UTF-8?Q?a b?=");
+        assert_eq!(decode("=??Q?abc?="), "=??Q?abc?=");
+    }
+}
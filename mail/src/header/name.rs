@@ -93,6 +93,9 @@ impl<'a> AsRef<[u8]> for HeaderName<'a> {
     }
 }
 
+// the hash is case-insensitive, so it matches neither the `str` nor the `[u8]`
+// one, maps keyed by `HeaderName` have to be queried with a `HeaderName`
+#[allow(clippy::impl_hash_borrow_with_str_and_bytes)]
 impl<'a> std::hash::Hash for HeaderName<'a> {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         for &ch in self.0.as_ref() {
@@ -151,7 +154,7 @@ impl<'a> TryFrom<&'a str> for HeaderName<'a> {
     fn try_from(value: &'a str) -> Result<Self, Self::Error> {
         value
             .is_ascii()
-            .then(move || HeaderName(Cow::Borrowed(value.as_bytes())))
+            .then_some(HeaderName(Cow::Borrowed(value.as_bytes())))
             .ok_or(InvalidHeaderName { _inner: () })
     }
 }
@@ -162,7 +165,7 @@ impl<'a> TryFrom<&'a [u8]> for HeaderName<'a> {
     fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
         value
             .is_ascii()
-            .then(move || HeaderName(Cow::Borrowed(value)))
+            .then_some(HeaderName(Cow::Borrowed(value)))
             .ok_or(InvalidHeaderName { _inner: () })
     }
 }
@@ -3,6 +3,9 @@
 #![allow(dead_code)]
 
 pub(crate) mod address;
+pub(crate) mod datetime;
+pub(crate) mod mime;
+pub(crate) mod msg_id;
 mod optional;
 
 use std::{
@@ -24,22 +27,27 @@ use nom::{
 
 use self::optional::optional_field;
 
-use super::{HeaderMap, InvalidHeaderMap};
+use super::{HeaderMap, HeaderName, InvalidHeaderMap};
 
 pub fn headers(i: &[u8]) -> Result<(&[u8], HeaderMap), InvalidHeaderMap> {
     terminated(
         fold_many0(
             optional_field,
             HeaderMap::default,
-            |mut map, (name, value)| {
-                let name = std::str::from_utf8(name)
-                    .expect("field name not valid UTF8")
-                    .to_owned();
-                let value = String::from_utf8(value)
-                    .expect("value not valid UTF8")
+            |mut map, (name, value, raw)| {
+                // field names only consist of printable US-ASCII
+                let name = String::from_utf8_lossy(name).into_owned();
+                let value = decode_8bit(&value)
                     .trim_matches(|ch: char| ch.is_ascii() && ch.is_whitespace())
                     .replace("\r\n", "");
-                map.insert(name, value);
+                let raw = trim_start_wsp(raw);
+                let raw_bytes = std::str::from_utf8(raw).is_err().then(|| raw.to_vec());
+                map.append_raw(
+                    HeaderName::try_from(name).expect("invalid header name"),
+                    value,
+                    decode_8bit(raw).into_owned(),
+                    raw_bytes,
+                );
                 map
             },
         ),
@@ -48,6 +56,23 @@ pub fn headers(i: &[u8]) -> Result<(&[u8], HeaderMap), InvalidHeaderMap> {
     .map_err(|_| InvalidHeaderMap::default())
 }
 
+/// Decodes a header value. Values are UTF-8 (RFC 6532), but legacy clients also
+/// send other 8-bit charsets, those values are decoded as Windows-1252.
+fn decode_8bit(bytes: &[u8]) -> Cow<'_, str> {
+    match std::str::from_utf8(bytes) {
+        Ok(value) => Cow::Borrowed(value),
+        Err(_) => crate::charset::decode("windows-1252", bytes),
+    }
+}
+
+fn trim_start_wsp(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|&ch| ch != b' ' && ch != b'\t')
+        .unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
 /// FWS = ([*WSP CRLF] 1*WSP) /  obs-FWS
 fn fws(i: &[u8]) -> IResult<&[u8], &[u8]> {
     let current = recognize(pair(
@@ -91,7 +116,7 @@ fn comment(i: &[u8]) -> IResult<&[u8], &[u8]> {
 }
 
 /// CFWS = (1*([FWS] comment) [FWS]) / FWS
-fn cfws(i: &[u8]) -> IResult<&[u8], &[u8]> {
+pub(crate) fn cfws(i: &[u8]) -> IResult<&[u8], &[u8]> {
     alt((
         terminated(
             recognize(many1_count(preceded(opt(fws), comment))),
@@ -191,7 +216,7 @@ fn quoted_string(i: &[u8]) -> IResult<&[u8], Vec<u8>> {
                         s.borrow_mut().push(b' ');
                     }
                 }),
-                map(qcontent, |q| s.borrow_mut().push(q as u8)),
+                map(qcontent, |q| s.borrow_mut().push(q)),
             )),
             char('"'),
         ),
@@ -206,18 +231,11 @@ fn word(i: &[u8]) -> IResult<&[u8], Vec<u8>> {
 }
 
 /// phrase = 1*word / obs-phrase
+///
+/// Words are joined with a single space.
 fn phrase(i: &[u8]) -> IResult<&[u8], Vec<u8>> {
-    alt((
-        fold_many1(word, Vec::new, |mut s, w| {
-            if s.is_empty() {
-                w
-            } else {
-                s.extend(w);
-                s
-            }
-        }),
-        map(obs_phrase, |o| o),
-    ))(i)
+    // obs-phrase is a superset of 1*word
+    obs_phrase(i)
 }
 
 /// obs-phrase = word *(word / "." / CFWS)
@@ -225,11 +243,16 @@ fn obs_phrase(i: &[u8]) -> IResult<&[u8], Vec<u8>> {
     let (i, mut s) = word(i)?;
     let (i, _) = many0_count(map(
         alt((
-            map(word, Cow::Owned),
-            map(tag("."), |_| Cow::Borrowed(&b"."[..])),
-            map(cfws, |_| Cow::Borrowed(&b""[..])),
+            map(word, |w| (Cow::Owned(w), true)),
+            map(tag("."), |_| (Cow::Borrowed(&b"."[..]), false)),
+            map(cfws, |_| (Cow::Borrowed(&b""[..]), false)),
         )),
-        |w| s.extend(&*w),
+        |(w, is_word)| {
+            if is_word {
+                s.push(b' ');
+            }
+            s.extend(&*w)
+        },
     ))(i)?;
     Ok((i, s))
 }
@@ -323,18 +346,21 @@ fn vchar(i: &[u8]) -> IResult<&[u8], u8> {
 }
 
 /// VCHAR = %x21-7E ; visible (printing) characters
+///
+/// VCHAR =/ UTF8-non-ascii (RFC 6532 §3.2), any 8-bit byte is accepted so
+/// headers in legacy charsets can be parsed as well.
 fn is_vchar(ch: u8) -> bool {
-    matches!(ch, 0x21..=0x7E)
+    matches!(ch, 0x21..=0x7E) || !ch.is_ascii()
 }
 
-/// see [`qtext`]
+/// see [`qtext`], includes 8-bit bytes like [`is_vchar`]
 fn is_qtext(ch: u8) -> bool {
-    matches!(ch, 33 | 35..=91 | 93..=126) || is_obs_qtext(ch)
+    matches!(ch, 33 | 35..=91 | 93..=126) || !ch.is_ascii() || is_obs_qtext(ch)
 }
 
-/// see [`ctext`]
+/// see [`ctext`], includes 8-bit bytes like [`is_vchar`]
 fn is_ctext(ch: u8) -> bool {
-    matches!(ch, 33..=39 | 42..=91 | 93..=126) || is_obs_ctext(ch)
+    matches!(ch, 33..=39 | 42..=91 | 93..=126) || !ch.is_ascii() || is_obs_ctext(ch)
 }
 
 /// obs-ctext = obs-NO-WS-CTL
@@ -357,10 +383,10 @@ fn is_obs_no_ws_ctl(ch: u8) -> bool {
     matches!(ch, 1..=8 | 11 | 12 | 14..=31 | 127)
 }
 
-/// see: [`atext`]
+/// see: [`atext`], includes 8-bit bytes like [`is_vchar`]
 fn is_atext(ch: u8) -> bool {
     const ATEXT_SYMBOLS: &[u8] = b"!#$%&'*+-/=?^_`{|}~";
-    ch.is_ascii_alphanumeric() || ATEXT_SYMBOLS.contains(&ch)
+    ch.is_ascii_alphanumeric() || ATEXT_SYMBOLS.contains(&ch) || !ch.is_ascii()
 }
 
 /// see: [`specials`]
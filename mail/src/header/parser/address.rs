@@ -148,7 +148,7 @@ fn obs_local_part(i: &[u8]) -> IResult<&[u8], Vec<u8>> {
         preceded(byte(b'.'), word),
         move || std::mem::take(&mut out),
         |mut acc, s| {
-            acc.extend(s.into_iter());
+            acc.extend(s);
             acc
         },
     )(i)
@@ -173,7 +173,7 @@ fn domain_literal(i: &[u8]) -> IResult<&[u8], &[u8]> {
 ///dtext = %d33-90 /          ; Printable US-ASCII
 ///        %d94-126 /         ;  characters not including
 ///        obs-dtext          ;  "[", "]", or "\"
-fn dtext(i: &[u8]) -> IResult<&[u8], u8> {
+pub(crate) fn dtext(i: &[u8]) -> IResult<&[u8], u8> {
     let current = satisfy_u8(is_dtext);
     alt((current, obs_dtext))(i)
 }
@@ -187,3 +187,47 @@ fn obs_dtext(i: &[u8]) -> IResult<&[u8], u8> {
 fn is_dtext(ch: u8) -> bool {
     matches!(ch, 33..=90 | 94..=126)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn display_name(i: &str) -> String {
+        let (_, mbox) = mailbox(i.as_bytes()).unwrap();
+        mbox.display_name().to_owned()
+    }
+
+    #[test]
+    fn parse_display_name_words() {
+        assert_eq!(display_name("John Smith <j@example.com>"), "John Smith");
+        assert_eq!(display_name("\"John Smith\" <j@example.com>"), "John Smith");
+        assert_eq!(
+            display_name("John (middle) Q. Public <j@example.com>"),
+            "John Q. Public"
+        );
+    }
+
+    #[test]
+    fn parse_encoded_display_name() {
+        assert_eq!(
+            display_name("=?ISO-8859-1?Q?Keld_J=F8rn_Simonsen?= <keld@example.com>"),
+            "Keld Jørn Simonsen"
+        );
+        assert_eq!(
+            display_name(
+                "=?UTF-8?B?0JjQstCw0L0=?= =?UTF-8?B?INCf0LXRgtGA0L7Qsg==?= <i@example.com>"
+            ),
+            "Иван Петров"
+        );
+    }
+
+    #[test]
+    fn parse_encoded_group_name() {
+        let (_, list) = address_list(b"=?UTF-8?Q?Caf=C3=A9?=: a@example.com;").unwrap();
+        let Address::Group(group) = &list[0] else {
+            panic!("expected a group");
+        };
+        assert_eq!(group.display_name(), "Café");
+        assert_eq!(group.mailboxes()[0].address(), "a@example.com");
+    }
+}
@@ -0,0 +1,214 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use nom::{
+    branch::alt,
+    bytes::complete::{tag_no_case, take_while1, take_while_m_n},
+    character::complete::{char, one_of},
+    combinator::{map, map_opt, map_res, opt, value},
+    sequence::{delimited, pair, preceded, terminated, tuple},
+    IResult,
+};
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+
+use super::{cfws, satisfy_u8};
+
+/// date-time = [ day-of-week "," ] date time [CFWS]
+///
+/// The obsolete syntax (comments between tokens, two digit years, named zones)
+/// is accepted as well. Leap seconds are clamped to the previous second.
+pub(crate) fn date_time(i: &[u8]) -> IResult<&[u8], OffsetDateTime> {
+    map_opt(
+        tuple((
+            opt(day_of_week),
+            date,
+            time_of_day,
+            terminated(zone, opt(cfws)),
+        )),
+        |(_, date, (hour, minute, second), offset)| {
+            let time = Time::from_hms(hour, minute, second.min(59)).ok()?;
+            Some(PrimitiveDateTime::new(date, time).assume_offset(offset))
+        },
+    )(i)
+}
+
+/// day-of-week = ([FWS] day-name) / obs-day-of-week
+///
+/// A missing "," after the day name is tolerated.
+fn day_of_week(i: &[u8]) -> IResult<&[u8], ()> {
+    let day_name = alt((
+        tag_no_case("Mon"),
+        tag_no_case("Tue"),
+        tag_no_case("Wed"),
+        tag_no_case("Thu"),
+        tag_no_case("Fri"),
+        tag_no_case("Sat"),
+        tag_no_case("Sun"),
+    ));
+    value(
+        (),
+        pair(delimited(opt(cfws), day_name, opt(cfws)), opt(char(','))),
+    )(i)
+}
+
+/// date = day month year
+fn date(i: &[u8]) -> IResult<&[u8], Date> {
+    map_opt(
+        tuple((
+            delimited(opt(cfws), number(1, 2), opt(cfws)),
+            delimited(opt(cfws), month, opt(cfws)),
+            delimited(opt(cfws), year, opt(cfws)),
+        )),
+        |(day, month, year)| Date::from_calendar_date(year, month, day as u8).ok(),
+    )(i)
+}
+
+fn month(i: &[u8]) -> IResult<&[u8], Month> {
+    alt((
+        value(Month::January, tag_no_case("Jan")),
+        value(Month::February, tag_no_case("Feb")),
+        value(Month::March, tag_no_case("Mar")),
+        value(Month::April, tag_no_case("Apr")),
+        value(Month::May, tag_no_case("May")),
+        value(Month::June, tag_no_case("Jun")),
+        value(Month::July, tag_no_case("Jul")),
+        value(Month::August, tag_no_case("Aug")),
+        value(Month::September, tag_no_case("Sep")),
+        value(Month::October, tag_no_case("Oct")),
+        value(Month::November, tag_no_case("Nov")),
+        value(Month::December, tag_no_case("Dec")),
+    ))(i)
+}
+
+/// year = (FWS 4*DIGIT FWS) / obs-year
+///
+/// obs-year = [CFWS] 2*DIGIT [CFWS]
+fn year(i: &[u8]) -> IResult<&[u8], i32> {
+    map_res(take_while1(is_digit), |digits: &[u8]| {
+        let year = std::str::from_utf8(digits)
+            .unwrap_or_default()
+            .parse::<i32>()?;
+        // RFC 5322 §4.3
+        Ok::<_, std::num::ParseIntError>(match digits.len() {
+            2 if year < 50 => year + 2000,
+            2 | 3 => year + 1900,
+            _ => year,
+        })
+    })(i)
+}
+
+/// time-of-day = hour ":" minute [ ":" second ]
+fn time_of_day(i: &[u8]) -> IResult<&[u8], (u8, u8, u8)> {
+    let separator = || delimited(opt(cfws), char(':'), opt(cfws));
+    map(
+        tuple((
+            preceded(opt(cfws), number(1, 2)),
+            preceded(separator(), number(2, 2)),
+            opt(preceded(separator(), number(2, 2))),
+        )),
+        |(hour, minute, second)| (hour as u8, minute as u8, second.unwrap_or(0) as u8),
+    )(i)
+}
+
+/// zone = (FWS ( "+" / "-" ) 4DIGIT) / obs-zone
+///
+/// A missing zone is treated as UTC.
+fn zone(i: &[u8]) -> IResult<&[u8], UtcOffset> {
+    let numeric = map_opt(
+        pair(one_of("+-"), pair(number(2, 2), number(2, 2))),
+        |(sign, (hours, minutes))| {
+            let sign = if sign == '-' { -1 } else { 1 };
+            UtcOffset::from_hms(sign * hours as i8, sign * minutes as i8, 0).ok()
+        },
+    );
+    let (i, offset) = opt(preceded(opt(cfws), alt((numeric, obs_zone))))(i)?;
+    Ok((i, offset.unwrap_or(UtcOffset::UTC)))
+}
+
+/// obs-zone = "UT" / "GMT" / "EST" / "EDT" / "CST" / "CDT" / "MST" / "MDT" / "PST" / "PDT"
+///          / %d65-73 / %d75-90 / %d97-105 / %d107-122
+///
+/// Military zones were defined incorrectly in RFC 822 and are treated as `-0000`.
+fn obs_zone(i: &[u8]) -> IResult<&[u8], UtcOffset> {
+    let hours = |h: i8| UtcOffset::from_hms(h, 0, 0).expect("invalid offset");
+    alt((
+        value(UtcOffset::UTC, alt((tag_no_case("UTC"), tag_no_case("UT")))),
+        value(UtcOffset::UTC, tag_no_case("GMT")),
+        value(hours(-5), tag_no_case("EST")),
+        value(hours(-4), tag_no_case("EDT")),
+        value(hours(-6), tag_no_case("CST")),
+        value(hours(-5), tag_no_case("CDT")),
+        value(hours(-7), tag_no_case("MST")),
+        value(hours(-6), tag_no_case("MDT")),
+        value(hours(-8), tag_no_case("PST")),
+        value(hours(-7), tag_no_case("PDT")),
+        value(
+            UtcOffset::UTC,
+            satisfy_u8(|ch: u8| ch.is_ascii_alphabetic() && ch != b'j' && ch != b'J'),
+        ),
+    ))(i)
+}
+
+fn number(min: usize, max: usize) -> impl Fn(&[u8]) -> IResult<&[u8], u16> {
+    move |i| {
+        map(take_while_m_n(min, max, is_digit), |digits: &[u8]| {
+            digits
+                .iter()
+                .fold(0, |acc, &d| acc * 10 + u16::from(d - b'0'))
+        })(i)
+    }
+}
+
+fn is_digit(ch: u8) -> bool {
+    ch.is_ascii_digit()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(i: &str) -> String {
+        let (rest, date) = date_time(i.as_bytes()).unwrap();
+        assert!(rest.is_empty(), "unparsed input: {:?}", rest);
+        date.format(&time::format_description::well_known::Rfc3339)
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_date_time() {
+        assert_eq!(
+            parse("Fri, 21 Nov 1997 09:55:06 -0600"),
+            "1997-11-21T09:55:06-06:00"
+        );
+        assert_eq!(
+            parse("Tue, 1 Jul 2003 10:52:37 +0200"),
+            "2003-07-01T10:52:37+02:00"
+        );
+        assert_eq!(parse("14 Feb 2023 08:00 +0000"), "2023-02-14T08:00:00Z");
+    }
+
+    #[test]
+    fn parse_obsolete_date_time() {
+        assert_eq!(
+            parse("Thu,\t13\t  Feb 69 23:32 (comment) -0330 (Newfoundland Time)"),
+            "1969-02-13T23:32:00-03:30"
+        );
+        assert_eq!(parse("21 Nov 97 09:55:06 GMT"), "1997-11-21T09:55:06Z");
+        assert_eq!(
+            parse("Mon, 1 Jan 07 12 : 00 : 00 EST"),
+            "2007-01-01T12:00:00-05:00"
+        );
+        assert_eq!(parse("1 Jan 2007 12:00:00 Z"), "2007-01-01T12:00:00Z");
+    }
+
+    #[test]
+    fn parse_leap_second() {
+        assert_eq!(parse("31 Dec 2016 23:59:60 +0000"), "2016-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn parse_invalid_date_time() {
+        assert!(date_time(b"31 Feb 2023 10:00 +0000").is_err());
+        assert!(date_time(b"1 Foo 2023 10:00 +0000").is_err());
+        assert!(date_time(b"yesterday").is_err());
+    }
+}
@@ -0,0 +1,67 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use nom::{
+    branch::alt,
+    bytes::complete::take_while1,
+    character::complete::char,
+    combinator::{map, opt},
+    multi::many0,
+    sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
+    IResult,
+};
+
+use super::{cfws, quoted_string};
+
+/// content := type "/" subtype *(";" parameter)
+#[allow(clippy::type_complexity)]
+pub(crate) fn content_type(i: &[u8]) -> IResult<&[u8], (&[u8], &[u8], Vec<(&[u8], Vec<u8>)>)> {
+    tuple((
+        token,
+        preceded(char('/'), token),
+        terminated(
+            many0(preceded(char(';'), parameter)),
+            // a trailing ";" is not allowed but is produced by quite a few mailers
+            opt(pair(char(';'), opt(cfws))),
+        ),
+    ))(i)
+}
+
+/// disposition := disposition-type *(";" disposition-parm)
+#[allow(clippy::type_complexity)]
+pub(crate) fn content_disposition(i: &[u8]) -> IResult<&[u8], (&[u8], Vec<(&[u8], Vec<u8>)>)> {
+    pair(
+        token,
+        terminated(
+            many0(preceded(char(';'), parameter)),
+            opt(pair(char(';'), opt(cfws))),
+        ),
+    )(i)
+}
+
+/// parameter := attribute "=" value
+pub(crate) fn parameter(i: &[u8]) -> IResult<&[u8], (&[u8], Vec<u8>)> {
+    separated_pair(token, char('='), value)(i)
+}
+
+/// value := token / quoted-string
+fn value(i: &[u8]) -> IResult<&[u8], Vec<u8>> {
+    alt((map(token, |t| t.to_vec()), quoted_string))(i)
+}
+
+/// token := 1*<any (US-ASCII) CHAR except SPACE, CTLs, or tspecials>
+pub(crate) fn token(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    delimited(opt(cfws), take_while1(is_token), opt(cfws))(i)
+}
+
+/// see: [`token`]
+fn is_token(ch: u8) -> bool {
+    matches!(ch, 0x21..=0x7E) && !is_tspecials(ch)
+}
+
+/// tspecials :=  "(" / ")" / "<" / ">" / "@" /
+///               "," / ";" / ":" / "\" / <">
+///               "/" / "[" / "]" / "?" / "="
+fn is_tspecials(ch: u8) -> bool {
+    const TSPECIALS: &[u8] = b"()<>@,;:\\\"/[]?=";
+    TSPECIALS.contains(&ch)
+}
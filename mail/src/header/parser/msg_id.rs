@@ -0,0 +1,104 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use nom::{
+    branch::alt,
+    bytes::complete::take_while1,
+    character::complete::char,
+    combinator::{map, opt, recognize},
+    multi::{many0_count, many1},
+    sequence::{delimited, separated_pair},
+    IResult,
+};
+
+use super::{address::dtext, cfws, dot_atom_text, phrase};
+
+/// msg-id = [CFWS] "<" id-left "@" id-right ">" [CFWS]
+///
+/// Ids that do not follow the grammar are accepted as long as they are delimited by
+/// angle brackets and contain no whitespace. The brackets are not part of the output.
+pub(crate) fn msg_id(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    let strict = recognize(separated_pair(id_left, char('@'), id_right));
+    let lenient = take_while1(|ch: u8| ch.is_ascii_graphic() && ch != b'<' && ch != b'>');
+    delimited(
+        opt(cfws),
+        delimited(char('<'), alt((strict, lenient)), char('>')),
+        opt(cfws),
+    )(i)
+}
+
+/// Parses the value of In-Reply-To and References (`1*msg-id`).
+///
+/// The obsolete syntax also allows phrases between ids, which are skipped. Some
+/// mailers separate ids with commas, which is tolerated as well.
+pub(crate) fn msg_id_list(i: &[u8]) -> IResult<&[u8], Vec<&[u8]>> {
+    let (i, ids) = many1(alt((
+        map(msg_id, Some),
+        map(phrase, |_| None),
+        map(char(','), |_| None),
+        map(cfws, |_| None),
+    )))(i)?;
+    let ids: Vec<&[u8]> = ids.into_iter().flatten().collect();
+    if ids.is_empty() {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            i,
+            nom::error::ErrorKind::Many1,
+        )));
+    }
+    Ok((i, ids))
+}
+
+/// id-left = dot-atom-text / obs-id-left
+fn id_left(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    dot_atom_text(i)
+}
+
+/// id-right = dot-atom-text / no-fold-literal / obs-id-right
+fn id_right(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    alt((dot_atom_text, no_fold_literal))(i)
+}
+
+/// no-fold-literal = "[" *dtext "]"
+fn no_fold_literal(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    recognize(delimited(char('['), many0_count(dtext), char(']')))(i)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_msg_id() {
+        assert_eq!(
+            msg_id(b" <1234@local.machine.example> ").unwrap().1,
+            b"1234@local.machine.example"
+        );
+        assert_eq!(msg_id(b"<abc@[127.0.0.1]>").unwrap().1, b"abc@[127.0.0.1]");
+        assert_eq!(msg_id(b"<no-at-sign>").unwrap().1, b"no-at-sign");
+        assert!(msg_id(b"<has space@example.com>").is_err());
+        assert!(msg_id(b"1234@example.com").is_err());
+    }
+
+    #[test]
+    fn parse_msg_id_list() {
+        let (rest, ids) = msg_id_list(
+            b"<1234@local.machine.example> (comment) <3456@example.net>, <78@example.org>",
+        )
+        .unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(
+            ids,
+            [
+                &b"1234@local.machine.example"[..],
+                b"3456@example.net",
+                b"78@example.org"
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_obs_msg_id_list() {
+        let (_, ids) = msg_id_list(b"Your message of \"Fri, 1 Jul\" <1@example.com>").unwrap();
+        assert_eq!(ids, [&b"1@example.com"[..]]);
+        assert!(msg_id_list(b"just a phrase").is_err());
+    }
+}
@@ -2,7 +2,7 @@
 
 use nom::{
     character::complete::{char, crlf, satisfy},
-    combinator::recognize,
+    combinator::{consumed, map, recognize},
     multi::{many0_count, many1_count},
     sequence::{pair, separated_pair, terminated},
     IResult,
@@ -11,10 +11,20 @@ use nom::{
 use super::{unstructured, wsp};
 
 /// optional-field = field-name ":" unstructured CRLF
-pub fn optional_field(i: &[u8]) -> IResult<&[u8], (&[u8], Vec<u8>)> {
-    terminated(
-        separated_pair(field_name, pair(many0_count(wsp), char(':')), unstructured),
-        crlf,
+///
+/// Returns the field name, the unstructured value and the raw value as received.
+#[allow(clippy::type_complexity)]
+pub fn optional_field(i: &[u8]) -> IResult<&[u8], (&[u8], Vec<u8>, &[u8])> {
+    map(
+        terminated(
+            separated_pair(
+                field_name,
+                pair(many0_count(wsp), char(':')),
+                consumed(unstructured),
+            ),
+            crlf,
+        ),
+        |(name, (raw, value))| (name, value, raw),
     )(i)
 }
 
@@ -2,6 +2,8 @@
 
 use serde::{Deserialize, Serialize};
 
+use crate::header::encoded_word;
+
 #[derive(Deserialize, Serialize)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum Address {
@@ -17,12 +19,11 @@ pub struct Mailbox {
 
 impl Mailbox {
     pub(crate) fn new_raw(display_name: Option<Vec<u8>>, address: &[u8]) -> Self {
-        let display_name = String::from_utf8(display_name.unwrap_or_default())
-            .expect("display_name not valid UTF8")
+        let display_name = String::from_utf8_lossy(&display_name.unwrap_or_default())
             .trim_matches(|ch: char| ch.is_ascii() && ch.is_whitespace())
             .replace("\r\n", "");
-        let address = std::str::from_utf8(address)
-            .expect("address not valid UTF8")
+        let display_name = encoded_word::decode(&display_name).into_owned();
+        let address = String::from_utf8_lossy(address)
             .trim_matches(|ch: char| ch.is_ascii() && ch.is_whitespace())
             .replace("\r\n", "");
 
@@ -31,6 +32,14 @@ impl Mailbox {
             address,
         }
     }
+
+    pub fn display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
 }
 
 #[derive(Deserialize, Serialize)]
@@ -41,14 +50,22 @@ pub struct Group {
 
 impl Group {
     pub(crate) fn new_raw(display_name: Option<Vec<u8>>, mailboxes: Vec<Mailbox>) -> Self {
-        let display_name = String::from_utf8(display_name.unwrap_or_default())
-            .expect("display_name not valid UTF8")
+        let display_name = String::from_utf8_lossy(&display_name.unwrap_or_default())
             .trim_matches(|ch: char| ch.is_ascii() && ch.is_whitespace())
             .replace("\r\n", "");
+        let display_name = encoded_word::decode(&display_name).into_owned();
 
         Group {
             display_name,
             mailboxes,
         }
     }
+
+    pub fn display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    pub fn mailboxes(&self) -> &[Mailbox] {
+        &self.mailboxes
+    }
 }
@@ -1,11 +1,30 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+mod bcc;
+mod cc;
+mod content_disposition;
+mod content_type;
+mod date;
 mod from;
+mod in_reply_to;
+mod message_id;
+mod parameters;
+mod references;
+mod reply_to;
 mod sender;
 mod subject;
 mod to;
 
+pub use bcc::*;
+pub use cc::*;
+pub use content_disposition::*;
+pub use content_type::*;
+pub use date::*;
 pub use from::*;
+pub use in_reply_to::*;
+pub use message_id::*;
+pub use references::*;
+pub use reply_to::*;
 pub use sender::*;
 pub use subject::*;
 pub use to::*;
@@ -0,0 +1,72 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt::Display;
+
+use nom::{
+    branch::alt,
+    character::complete::space0,
+    combinator::{eof, map, opt},
+    sequence::{pair, terminated},
+};
+use serde::Serialize;
+
+use crate::header::{
+    parser::{address::address_list, cfws},
+    parts::Address,
+    BCC,
+};
+
+use super::TypedHeader;
+
+#[derive(Serialize)]
+pub struct Bcc(Vec<Address>);
+
+impl TypedHeader for Bcc {
+    type Error = InvalidBcc;
+    const NAME: crate::header::HeaderName<'static> = BCC;
+
+    /// bcc = "Bcc:" [address-list / CFWS] CRLF
+    fn decode(encoded: &str) -> Result<Self, Self::Error> {
+        let list = alt((address_list, map(cfws, |_| Vec::new())));
+        terminated(opt(list), pair(space0, eof))(encoded.as_bytes())
+            .map(|(_, list)| Bcc(list.unwrap_or_default()))
+            .map_err(|_| InvalidBcc::new())
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidBcc {
+    _inner: (),
+}
+
+impl InvalidBcc {
+    fn new() -> Self {
+        Self { _inner: () }
+    }
+}
+
+impl Display for InvalidBcc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid bcc header")
+    }
+}
+
+impl std::error::Error for InvalidBcc {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_empty() {
+        assert!(Bcc::decode("").unwrap().0.is_empty());
+        assert!(Bcc::decode(" (hidden) ").unwrap().0.is_empty());
+    }
+
+    #[test]
+    fn decode_list() {
+        let Bcc(list) = Bcc::decode("a@example.com, undisclosed-recipients:;").unwrap();
+        assert_eq!(list.len(), 2);
+        assert!(matches!(&list[1], Address::Group(g) if g.mailboxes().is_empty()));
+    }
+}
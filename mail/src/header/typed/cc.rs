@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt::Display;
+
+use nom::{
+    character::complete::space0,
+    combinator::eof,
+    sequence::{pair, terminated},
+};
+use serde::Serialize;
+
+use crate::header::{parser::address::address_list, parts::Address, CC};
+
+use super::TypedHeader;
+
+#[derive(Serialize)]
+pub struct Cc(Vec<Address>);
+
+impl TypedHeader for Cc {
+    type Error = InvalidCc;
+    const NAME: crate::header::HeaderName<'static> = CC;
+
+    fn decode(encoded: &str) -> Result<Self, Self::Error> {
+        terminated(address_list, pair(space0, eof))(encoded.as_bytes())
+            .map(|(_, list)| Cc(list))
+            .map_err(|_| InvalidCc::new())
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidCc {
+    _inner: (),
+}
+
+impl InvalidCc {
+    fn new() -> Self {
+        Self { _inner: () }
+    }
+}
+
+impl Display for InvalidCc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid cc header")
+    }
+}
+
+impl std::error::Error for InvalidCc {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_folded_list() {
+        let Cc(list) =
+            Cc::decode("Alice <alice@example.com>,\r\n \"Bob B.\" <bob@example.com>").unwrap();
+        assert_eq!(list.len(), 2);
+        assert!(matches!(&list[1], Address::Mailbox(m)
+            if m.display_name() == "Bob B." && m.address() == "bob@example.com"));
+        assert!(Cc::decode("alice@example.com,,").is_err());
+    }
+}
@@ -0,0 +1,106 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use nom::{
+    character::complete::space0,
+    combinator::eof,
+    sequence::{pair, terminated},
+};
+use serde::Serialize;
+
+use crate::header::{parser::mime::content_disposition, CONTENT_DISPOSITION};
+
+use super::{parameters::decode_parameters, TypedHeader};
+
+/// The Content-Disposition header (RFC 2183).
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ContentDisposition {
+    disposition: String,
+    parameters: BTreeMap<String, String>,
+}
+
+impl ContentDisposition {
+    /// The disposition type (e.g. `inline` or `attachment`), always lowercase.
+    pub fn disposition(&self) -> &str {
+        &self.disposition
+    }
+
+    pub fn is_attachment(&self) -> bool {
+        self.disposition == "attachment"
+    }
+
+    /// Returns the value of a parameter. Parameter names are case-insensitive.
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    pub fn filename(&self) -> Option<&str> {
+        self.parameter("filename")
+    }
+}
+
+impl TypedHeader for ContentDisposition {
+    type Error = InvalidContentDisposition;
+    const NAME: crate::header::HeaderName<'static> = CONTENT_DISPOSITION;
+
+    fn decode(encoded: &str) -> Result<Self, Self::Error> {
+        terminated(content_disposition, pair(space0, eof))(encoded.as_bytes())
+            .map(|(_, (disposition, params))| ContentDisposition {
+                disposition: String::from_utf8_lossy(disposition).to_ascii_lowercase(),
+                parameters: decode_parameters(params),
+            })
+            .map_err(|_| InvalidContentDisposition::new())
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidContentDisposition {
+    _inner: (),
+}
+
+impl InvalidContentDisposition {
+    fn new() -> Self {
+        Self { _inner: () }
+    }
+}
+
+impl Display for InvalidContentDisposition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid content-disposition header")
+    }
+}
+
+impl std::error::Error for InvalidContentDisposition {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_attachment() {
+        let cd = ContentDisposition::decode(r#"Attachment; filename="report 2023.pdf"; size=1024"#)
+            .unwrap();
+        assert!(cd.is_attachment());
+        assert_eq!(cd.filename(), Some("report 2023.pdf"));
+        assert_eq!(cd.parameter("SIZE"), Some("1024"));
+    }
+
+    #[test]
+    fn decode_inline() {
+        let cd = ContentDisposition::decode("inline").unwrap();
+        assert_eq!(cd.disposition(), "inline");
+        assert_eq!(cd.filename(), None);
+    }
+
+    #[test]
+    fn decode_rfc2231_filename() {
+        let cd = ContentDisposition::decode(
+            "attachment; filename=\"fallback\"; filename*0*=UTF-8''%C3%BCber; filename*1=\" 2.txt\"",
+        )
+        .unwrap();
+        assert_eq!(cd.filename(), Some("über 2.txt"));
+    }
+}
@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{collections::BTreeMap, fmt::Display};
+
+use nom::{
+    character::complete::space0,
+    combinator::eof,
+    sequence::{pair, terminated},
+};
+use serde::Serialize;
+
+use crate::header::{parser::mime::content_type, CONTENT_TYPE};
+
+use super::{parameters::decode_parameters, TypedHeader};
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ContentType {
+    #[serde(rename = "type")]
+    mime_type: String,
+    subtype: String,
+    parameters: BTreeMap<String, String>,
+}
+
+impl ContentType {
+    pub fn new(mime_type: &str, subtype: &str) -> Self {
+        ContentType {
+            mime_type: mime_type.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            parameters: BTreeMap::new(),
+        }
+    }
+
+    /// The top-level media type (e.g. `text` in `text/plain`), always lowercase.
+    pub fn mime_type(&self) -> &str {
+        &self.mime_type
+    }
+
+    /// The media subtype (e.g. `plain` in `text/plain`), always lowercase.
+    pub fn subtype(&self) -> &str {
+        &self.subtype
+    }
+
+    /// Returns `type/subtype` without any parameters.
+    pub fn essence(&self) -> String {
+        format!("{}/{}", self.mime_type, self.subtype)
+    }
+
+    /// Returns the value of a parameter. Parameter names are case-insensitive.
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    pub fn parameters(&self) -> impl '_ + Iterator<Item = (&str, &str)> {
+        self.parameters
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        self.mime_type == "multipart"
+    }
+
+    pub fn boundary(&self) -> Option<&str> {
+        self.parameter("boundary").filter(|b| !b.is_empty())
+    }
+
+    pub fn charset(&self) -> Option<&str> {
+        self.parameter("charset")
+    }
+}
+
+impl Default for ContentType {
+    /// RFC 2045 §5.2: `Content-Type: text/plain; charset=us-ascii` is assumed
+    /// if no Content-Type header field is specified.
+    fn default() -> Self {
+        let mut content_type = ContentType::new("text", "plain");
+        content_type
+            .parameters
+            .insert("charset".to_owned(), "us-ascii".to_owned());
+        content_type
+    }
+}
+
+impl TypedHeader for ContentType {
+    type Error = InvalidContentType;
+    const NAME: crate::header::HeaderName<'static> = CONTENT_TYPE;
+
+    fn decode(encoded: &str) -> Result<Self, Self::Error> {
+        terminated(content_type, pair(space0, eof))(encoded.as_bytes())
+            .map(|(_, (mime_type, subtype, params))| {
+                let mut content_type = ContentType::new(
+                    &String::from_utf8_lossy(mime_type),
+                    &String::from_utf8_lossy(subtype),
+                );
+                content_type.parameters = decode_parameters(params);
+                content_type
+            })
+            .map_err(|_| InvalidContentType::new())
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidContentType {
+    _inner: (),
+}
+
+impl InvalidContentType {
+    fn new() -> Self {
+        Self { _inner: () }
+    }
+}
+
+impl Display for InvalidContentType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid content-type header")
+    }
+}
+
+impl std::error::Error for InvalidContentType {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_simple() {
+        let ct = ContentType::decode("text/html").unwrap();
+        assert_eq!(ct.essence(), "text/html");
+        assert_eq!(ct.charset(), None);
+    }
+
+    #[test]
+    fn decode_parameters() {
+        let ct =
+            ContentType::decode(r#"Multipart/Mixed; Boundary="--=_abc 123"; foo=bar;"#).unwrap();
+        assert!(ct.is_multipart());
+        assert_eq!(ct.subtype(), "mixed");
+        assert_eq!(ct.boundary(), Some("--=_abc 123"));
+        assert_eq!(ct.parameter("FOO"), Some("bar"));
+    }
+
+    #[test]
+    fn decode_with_comments() {
+        let ct = ContentType::decode("text/plain (plain text); charset=utf-8 (unicode)").unwrap();
+        assert_eq!(ct.essence(), "text/plain");
+        assert_eq!(ct.charset(), Some("utf-8"));
+    }
+
+    #[test]
+    fn decode_invalid() {
+        assert!(ContentType::decode("text").is_err());
+        assert!(ContentType::decode("text/plain; charset").is_err());
+    }
+}
@@ -0,0 +1,67 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt::Display;
+
+use nom::{
+    character::complete::space0,
+    combinator::eof,
+    sequence::{pair, terminated},
+};
+use serde::Serialize;
+use time::{format_description::well_known::Iso8601, OffsetDateTime};
+
+use crate::header::{parser::datetime::date_time, ORIG_DATE};
+
+use super::TypedHeader;
+
+/// The origination date of a message (the `Date` header).
+pub struct Date(OffsetDateTime);
+
+impl Date {
+    pub fn date_time(&self) -> OffsetDateTime {
+        self.0
+    }
+}
+
+impl Serialize for Date {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let formatted = self
+            .0
+            .format(&Iso8601::DEFAULT)
+            .map_err(serde::ser::Error::custom)?;
+        serializer.serialize_str(&formatted)
+    }
+}
+
+impl TypedHeader for Date {
+    type Error = InvalidDate;
+    const NAME: crate::header::HeaderName<'static> = ORIG_DATE;
+
+    fn decode(encoded: &str) -> Result<Self, Self::Error> {
+        terminated(date_time, pair(space0, eof))(encoded.as_bytes())
+            .map(|(_, date_time)| Date(date_time))
+            .map_err(|_| InvalidDate::new())
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidDate {
+    _inner: (),
+}
+
+impl InvalidDate {
+    fn new() -> Self {
+        Self { _inner: () }
+    }
+}
+
+impl Display for InvalidDate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid date header")
+    }
+}
+
+impl std::error::Error for InvalidDate {}
@@ -0,0 +1,72 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt::Display;
+
+use nom::{
+    character::complete::space0,
+    combinator::eof,
+    sequence::{pair, terminated},
+};
+use serde::Serialize;
+
+use crate::header::{parser::msg_id::msg_id_list, IN_REPLY_TO};
+
+use super::TypedHeader;
+
+/// Message identifiers, without the surrounding angle brackets.
+#[derive(Serialize)]
+pub struct InReplyTo(Vec<String>);
+
+impl InReplyTo {
+    pub fn ids(&self) -> &[String] {
+        &self.0
+    }
+}
+
+impl TypedHeader for InReplyTo {
+    type Error = InvalidInReplyTo;
+    const NAME: crate::header::HeaderName<'static> = IN_REPLY_TO;
+
+    fn decode(encoded: &str) -> Result<Self, Self::Error> {
+        terminated(msg_id_list, pair(space0, eof))(encoded.as_bytes())
+            .map(|(_, ids)| {
+                InReplyTo(
+                    ids.into_iter()
+                        .map(|id| String::from_utf8_lossy(id).into_owned())
+                        .collect(),
+                )
+            })
+            .map_err(|_| InvalidInReplyTo::new())
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidInReplyTo {
+    _inner: (),
+}
+
+impl InvalidInReplyTo {
+    fn new() -> Self {
+        Self { _inner: () }
+    }
+}
+
+impl Display for InvalidInReplyTo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid in-reply-to header")
+    }
+}
+
+impl std::error::Error for InvalidInReplyTo {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_multiple_ids() {
+        let in_reply_to = InReplyTo::decode("<a@example.com>\r\n <b@example.com>").unwrap();
+        assert_eq!(in_reply_to.ids(), ["a@example.com", "b@example.com"]);
+        assert!(InReplyTo::decode("").is_err());
+    }
+}
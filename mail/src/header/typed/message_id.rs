@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt::Display;
+
+use nom::{
+    character::complete::space0,
+    combinator::eof,
+    sequence::{pair, terminated},
+};
+use serde::Serialize;
+
+use crate::header::{parser::msg_id::msg_id, MESSAGE_ID};
+
+use super::TypedHeader;
+
+/// The message identifier, without the surrounding angle brackets.
+#[derive(Serialize)]
+pub struct MessageId(String);
+
+impl MessageId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TypedHeader for MessageId {
+    type Error = InvalidMessageId;
+    const NAME: crate::header::HeaderName<'static> = MESSAGE_ID;
+
+    fn decode(encoded: &str) -> Result<Self, Self::Error> {
+        terminated(msg_id, pair(space0, eof))(encoded.as_bytes())
+            .map(|(_, id)| MessageId(String::from_utf8_lossy(id).into_owned()))
+            .map_err(|_| InvalidMessageId::new())
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidMessageId {
+    _inner: (),
+}
+
+impl InvalidMessageId {
+    fn new() -> Self {
+        Self { _inner: () }
+    }
+}
+
+impl Display for InvalidMessageId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid message-id header")
+    }
+}
+
+impl std::error::Error for InvalidMessageId {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_message_id() {
+        let id = MessageId::decode("\r\n <1234.5678@mail.example.com>").unwrap();
+        assert_eq!(id.as_str(), "1234.5678@mail.example.com");
+        assert!(MessageId::decode("<a@example.com> <b@example.com>").is_err());
+    }
+}
@@ -0,0 +1,168 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::collections::BTreeMap;
+
+use crate::{charset, encoding::hex_value, header::encoded_word};
+
+/// Collects the parameters of a Content-Type or Content-Disposition header into a
+/// map with lowercase names, decoding RFC 2231 extended values and continuations
+/// (e.g. `filename*0*=utf-8''%E2%82%AC; filename*1=.pdf`).
+///
+/// An extended or continued parameter takes precedence over a plain parameter of
+/// the same name, as senders usually include the plain one as a fallback.
+pub(super) fn decode_parameters(params: Vec<(&[u8], Vec<u8>)>) -> BTreeMap<String, String> {
+    let mut parameters = BTreeMap::new();
+    let mut sections: BTreeMap<String, Vec<Section>> = BTreeMap::new();
+
+    for (name, value) in params {
+        let name = String::from_utf8_lossy(name).to_ascii_lowercase();
+        match split_section(&name) {
+            Some((base, index, extended)) => {
+                sections.entry(base.to_owned()).or_default().push(Section {
+                    index,
+                    extended,
+                    value,
+                });
+            }
+            None => {
+                let value = String::from_utf8_lossy(&value);
+                // not allowed by RFC 2047, but a lot of mailers encode filenames this way
+                let value = match name.as_str() {
+                    "name" | "filename" => encoded_word::decode(&value).into_owned(),
+                    _ => value.into_owned(),
+                };
+                parameters.entry(name).or_insert(value);
+            }
+        }
+    }
+
+    for (name, mut sections) in sections {
+        sections.sort_by_key(|section| section.index);
+        parameters.insert(name, join_sections(sections));
+    }
+
+    parameters
+}
+
+struct Section {
+    index: usize,
+    extended: bool,
+    value: Vec<u8>,
+}
+
+/// Splits a parameter name of the form `name*`, `name*N` or `name*N*` into the
+/// name, the section index and whether the section value is extended.
+fn split_section(name: &str) -> Option<(&str, usize, bool)> {
+    let (name, extended) = match name.strip_suffix('*') {
+        Some(name) => (name, true),
+        None => (name, false),
+    };
+    match name.rsplit_once('*') {
+        Some((base, index)) if !index.is_empty() && index.bytes().all(|ch| ch.is_ascii_digit()) => {
+            Some((base, index.parse().ok()?, extended))
+        }
+        _ if extended => Some((name, 0, true)),
+        _ => None,
+    }
+}
+
+fn join_sections(sections: Vec<Section>) -> String {
+    let mut charset = None;
+    let mut bytes = Vec::new();
+
+    for (i, section) in sections.into_iter().enumerate() {
+        if !section.extended {
+            bytes.extend(section.value);
+            continue;
+        }
+
+        // only the first section carries the charset and language
+        let mut value = &section.value[..];
+        if i == 0 {
+            let mut fields = section.value.splitn(3, |&ch| ch == b'\'');
+            if let (Some(cs), Some(_language), Some(rest)) =
+                (fields.next(), fields.next(), fields.next())
+            {
+                charset = Some(String::from_utf8_lossy(cs).into_owned());
+                value = rest;
+            }
+        }
+        percent_decode(value, &mut bytes);
+    }
+
+    match charset.filter(|charset| !charset.is_empty()) {
+        Some(charset) => charset::decode(&charset, &bytes).into_owned(),
+        None => String::from_utf8_lossy(&bytes).into_owned(),
+    }
+}
+
+/// Decodes `%XX` escapes. Invalid escapes are kept as they are.
+fn percent_decode(input: &[u8], output: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < input.len() {
+        let decoded = match input[i] {
+            b'%' => input
+                .get(i + 1..i + 3)
+                .and_then(|hex| Some(hex_value(hex[0])? << 4 | hex_value(hex[1])?)),
+            _ => None,
+        };
+        match decoded {
+            Some(byte) => {
+                output.push(byte);
+                i += 3;
+            }
+            None => {
+                output.push(input[i]);
+                i += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(params: &[(&str, &str)]) -> BTreeMap<String, String> {
+        decode_parameters(
+            params
+                .iter()
+                .map(|(name, value)| (name.as_bytes(), value.as_bytes().to_vec()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn decode_extended_value() {
+        let params = decode(&[
+            ("filename", "fallback.pdf"),
+            ("filename*", "UTF-8'en'%E2%82%AC%20rates.pdf"),
+        ]);
+        assert_eq!(params["filename"], "€ rates.pdf");
+    }
+
+    #[test]
+    fn decode_continuations() {
+        let params = decode(&[
+            ("Filename*1", "long"),
+            ("filename*0*", "iso-8859-1''Gr%FC%DFe-"),
+            ("filename*2*", "%2Etxt"),
+            ("size", "10"),
+        ]);
+        assert_eq!(params["filename"], "Grüße-long.txt");
+        assert_eq!(params["size"], "10");
+    }
+
+    #[test]
+    fn decode_encoded_word_filename() {
+        let params = decode(&[("name", "=?UTF-8?B?w6RiYy50eHQ=?="), ("x", "=?a?b?c?=")]);
+        assert_eq!(params["name"], "äbc.txt");
+        assert_eq!(params["x"], "=?a?b?c?=");
+    }
+
+    #[test]
+    fn keep_invalid_escapes() {
+        let params = decode(&[("filename*", "utf-8''100%25%zz%+A%4")]);
+        assert_eq!(params["filename"], "100%%zz%+A%4");
+    }
+}
@@ -0,0 +1,82 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt::Display;
+
+use nom::{
+    character::complete::space0,
+    combinator::eof,
+    sequence::{pair, terminated},
+};
+use serde::Serialize;
+
+use crate::header::{parser::msg_id::msg_id_list, REFERENCES};
+
+use super::TypedHeader;
+
+/// Message identifiers, without the surrounding angle brackets.
+#[derive(Serialize)]
+pub struct References(Vec<String>);
+
+impl References {
+    pub fn ids(&self) -> &[String] {
+        &self.0
+    }
+}
+
+impl TypedHeader for References {
+    type Error = InvalidReferences;
+    const NAME: crate::header::HeaderName<'static> = REFERENCES;
+
+    fn decode(encoded: &str) -> Result<Self, Self::Error> {
+        terminated(msg_id_list, pair(space0, eof))(encoded.as_bytes())
+            .map(|(_, ids)| {
+                References(
+                    ids.into_iter()
+                        .map(|id| String::from_utf8_lossy(id).into_owned())
+                        .collect(),
+                )
+            })
+            .map_err(|_| InvalidReferences::new())
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidReferences {
+    _inner: (),
+}
+
+impl InvalidReferences {
+    fn new() -> Self {
+        Self { _inner: () }
+    }
+}
+
+impl Display for InvalidReferences {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid references header")
+    }
+}
+
+impl std::error::Error for InvalidReferences {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_folded_ids() {
+        let references = References::decode(
+            "<1@example.com> <2@example.com>\r\n\t<3@example.com>\r\n <4@example.com>",
+        )
+        .unwrap();
+        assert_eq!(
+            references.ids(),
+            [
+                "1@example.com",
+                "2@example.com",
+                "3@example.com",
+                "4@example.com"
+            ]
+        );
+    }
+}
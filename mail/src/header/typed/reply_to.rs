@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::fmt::Display;
+
+use nom::{
+    character::complete::space0,
+    combinator::eof,
+    sequence::{pair, terminated},
+};
+use serde::Serialize;
+
+use crate::header::{parser::address::address_list, parts::Address, REPLY_TO};
+
+use super::TypedHeader;
+
+#[derive(Serialize)]
+pub struct ReplyTo(Vec<Address>);
+
+impl TypedHeader for ReplyTo {
+    type Error = InvalidReplyTo;
+    const NAME: crate::header::HeaderName<'static> = REPLY_TO;
+
+    fn decode(encoded: &str) -> Result<Self, Self::Error> {
+        terminated(address_list, pair(space0, eof))(encoded.as_bytes())
+            .map(|(_, list)| ReplyTo(list))
+            .map_err(|_| InvalidReplyTo::new())
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidReplyTo {
+    _inner: (),
+}
+
+impl InvalidReplyTo {
+    fn new() -> Self {
+        Self { _inner: () }
+    }
+}
+
+impl Display for InvalidReplyTo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid reply-to header")
+    }
+}
+
+impl std::error::Error for InvalidReplyTo {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_folded_list() {
+        let ReplyTo(list) =
+            ReplyTo::decode("Support <support@example.com>,\r\n\tteam: a@example.com;").unwrap();
+        assert_eq!(list.len(), 2);
+        assert!(matches!(&list[0], Address::Mailbox(m) if m.address() == "support@example.com"));
+        assert!(matches!(&list[1], Address::Group(g)
+            if g.display_name() == "team" && g.mailboxes().len() == 1));
+    }
+}
@@ -4,7 +4,7 @@ use std::{convert::Infallible, fmt::Display};
 
 use serde::Serialize;
 
-use crate::header::SUBJECT;
+use crate::header::{encoded_word, SUBJECT};
 
 use super::TypedHeader;
 
@@ -16,8 +16,8 @@ impl TypedHeader for Subject {
     const NAME: crate::header::HeaderName<'static> = SUBJECT;
 
     fn decode(encoded: &str) -> Result<Self, Self::Error> {
-        // NOTE: encoded is already unstructured, so no need to do anything here
-        Ok(Subject(encoded.to_owned()))
+        // NOTE: encoded is already unstructured, so only encoded-words need decoding
+        Ok(Subject(encoded_word::decode(encoded).into_owned()))
     }
 }
 
@@ -33,3 +33,15 @@ impl Display for InvalidSubject {
 }
 
 impl std::error::Error for InvalidSubject {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_encoded_subject() {
+        let Subject(subject) =
+            Subject::decode("=?UTF-8?B?UGFzc3dvcnQgenVyw7xja3NldHplbg==?= (Erinnerung)").unwrap();
+        assert_eq!(subject, "Passwort zurücksetzen (Erinnerung)");
+    }
+}
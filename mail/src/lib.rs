@@ -1,10 +1,15 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+pub mod charset;
+mod encoding;
+mod entity;
 pub mod header;
 
-use std::ops::Range;
+use std::{fmt::Display, ops::Range};
 
+pub use encoding::{Base64Decoder, Decoder, InvalidEncoding, QuotedPrintableDecoder};
 pub use header::HeaderMap;
+use serde::Serialize;
 
 #[allow(dead_code)]
 mod parser;
@@ -15,6 +20,7 @@ pub enum Entity {
 }
 
 pub struct SinglePart {
+    pub header: HeaderMap,
     pub encoding: Encoding,
     pub body: Range<usize>,
 }
@@ -25,9 +31,42 @@ pub struct MultiPart {
     pub parts: Vec<Entity>,
 }
 
-pub enum Encoding {}
+/// Content-Transfer-Encoding mechanisms (RFC 2045 §6.1)
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Serialize)]
+pub enum Encoding {
+    #[default]
+    #[serde(rename = "7bit")]
+    SevenBit,
+    #[serde(rename = "8bit")]
+    EightBit,
+    #[serde(rename = "binary")]
+    Binary,
+    #[serde(rename = "quoted-printable")]
+    QuotedPrintable,
+    #[serde(rename = "base64")]
+    Base64,
+}
 
 pub enum Part {
     Single(SinglePart),
     Multi(MultiPart),
 }
+
+#[derive(Debug)]
+pub struct InvalidEntity {
+    _inner: (),
+}
+
+impl InvalidEntity {
+    fn new() -> Self {
+        Self { _inner: () }
+    }
+}
+
+impl Display for InvalidEntity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid entity")
+    }
+}
+
+impl std::error::Error for InvalidEntity {}
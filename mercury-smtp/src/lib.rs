@@ -1,18 +1,51 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use std::{collections::HashMap, path::PathBuf};
+
 use anyhow::Context as _;
-use mail::header::KNOWN_HEADERS;
-use smtp_server::RawMail;
-use storage::Storage;
+use smtp_server::{extension, Credentials, Mailbox, RawMail, ServerBuilder, TlsConfig};
+use storage::{
+    mail::{Envelope, Recipient, TlsInfo},
+    Storage,
+};
 use tokio::sync::mpsc;
-use tracing::{error, warn};
+use tracing::{error, info, warn};
 
 pub async fn run(config: &SmtpConfig, storage: Storage) -> anyhow::Result<()> {
     let (new_mail_tx, new_mail_rx) = mpsc::unbounded_channel();
     tokio::spawn(new_mail_processing_task(new_mail_rx, storage));
 
-    let server = smtp_server::Server::builder()
+    let mut builder = smtp_server::Server::builder();
+    for name in &config.extensions {
+        builder = add_extension(builder, name, config.max_message_size)?;
+    }
+    if config.tls.enabled {
+        builder = configure_tls(builder, &config.tls)?;
+    }
+    for mailbox in &config.mailboxes {
+        builder = builder.mailbox(Mailbox {
+            name: mailbox.name.clone(),
+            address: mailbox.address.clone(),
+        });
+    }
+    for (name, members) in &config.mailing_lists {
+        builder = builder.mailing_list(name, members.clone());
+    }
+    builder = match config.auth.mode {
+        AuthMode::Off => builder,
+        AuthMode::Any => builder.auth(Credentials::Any),
+        AuthMode::Users => builder.auth(Credentials::Users(config.auth.users.clone())),
+    };
+
+    let server = builder
         .bind(config.address.clone())
+        .strict(config.strict)
+        .bare_line_endings(match config.bare_line_endings {
+            BareLineEndings::Normalize => smtp_server::BareLineEndings::Normalize,
+            BareLineEndings::Reject => smtp_server::BareLineEndings::Reject,
+        })
+        .max_line_length(config.max_line_length)
+        .max_message_size(config.max_message_size)
         .on_conn_err(|err| {
             error!("connection error: {err:?}");
         })
@@ -26,6 +59,44 @@ pub async fn run(config: &SmtpConfig, storage: Storage) -> anyhow::Result<()> {
     server.run().await.map_err(Into::into)
 }
 
+fn add_extension(
+    builder: ServerBuilder,
+    name: &str,
+    max_message_size: usize,
+) -> anyhow::Result<ServerBuilder> {
+    let builder = match &name.to_ascii_uppercase()[..] {
+        "SIZE" => builder.extension(extension::Size(Some(max_message_size as u64))),
+        "8BITMIME" => builder.extension(extension::EightBitMime),
+        "PIPELINING" => builder.extension(extension::Pipelining),
+        "ENHANCEDSTATUSCODES" => builder.extension(extension::EnhancedStatusCodes),
+        "DSN" => builder.extension(extension::Dsn),
+        "STARTTLS" => builder.extension(extension::StartTls),
+        "CHUNKING" => builder.extension(extension::Chunking),
+        "BINARYMIME" => builder.extension(extension::BinaryMime),
+        "SMTPUTF8" => builder.extension(extension::SmtpUtf8),
+        _ => anyhow::bail!("unsupported ESMTP extension `{name}`"),
+    };
+    Ok(builder)
+}
+
+fn configure_tls(builder: ServerBuilder, config: &TlsSettings) -> anyhow::Result<ServerBuilder> {
+    let tls = match (&config.cert, &config.key) {
+        (Some(cert), Some(key)) => TlsConfig::from_pem_files(cert, key)
+            .with_context(|| format!("error while loading certificate {cert:?}"))?,
+        (None, None) => {
+            info!("no TLS certificate configured, generating a self-signed certificate");
+            TlsConfig::self_signed("localhost")
+                .context("error while generating self-signed certificate")?
+        }
+        _ => anyhow::bail!("TLS certificate and key must be configured together"),
+    };
+    let builder = builder.tls(tls);
+    Ok(match &config.address {
+        Some(address) => builder.bind_tls(address.clone()),
+        None => builder,
+    })
+}
+
 async fn new_mail_processing_task(mut rx: mpsc::UnboundedReceiver<RawMail>, storage: Storage) {
     while let Some(raw_mail) = rx.recv().await {
         if let Err(err) = process_new_mail(raw_mail, &storage).await {
@@ -38,19 +109,40 @@ async fn process_new_mail(raw_mail: RawMail, storage: &Storage) -> anyhow::Resul
     let byte_size = raw_mail.data.len();
     tracing::debug!(bytes = byte_size, "received mail");
 
-    let (_data, headers) = mail::HeaderMap::parse(&raw_mail.data)
-        .map_err(|_| anyhow::Error::msg("failed to parse mail headers"))?;
-    let mut known_headers = mail::HeaderMap::default();
-
-    for header_name in KNOWN_HEADERS {
-        if let Some(value) = headers.get(header_name) {
-            known_headers.insert(header_name, value.to_owned());
+    // malformed mail is still stored, so the raw data can be inspected
+    let headers = match mail::HeaderMap::parse(&raw_mail.data) {
+        Ok((_data, headers)) => headers,
+        Err(_) => {
+            warn!("failed to parse mail headers, storing mail without headers");
+            mail::HeaderMap::default()
         }
-    }
+    };
+
+    let recipients = raw_mail
+        .forward_path
+        .into_iter()
+        .zip(raw_mail.rcpt_parameters)
+        .map(|(forward_path, rcpt_parameters)| Recipient {
+            forward_path,
+            rcpt_parameters: rcpt_parameters.into_iter().collect(),
+        })
+        .collect();
+    let envelope = Envelope {
+        client_addr: raw_mail.client_addr,
+        helo: raw_mail.helo,
+        reverse_path: raw_mail.reverse_path,
+        mail_parameters: raw_mail.mail_parameters.into_iter().collect(),
+        recipients,
+        tls: raw_mail.tls.map(|tls| TlsInfo {
+            protocol: tls.protocol,
+            cipher: tls.cipher,
+        }),
+        auth_username: raw_mail.auth_username,
+    };
 
     storage
         .mail()
-        .store_mail(&known_headers, &raw_mail.data)
+        .store_mail(&envelope, &headers, &raw_mail.data)
         .await
         .context("error occurred while storage mail")?;
 
@@ -60,4 +152,104 @@ async fn process_new_mail(raw_mail: RawMail, storage: &Storage) -> anyhow::Resul
 #[derive(serde::Deserialize)]
 pub struct SmtpConfig {
     pub address: String,
+    /// ESMTP extensions advertised in the reply to EHLO.
+    #[serde(default = "default_extensions")]
+    pub extensions: Vec<String>,
+    /// Whether the command sequence is enforced strictly.
+    #[serde(default)]
+    pub strict: bool,
+    /// How lines with a bare LF or CR are handled.
+    #[serde(default)]
+    pub bare_line_endings: BareLineEndings,
+    /// The maximum length of a line of the mail data including CRLF.
+    #[serde(default = "default_max_line_length")]
+    pub max_line_length: usize,
+    /// The maximum size of the mail data in bytes, advertised with SIZE.
+    #[serde(default = "default_max_message_size")]
+    pub max_message_size: usize,
+    #[serde(default)]
+    pub tls: TlsSettings,
+    #[serde(default)]
+    pub auth: AuthSettings,
+    /// Mailboxes that VRFY can verify.
+    #[serde(default)]
+    pub mailboxes: Vec<MailboxSettings>,
+    /// Mailing lists with the addresses of their members that EXPN can expand.
+    #[serde(default)]
+    pub mailing_lists: HashMap<String, Vec<String>>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct MailboxSettings {
+    pub address: String,
+    pub name: Option<String>,
+}
+
+#[derive(serde::Deserialize, Default)]
+pub struct TlsSettings {
+    /// Whether STARTTLS is offered and implicit TLS connections are accepted.
+    #[serde(default)]
+    pub enabled: bool,
+    /// PEM encoded certificate chain, a self-signed certificate is generated if
+    /// neither the certificate nor the key are set.
+    pub cert: Option<PathBuf>,
+    /// PEM encoded private key.
+    pub key: Option<PathBuf>,
+    /// Address for implicit TLS connections.
+    pub address: Option<String>,
+}
+
+#[derive(serde::Deserialize, Default)]
+pub struct AuthSettings {
+    #[serde(default)]
+    pub mode: AuthMode,
+    /// Usernames and their passwords for [`AuthMode::Users`].
+    #[serde(default)]
+    pub users: HashMap<String, String>,
+}
+
+#[derive(serde::Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    /// The AUTH command is not offered.
+    #[default]
+    Off,
+    /// Any credentials are accepted.
+    Any,
+    /// Only the configured users are accepted.
+    Users,
+}
+
+#[derive(serde::Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum BareLineEndings {
+    /// Bare LF and CR are treated as CRLF.
+    #[default]
+    Normalize,
+    /// Commands and mail with bare LF or CR are rejected.
+    Reject,
+}
+
+fn default_max_line_length() -> usize {
+    1000
+}
+
+fn default_max_message_size() -> usize {
+    10 * 1024 * 1024
+}
+
+fn default_extensions() -> Vec<String> {
+    [
+        "SIZE",
+        "8BITMIME",
+        "PIPELINING",
+        "ENHANCEDSTATUSCODES",
+        "DSN",
+        "STARTTLS",
+        "CHUNKING",
+        "BINARYMIME",
+        "SMTPUTF8",
+    ]
+    .map(String::from)
+    .to_vec()
 }
@@ -21,6 +21,7 @@ pub enum Error {
     #[error("json error: {1}")]
     Json(#[source] serde_json::Error, &'static str),
 
+    #[allow(clippy::enum_variant_names)]
     #[error("compression error")]
     CompressionError(#[source] std::io::Error),
 }
@@ -2,11 +2,15 @@
 
 mod error;
 pub mod mail;
+mod retention;
+mod search;
 mod sqlite;
 
 use self::mail::MailId;
 use self::mail::MailStorage;
 use error::{Error, Result};
+pub use retention::RetentionConfig;
+pub use search::SearchQuery;
 use serde::Deserialize;
 use sqlite::SqliteStorage;
 use std::{path::PathBuf, sync::Arc};
@@ -36,6 +40,7 @@ impl Storage {
 pub struct StorageInner {
     pub mail: MailStorage,
     pub event_tx: broadcast::Sender<StorageEvent>,
+    retention_task: Option<tokio::task::JoinHandle<()>>,
 }
 
 impl StorageInner {
@@ -44,14 +49,21 @@ impl StorageInner {
         let mut connection = rusqlite::Connection::open(&config.sqlite.path)
             .map_err(|e| Error::Sqlite(e, "opening database"))?;
         sqlite::add_callbacks(&mut connection);
-        sqlite::migrations::migrate(&mut connection)?;
+        sqlite::migrations::migrate(&mut connection, &config.mail)?;
         let sql = SqliteStorage::new(connection);
 
         let (event_tx, _event_rx) = broadcast::channel(8);
 
+        let mail = MailStorage::new(sql, event_tx.clone(), config.mail);
+        let retention_task = config
+            .retention
+            .is_enabled()
+            .then(|| tokio::spawn(retention::run(mail.clone(), config.retention)));
+
         Ok(StorageInner {
-            mail: MailStorage::new(sql, event_tx.clone(), config.mail),
+            mail,
             event_tx,
+            retention_task,
         })
     }
 
@@ -69,15 +81,26 @@ impl StorageInner {
     }
 }
 
+impl Drop for StorageInner {
+    fn drop(&mut self) {
+        if let Some(task) = &self.retention_task {
+            task.abort();
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum StorageEvent {
     NewMail(MailId),
+    DeletedMail(Vec<MailId>),
 }
 
 #[derive(Deserialize)]
 pub struct StorageConfig {
     sqlite: SqliteStorageConfig,
     mail: MailStorageConfig,
+    #[serde(default)]
+    retention: RetentionConfig,
 }
 
 #[derive(Deserialize, Clone)]
@@ -1,23 +1,37 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use std::{
+    collections::{BTreeMap, HashMap},
     fmt::Display,
+    io::Read,
+    net::SocketAddr,
     path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
 };
 
-use async_compression::tokio::write::GzipEncoder;
-use mail::HeaderMap;
+use async_compression::tokio::{bufread::GzipDecoder, write::GzipEncoder};
+use flate2::read::GzDecoder;
+use mail::{
+    header::{encoded_word, SUBJECT},
+    HeaderMap,
+};
 use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
-use tokio::{io::AsyncWriteExt, sync::broadcast};
-use tracing::debug;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, BufReader},
+    sync::broadcast,
+};
+use tracing::{debug, error};
 
 use crate::{
     error::{Error, Result},
+    retention::RetentionConfig,
+    search::{SearchDocument, SearchQuery},
     sqlite::SqliteStorage,
     MailStorageConfig, StorageEvent,
 };
-use rusqlite::Result as SqliteResult;
+use rusqlite::{params_from_iter, types::Value, OptionalExtension, Result as SqliteResult};
 
 #[derive(Clone)]
 pub struct MailStorage {
@@ -39,81 +53,510 @@ impl MailStorage {
         }
     }
 
-    pub async fn store_mail(&self, headers: &HeaderMap, data: &[u8]) -> Result<MailId> {
-        let mail_id = self.store_mail_headers(headers).await?;
+    pub async fn store_mail(
+        &self,
+        envelope: &Envelope,
+        headers: &HeaderMap,
+        data: &[u8],
+    ) -> Result<MailId> {
+        let mail_id = self
+            .store_mail_metadata(envelope, headers, data.len())
+            .await?;
         debug!(id = debug(mail_id), "mail metadata stored");
         let mail_file_path = self.mail_file_path(mail_id);
-        write_mail_file(&mail_file_path, data).await?;
+        let file_size = write_mail_file(&mail_file_path, data).await?;
         debug!(path = debug(&mail_file_path), "mail data stored");
+        let forward_paths = envelope
+            .recipients
+            .iter()
+            .map(|rcpt| rcpt.forward_path.clone())
+            .collect::<Vec<_>>();
+        let document =
+            SearchDocument::new(headers, data, Some(&envelope.reverse_path), &forward_paths);
+        let stored = self
+            .sql
+            .with::<SqliteResult<bool>, _>(move |conn| {
+                let tx = conn.transaction()?;
+                let sql = "UPDATE mail SET file_size = ? WHERE id = ?;";
+                let updated = tx.prepare_cached(sql)?.execute((file_size, mail_id.0))?;
+                // the mail was deleted while its data was written
+                if updated == 0 {
+                    return Ok(false);
+                }
+                document.insert(&tx, mail_id)?;
+                tx.commit()?;
+                Ok(true)
+            })
+            .await
+            .map_err(|e| Error::Sqlite(e, "storing mail file size and search index"))?;
+        if !stored {
+            debug!(id = debug(mail_id), "mail deleted while it was stored");
+            self.remove_mail_file(mail_id).await;
+            return Ok(mail_id);
+        }
         let _ = self.event_tx.send(StorageEvent::NewMail(mail_id));
         Ok(mail_id)
     }
 
-    pub async fn store_mail_headers(&self, headers: &HeaderMap) -> Result<MailId> {
+    pub async fn store_mail_metadata(
+        &self,
+        envelope: &Envelope,
+        headers: &HeaderMap,
+        size: usize,
+    ) -> Result<MailId> {
         let headers_json = serde_json::to_string(headers)
             .map_err(|e| Error::Json(e, "serializing mail headers"))?;
+        let mail_parameters_json = serde_json::to_string(&envelope.mail_parameters)
+            .map_err(|e| Error::Json(e, "serializing mail parameters"))?;
+        let recipients = envelope
+            .recipients
+            .iter()
+            .map(|rcpt| {
+                serde_json::to_string(&rcpt.rcpt_parameters)
+                    .map(|params| (rcpt.forward_path.clone(), params))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| Error::Json(e, "serializing rcpt parameters"))?;
+        let client_addr = envelope.client_addr.to_string();
+        let helo = envelope.helo.clone();
+        let reverse_path = envelope.reverse_path.clone();
+        let (tls_protocol, tls_cipher) = match &envelope.tls {
+            Some(tls) => (Some(tls.protocol.clone()), Some(tls.cipher.clone())),
+            None => (None, None),
+        };
+        let auth_username = envelope.auth_username.clone();
+        let subject = decoded_subject(headers);
+        let created_at = OffsetDateTime::now_utc();
+
         self.sql
             .with::<SqliteResult<MailId>, _>(move |conn| {
-                let sql = "INSERT INTO mail (headers, created_at) VALUES (?, ?) RETURNING id;";
-                let mut statement = conn.prepare_cached(sql)?;
-                statement
-                    .query_row((headers_json, OffsetDateTime::now_utc()), |r| r.get(0usize))
-                    .map(MailId)
+                let tx = conn.transaction()?;
+                let sql = "INSERT INTO mail (headers, created_at, client_addr, helo, reverse_path, mail_parameters, tls_protocol, tls_cipher, auth_username, received_at, size, subject) \
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING id;";
+                let mail_id = tx.prepare_cached(sql)?.query_row(
+                    (
+                        headers_json,
+                        created_at,
+                        client_addr,
+                        helo,
+                        reverse_path,
+                        mail_parameters_json,
+                        tls_protocol,
+                        tls_cipher,
+                        auth_username,
+                        unix_millis(created_at),
+                        size,
+                        subject,
+                    ),
+                    |r| r.get(0usize),
+                )?;
+
+                {
+                    let sql = "INSERT INTO mail_recipient (mail_id, position, forward_path, rcpt_parameters) \
+                        VALUES (?, ?, ?, ?);";
+                    let mut statement = tx.prepare_cached(sql)?;
+                    for (position, (forward_path, params)) in recipients.into_iter().enumerate() {
+                        statement.execute((mail_id, position, forward_path, params))?;
+                    }
+                }
+
+                tx.commit()?;
+                Ok(MailId(mail_id))
             })
             .await
             .map_err(|e| Error::Sqlite(e, "storing mail"))
     }
 
+    /// Fetches a page of mail matching `filter`, sorted by `sort`. The next
+    /// page starts at the [`MailPage::next`] cursor of the previous one.
     pub async fn get_mail(
         &self,
-        max: usize,
-        before: Option<MailId>,
-        after: Option<MailId>,
+        filter: &MailFilter,
+        sort: SortKey,
         ordering: Ordering,
-    ) -> Result<Vec<StoredMail>> {
-        let before = before.map(|mid| mid.0).unwrap_or(i64::MAX);
-        let after = after.map(|mid| mid.0).unwrap_or(0);
-        let ordering = match ordering {
-            Ordering::Ascending => "ASC",
-            Ordering::Descending => "DESC",
+        cursor: Option<Cursor>,
+        max: usize,
+    ) -> Result<MailPage> {
+        let (condition, mut params) = filter.condition();
+        let key = sort.column();
+        let (direction, comparison) = match ordering {
+            Ordering::Ascending => ("ASC", ">"),
+            Ordering::Descending => ("DESC", "<"),
         };
 
         self.sql
-            .with::<SqliteResult<Vec<StoredMail>>, _>(move |conn| {
-                let sql =
-                    format!("SELECT id, headers, created_at FROM mail WHERE id < ? AND id > ? ORDER BY id {ordering} LIMIT ?;");
+            .with::<SqliteResult<MailPage>, _>(move |conn| {
+                let sql = format!("SELECT COUNT(*) FROM mail WHERE {condition};");
+                let total = conn
+                    .prepare_cached(&sql)?
+                    .query_row(params_from_iter(&params), |row| row.get(0usize))?;
+
+                let cursor_condition = match cursor {
+                    Some(cursor) => {
+                        params.extend([
+                            Value::Integer(cursor.key),
+                            Value::Integer(cursor.key),
+                            Value::Integer(cursor.id),
+                        ]);
+                        format!("({key} {comparison} ? OR ({key} = ? AND id {comparison} ?))")
+                    }
+                    None => "1".to_owned(),
+                };
+                // one more than requested to find out if there is a next page
+                let limit = i64::try_from(max).map_or(i64::MAX, |max| max.saturating_add(1));
+                params.push(Value::Integer(limit));
+                let sql = format!(
+                    "SELECT {MAIL_COLUMNS}, {key} FROM mail WHERE {condition} AND {cursor_condition} \
+                        ORDER BY {key} {direction}, id {direction} LIMIT ?;"
+                );
 
                 let mut statement = conn.prepare_cached(&sql)?;
-                let rows = statement.query_map((before, after, max as i64), |row| {
-                    let headers_json = row.get::<_, String>(1usize)?;
-                    let headers = serde_json::from_str(&headers_json).map_err(|e| {
-                        rusqlite::Error::FromSqlConversionFailure(
-                            1,
-                            rusqlite::types::Type::Text,
-                            Box::new(e),
-                        )
-                    })?;
-
-                    Ok(StoredMail {
-                        id: MailId(row.get(0usize)?),
-                        headers,
-                        created_at: row.get(2usize)?,
-                    })
+                let rows = statement.query_map(params_from_iter(&params), |row| {
+                    let key = row.get::<_, i64>(MAIL_COLUMN_COUNT)?;
+                    Ok((stored_mail_from_row(row)?, key))
                 })?;
-                rows.collect()
+                let mut rows = rows.collect::<SqliteResult<Vec<_>>>()?;
+
+                let next = match rows.len() > max {
+                    true => {
+                        rows.truncate(max);
+                        rows.last().map(|(mail, key)| Cursor {
+                            key: *key,
+                            id: mail.id.0,
+                        })
+                    }
+                    false => None,
+                };
+                let mut mail = rows.into_iter().map(|(mail, _)| mail).collect::<Vec<_>>();
+                load_recipients(conn, &mut mail)?;
+                Ok(MailPage { mail, total, next })
             })
             .await
             .map_err(|e| Error::Sqlite(e, "fetching mail headers"))
     }
 
+    /// Returns the first `max` matching mails in the order they were stored.
+    /// Unlike [`Self::get_mail`] this does not count all matching mail.
+    pub async fn first_mail(&self, filter: &MailFilter, max: usize) -> Result<Vec<StoredMail>> {
+        let (condition, mut params) = filter.condition();
+        params.push(Value::Integer(i64::try_from(max).unwrap_or(i64::MAX)));
+        self.sql
+            .with::<SqliteResult<Vec<StoredMail>>, _>(move |conn| {
+                let sql = format!(
+                    "SELECT {MAIL_COLUMNS} FROM mail WHERE {condition} ORDER BY id LIMIT ?;"
+                );
+                let mut statement = conn.prepare_cached(&sql)?;
+                let mut mail = statement
+                    .query_map(params_from_iter(&params), stored_mail_from_row)?
+                    .collect::<SqliteResult<Vec<_>>>()?;
+                load_recipients(conn, &mut mail)?;
+                Ok(mail)
+            })
+            .await
+            .map_err(|e| Error::Sqlite(e, "fetching first matching mail"))
+    }
+
+    /// Returns the id of the newest mail, `None` if no mail is stored.
+    pub async fn latest_mail_id(&self) -> Result<Option<MailId>> {
+        self.sql
+            .with::<SqliteResult<Option<i64>>, _>(move |conn| {
+                let sql = "SELECT MAX(id) FROM mail;";
+                conn.prepare_cached(sql)?
+                    .query_row((), |row| row.get(0usize))
+            })
+            .await
+            .map(|id| id.map(MailId))
+            .map_err(|e| Error::Sqlite(e, "fetching latest mail id"))
+    }
+
+    pub async fn get_mail_by_id(&self, id: MailId) -> Result<Option<StoredMail>> {
+        self.sql
+            .with::<SqliteResult<Option<StoredMail>>, _>(move |conn| {
+                let sql = format!("SELECT {MAIL_COLUMNS} FROM mail WHERE id = ?;");
+                let mut statement = conn.prepare_cached(&sql)?;
+                let mail = statement
+                    .query_row([id.0], stored_mail_from_row)
+                    .optional()?;
+                let mut mail = Vec::from_iter(mail);
+                load_recipients(conn, &mut mail)?;
+                Ok(mail.pop())
+            })
+            .await
+            .map_err(|e| Error::Sqlite(e, "fetching mail by id"))
+    }
+
+    /// Reads and decompresses the stored data of a mail.
+    pub async fn read_mail_data(&self, id: MailId) -> Result<Vec<u8>> {
+        let path = self.mail_file_path(id);
+        let file = tokio::fs::File::open(&path)
+            .await
+            .map_err(|err| Error::OpenFile(err, path))?;
+        let mut decoder = GzipDecoder::new(BufReader::new(file));
+        let mut data = Vec::new();
+        decoder
+            .read_to_end(&mut data)
+            .await
+            .map_err(Error::CompressionError)?;
+        Ok(data)
+    }
+
+    /// Deletes a mail and its data file. Returns `false` if the mail does not exist.
+    pub async fn delete_mail(&self, id: MailId) -> Result<bool> {
+        let deleted = self
+            .delete_mail_where("id = ?", vec![Value::Integer(id.0)])
+            .await?;
+        Ok(!deleted.is_empty())
+    }
+
+    /// Deletes all mail and returns the number of deleted mails.
+    pub async fn delete_all(&self) -> Result<usize> {
+        let deleted = self.delete_mail_where("1", Vec::new()).await?;
+        Ok(deleted.len())
+    }
+
+    /// Deletes the oldest mail until the limits of the retention policy are met
+    /// and returns the ids of the deleted mails.
+    pub async fn prune(&self, policy: &RetentionConfig) -> Result<Vec<MailId>> {
+        let max_count = policy.max_count;
+        let max_bytes = policy.max_bytes;
+        let cutoff = policy
+            .max_age_secs
+            .map(|secs| OffsetDateTime::now_utc() - Duration::from_secs(secs));
+
+        // everything up to and including this id has to be deleted
+        let newest_expired = self
+            .sql
+            .with::<SqliteResult<Option<i64>>, _>(move |conn| {
+                let mut newest: Option<i64> = None;
+
+                if let Some(max_count) = max_count {
+                    let sql = "SELECT id FROM mail ORDER BY id DESC LIMIT 1 OFFSET ?;";
+                    let id = conn
+                        .prepare_cached(sql)?
+                        .query_row([max_count], |row| row.get(0usize))
+                        .optional()?;
+                    newest = newest.max(id);
+                }
+
+                if let Some(max_bytes) = max_bytes {
+                    let sql = "\
+                        SELECT id FROM (
+                            SELECT id, SUM(COALESCE(file_size, 0)) OVER (ORDER BY id DESC) AS total
+                            FROM mail
+                        ) WHERE total > ? ORDER BY id DESC LIMIT 1;";
+                    let id = conn
+                        .prepare_cached(sql)?
+                        .query_row([max_bytes], |row| row.get(0usize))
+                        .optional()?;
+                    newest = newest.max(id);
+                }
+
+                if let Some(cutoff) = cutoff {
+                    let sql = "SELECT MAX(id) FROM mail WHERE created_at < ?;";
+                    let id = conn
+                        .prepare_cached(sql)?
+                        .query_row([cutoff], |row| row.get(0usize))?;
+                    newest = newest.max(id);
+                }
+
+                Ok(newest)
+            })
+            .await
+            .map_err(|e| Error::Sqlite(e, "finding expired mail"))?;
+
+        match newest_expired {
+            Some(id) => {
+                self.delete_mail_where("id <= ?", vec![Value::Integer(id)])
+                    .await
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Deletes all mail matching an SQL condition on the `mail` table. The data
+    /// files are removed after the rows, so a mail is never listed without its
+    /// data. Files that can't be removed are only logged.
+    async fn delete_mail_where(
+        &self,
+        condition: &'static str,
+        params: Vec<Value>,
+    ) -> Result<Vec<MailId>> {
+        let deleted = self
+            .sql
+            .with::<SqliteResult<Vec<MailId>>, _>(move |conn| {
+                let tx = conn.transaction()?;
+                let ids = delete_rows_where(&tx, condition, &params)?;
+                tx.commit()?;
+                Ok(ids)
+            })
+            .await
+            .map_err(|e| Error::Sqlite(e, "deleting mail"))?;
+
+        for &id in &deleted {
+            self.remove_mail_file(id).await;
+        }
+        debug!(count = deleted.len(), "mail deleted");
+        if !deleted.is_empty() {
+            let _ = self
+                .event_tx
+                .send(StorageEvent::DeletedMail(deleted.clone()));
+        }
+        Ok(deleted)
+    }
+
+    async fn remove_mail_file(&self, id: MailId) {
+        let path = self.mail_file_path(id);
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                debug!(path = debug(&path), "mail data already removed");
+            }
+            Err(err) => error!(path = debug(&path), "error while removing mail data: {err}"),
+        }
+    }
+
     pub fn mail_file_path(&self, id: MailId) -> PathBuf {
-        self.config
-            .directory
-            .join(Path::new(&format!("{}.mail.gz", id.0)))
+        mail_file_path(&self.config.directory, id)
     }
 }
 
-async fn write_mail_file(path: &Path, data: &[u8]) -> Result<()> {
+pub(crate) fn mail_file_path(directory: &Path, id: MailId) -> PathBuf {
+    directory.join(Path::new(&format!("{}.mail.gz", id.0)))
+}
+
+fn delete_rows_where(
+    conn: &rusqlite::Connection,
+    condition: &str,
+    params: &[Value],
+) -> SqliteResult<Vec<MailId>> {
+    let sql = format!(
+        "DELETE FROM mail_recipient WHERE mail_id IN (SELECT id FROM mail WHERE {condition});"
+    );
+    conn.prepare_cached(&sql)?
+        .execute(params_from_iter(params))?;
+
+    let sql =
+        format!("DELETE FROM mail_fts WHERE rowid IN (SELECT id FROM mail WHERE {condition});");
+    conn.prepare_cached(&sql)?
+        .execute(params_from_iter(params))?;
+
+    let sql = format!("DELETE FROM mail WHERE {condition} RETURNING id;");
+    let mut statement = conn.prepare_cached(&sql)?;
+    let rows = statement.query_map(params_from_iter(params), |row| row.get(0usize).map(MailId))?;
+    rows.collect()
+}
+
+/// Columns read by [`stored_mail_from_row`].
+const MAIL_COLUMNS: &str = "id, headers, created_at, client_addr, helo, reverse_path, \
+    mail_parameters, tls_protocol, tls_cipher, auth_username, size";
+const MAIL_COLUMN_COUNT: usize = 11;
+
+fn stored_mail_from_row(row: &rusqlite::Row) -> SqliteResult<StoredMail> {
+    let headers = json_column(row, 1)?;
+
+    // mail stored before envelopes were recorded has no reverse-path
+    let envelope = match row.get::<_, Option<String>>(5usize)? {
+        Some(reverse_path) => {
+            let client_addr = row.get::<_, String>(3usize)?.parse().map_err(|e| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    3,
+                    rusqlite::types::Type::Text,
+                    Box::new(e),
+                )
+            })?;
+            let tls = match (row.get(7usize)?, row.get(8usize)?) {
+                (Some(protocol), Some(cipher)) => Some(TlsInfo { protocol, cipher }),
+                _ => None,
+            };
+            Some(Envelope {
+                client_addr,
+                helo: row.get(4usize)?,
+                reverse_path,
+                mail_parameters: json_column(row, 6)?,
+                recipients: Vec::new(),
+                tls,
+                auth_username: row.get(9usize)?,
+            })
+        }
+        None => None,
+    };
+
+    Ok(StoredMail {
+        id: MailId(row.get(0usize)?),
+        headers,
+        created_at: row.get(2usize)?,
+        size: row.get(10usize)?,
+        envelope,
+    })
+}
+
+fn json_column<T: serde::de::DeserializeOwned>(row: &rusqlite::Row, idx: usize) -> SqliteResult<T> {
+    let json = row.get::<_, String>(idx)?;
+    serde_json::from_str(&json).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(idx, rusqlite::types::Type::Text, Box::new(e))
+    })
+}
+
+/// Loads the envelope recipients of all mails with one query.
+fn load_recipients(conn: &rusqlite::Connection, mail: &mut [StoredMail]) -> SqliteResult<()> {
+    let ids = mail
+        .iter()
+        .filter(|mail| mail.envelope.is_some())
+        .map(|mail| mail.id.0)
+        .collect::<Vec<_>>();
+    if ids.is_empty() {
+        return Ok(());
+    }
+
+    // the ids are passed as one JSON array, pages can have more mails than
+    // SQLite allows parameters
+    let ids = serde_json::to_string(&ids).expect("serialization error");
+    let mut statement = conn.prepare_cached(
+        "SELECT mail_id, forward_path, rcpt_parameters FROM mail_recipient \
+            WHERE mail_id IN (SELECT value FROM json_each(?)) ORDER BY mail_id, position;",
+    )?;
+    let rows = statement.query_map([ids], |row| {
+        let recipient = Recipient {
+            forward_path: row.get(1usize)?,
+            rcpt_parameters: json_column(row, 2)?,
+        };
+        Ok((row.get::<_, i64>(0usize)?, recipient))
+    })?;
+    let mut recipients = HashMap::<i64, Vec<Recipient>>::new();
+    for row in rows {
+        let (mail_id, recipient) = row?;
+        recipients.entry(mail_id).or_default().push(recipient);
+    }
+
+    for mail in mail {
+        if let Some(envelope) = &mut mail.envelope {
+            envelope.recipients = recipients.remove(&mail.id.0).unwrap_or_default();
+        }
+    }
+    Ok(())
+}
+
+/// Reads and decompresses a mail data file outside of the async runtime.
+pub(crate) fn read_mail_file_blocking(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    GzDecoder::new(std::fs::File::open(path)?).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Returns the decoded Subject of a mail for the `subject` column.
+pub(crate) fn decoded_subject(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(SUBJECT)
+        .map(|subject| encoded_word::decode(subject).into_owned())
+}
+
+/// Returns the time for the `received_at` column.
+fn unix_millis(time: OffsetDateTime) -> i64 {
+    (time.unix_timestamp_nanos() / 1_000_000) as i64
+}
+
+/// Writes the compressed mail data and returns the size of the file.
+async fn write_mail_file(path: &Path, data: &[u8]) -> Result<u64> {
     let file = tokio::fs::File::create(&path)
         .await
         .map_err(|err| Error::CreateFile(err, path.into()))?;
@@ -124,16 +567,55 @@ async fn write_mail_file(path: &Path, data: &[u8]) -> Result<()> {
         .await
         .map_err(Error::CompressionError)?;
     encoder.shutdown().await.map_err(Error::CompressionError)?;
-    Ok(())
+    let metadata = encoder
+        .get_ref()
+        .metadata()
+        .await
+        .map_err(|err| Error::CreateFile(err, path.into()))?;
+    Ok(metadata.len())
 }
 
 pub struct StoredMail {
     pub id: MailId,
     pub headers: HeaderMap,
     pub created_at: OffsetDateTime,
+    /// The size of the message in bytes, `None` if its data was missing when
+    /// sizes were first recorded.
+    pub size: Option<u64>,
+    /// The SMTP envelope, `None` for mail stored before envelopes were recorded.
+    pub envelope: Option<Envelope>,
+}
+
+/// The SMTP envelope a mail was received with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope {
+    pub client_addr: SocketAddr,
+    /// Domain given by the client in its HELO/EHLO command.
+    pub helo: Option<String>,
+    /// The MAIL FROM address, empty for the null reverse-path (`<>`).
+    pub reverse_path: String,
+    pub mail_parameters: BTreeMap<String, String>,
+    pub recipients: Vec<Recipient>,
+    /// The TLS parameters, `None` if the mail was received in plaintext.
+    pub tls: Option<TlsInfo>,
+    /// The username the client authenticated with.
+    pub auth_username: Option<String>,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recipient {
+    pub forward_path: String,
+    pub rcpt_parameters: BTreeMap<String, String>,
+}
+
+/// The protocol version and cipher suite negotiated for a TLS connection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsInfo {
+    pub protocol: String,
+    pub cipher: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct MailId(i64);
 
 impl From<MailId> for i64 {
@@ -142,13 +624,584 @@ impl From<MailId> for i64 {
     }
 }
 
+impl From<i64> for MailId {
+    fn from(id: i64) -> Self {
+        MailId(id)
+    }
+}
+
 impl Display for MailId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         <i64 as Display>::fmt(&self.0, f)
     }
 }
 
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
 pub enum Ordering {
+    #[serde(rename = "asc")]
     Ascending,
+    #[default]
+    #[serde(rename = "desc")]
     Descending,
 }
+
+/// The value mail is sorted by in [`MailStorage::get_mail`]. Mail with the same
+/// value is sorted by id.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    /// The time the mail was received.
+    #[default]
+    Date,
+    /// The size of the message.
+    Size,
+}
+
+impl SortKey {
+    fn column(self) -> &'static str {
+        match self {
+            SortKey::Date => "received_at",
+            SortKey::Size => "IFNULL(size, 0)",
+        }
+    }
+}
+
+/// Selects the mail returned by [`MailStorage::get_mail`], only mail matching
+/// all conditions is returned.
+#[derive(Debug, Clone, Default)]
+pub struct MailFilter {
+    id: Option<MailId>,
+    before: Option<MailId>,
+    after: Option<MailId>,
+    recipient: Option<String>,
+    sender: Option<String>,
+    subject: Option<String>,
+    received_after: Option<OffsetDateTime>,
+    received_before: Option<OffsetDateTime>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    headers: Vec<(String, String)>,
+    search: Option<SearchQuery>,
+}
+
+impl MailFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only the mail with the given id, to check if it matches the other
+    /// conditions.
+    pub fn id(mut self, id: MailId) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// Only mail with an id lower than `id`.
+    pub fn before(mut self, id: MailId) -> Self {
+        self.before = Some(id);
+        self
+    }
+
+    /// Only mail with an id higher than `id`.
+    pub fn after(mut self, id: MailId) -> Self {
+        self.after = Some(id);
+        self
+    }
+
+    /// Only mail with the envelope recipient `address`, ignoring case.
+    pub fn recipient(mut self, address: impl Into<String>) -> Self {
+        self.recipient = Some(address.into());
+        self
+    }
+
+    /// Only mail with the envelope sender (reverse-path) `address`, ignoring
+    /// case. The empty string matches the null reverse-path.
+    pub fn sender(mut self, address: impl Into<String>) -> Self {
+        self.sender = Some(address.into());
+        self
+    }
+
+    /// Only mail whose decoded subject contains `text`, ignoring ASCII case.
+    pub fn subject(mut self, text: impl Into<String>) -> Self {
+        self.subject = Some(text.into());
+        self
+    }
+
+    /// Only mail received at or after `time`.
+    pub fn received_after(mut self, time: OffsetDateTime) -> Self {
+        self.received_after = Some(time);
+        self
+    }
+
+    /// Only mail received before `time`.
+    pub fn received_before(mut self, time: OffsetDateTime) -> Self {
+        self.received_before = Some(time);
+        self
+    }
+
+    /// Only messages of at least `size` bytes.
+    pub fn min_size(mut self, size: u64) -> Self {
+        self.min_size = Some(size);
+        self
+    }
+
+    /// Only messages of at most `size` bytes.
+    pub fn max_size(mut self, size: u64) -> Self {
+        self.max_size = Some(size);
+        self
+    }
+
+    /// Only mail with a header field `name` whose value contains `value`,
+    /// ignoring ASCII case. Can be given multiple times.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Only mail matching a full-text search query.
+    pub fn search(mut self, query: SearchQuery) -> Self {
+        self.search = Some(query);
+        self
+    }
+
+    /// Returns an SQL condition on the `mail` table and its parameters.
+    fn condition(&self) -> (String, Vec<Value>) {
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+
+        if let Some(id) = self.id {
+            conditions.push("id = ?".to_owned());
+            params.push(Value::Integer(id.0));
+        }
+        if let Some(before) = self.before {
+            conditions.push("id < ?".to_owned());
+            params.push(Value::Integer(before.0));
+        }
+        if let Some(after) = self.after {
+            conditions.push("id > ?".to_owned());
+            params.push(Value::Integer(after.0));
+        }
+        if let Some(recipient) = &self.recipient {
+            conditions.push(
+                "id IN (SELECT mail_id FROM mail_recipient WHERE forward_path = ? COLLATE NOCASE)"
+                    .to_owned(),
+            );
+            params.push(Value::Text(recipient.clone()));
+        }
+        if let Some(sender) = &self.sender {
+            conditions.push("reverse_path = ? COLLATE NOCASE".to_owned());
+            params.push(Value::Text(sender.clone()));
+        }
+        if let Some(subject) = &self.subject {
+            conditions.push("subject LIKE ? ESCAPE '\\'".to_owned());
+            params.push(Value::Text(like_pattern(subject)));
+        }
+        if let Some(time) = self.received_after {
+            conditions.push("received_at >= ?".to_owned());
+            params.push(Value::Integer(unix_millis(time)));
+        }
+        if let Some(time) = self.received_before {
+            conditions.push("received_at < ?".to_owned());
+            params.push(Value::Integer(unix_millis(time)));
+        }
+        if let Some(size) = self.min_size {
+            conditions.push("size >= ?".to_owned());
+            params.push(Value::Integer(size as i64));
+        }
+        if let Some(size) = self.max_size {
+            conditions.push("size <= ?".to_owned());
+            params.push(Value::Integer(size as i64));
+        }
+        for (name, value) in &self.headers {
+            conditions.push(
+                "EXISTS (SELECT 1 FROM json_each(mail.headers) \
+                    WHERE json_extract(value, '$.name') = ? COLLATE NOCASE \
+                    AND json_extract(value, '$.value') LIKE ? ESCAPE '\\')"
+                    .to_owned(),
+            );
+            params.push(Value::Text(name.clone()));
+            params.push(Value::Text(like_pattern(value)));
+        }
+        if let Some(search) = &self.search {
+            let (condition, search_params) = search.condition();
+            conditions.push(condition);
+            params.extend(search_params.into_iter().map(Value::Text));
+        }
+
+        match conditions.is_empty() {
+            true => ("1".to_owned(), params),
+            false => (conditions.join(" AND "), params),
+        }
+    }
+}
+
+/// Builds a LIKE pattern matching values that contain `text`.
+fn like_pattern(text: &str) -> String {
+    let mut pattern = String::with_capacity(text.len() + 2);
+    pattern.push('%');
+    for ch in text.chars() {
+        if matches!(ch, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(ch);
+    }
+    pattern.push('%');
+    pattern
+}
+
+/// A page of mail returned by [`MailStorage::get_mail`].
+pub struct MailPage {
+    pub mail: Vec<StoredMail>,
+    /// The number of mails matching the filter on all pages.
+    pub total: usize,
+    /// The position of the next page, `None` if this is the last page.
+    pub next: Option<Cursor>,
+}
+
+/// The position of a page of mail. A cursor is only valid for the sort key and
+/// ordering it was returned for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cursor {
+    key: i64,
+    id: i64,
+}
+
+impl Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}_{}", self.key, self.id)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = InvalidCursor;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, id) = s.split_once('_').ok_or(InvalidCursor { _inner: () })?;
+        match (key.parse(), id.parse()) {
+            (Ok(key), Ok(id)) => Ok(Cursor { key, id }),
+            _ => Err(InvalidCursor { _inner: () }),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidCursor {
+    _inner: (),
+}
+
+impl Display for InvalidCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid cursor")
+    }
+}
+
+impl std::error::Error for InvalidCursor {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn envelope(reverse_path: &str, recipient: &str) -> Envelope {
+        Envelope {
+            client_addr: "127.0.0.1:2525".parse().unwrap(),
+            helo: Some("client.test".to_owned()),
+            reverse_path: reverse_path.to_owned(),
+            mail_parameters: BTreeMap::new(),
+            recipients: vec![Recipient {
+                forward_path: recipient.to_owned(),
+                rcpt_parameters: BTreeMap::new(),
+            }],
+            tls: None,
+            auth_username: None,
+        }
+    }
+
+    async fn store(storage: &MailStorage, envelope: &Envelope, data: &str) -> MailId {
+        let (_, headers) = HeaderMap::parse(data.as_bytes()).unwrap();
+        storage
+            .store_mail(envelope, &headers, data.as_bytes())
+            .await
+            .unwrap()
+    }
+
+    /// Creates a migrated in-memory storage with its own mail directory.
+    fn test_storage(name: &str) -> (MailStorage, PathBuf) {
+        let directory =
+            std::env::temp_dir().join(format!("mercury-storage-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let config = MailStorageConfig {
+            directory: directory.clone(),
+        };
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::sqlite::migrations::migrate(&mut conn, &config).unwrap();
+        let (event_tx, _) = broadcast::channel(8);
+        let storage = MailStorage::new(SqliteStorage::new(conn), event_tx, config);
+        (storage, directory)
+    }
+
+    fn ids(page: &MailPage) -> Vec<i64> {
+        page.mail.iter().map(|mail| mail.id.0).collect()
+    }
+
+    #[tokio::test]
+    async fn filter_and_page() {
+        let (storage, directory) = test_storage("filter");
+
+        let alice = envelope("app@example.test", "Alice@example.test");
+        let bob = envelope("", "bob@example.test");
+        let reset = "Subject: Password reset\r\nX-Test: run 7\r\n\r\nhello\r\n";
+        store(&storage, &alice, reset).await;
+        store(&storage, &bob, "Subject: Welcome\r\n\r\nhi\r\n").await;
+        store(
+            &storage,
+            &alice,
+            "Subject: Welcome 100%\r\n\r\nhello again\r\n",
+        )
+        .await;
+
+        let get = |filter: MailFilter, sort, ordering, cursor, max| {
+            let storage = storage.clone();
+            async move {
+                storage
+                    .get_mail(&filter, sort, ordering, cursor, max)
+                    .await
+                    .unwrap()
+            }
+        };
+        let date = SortKey::Date;
+        let desc = Ordering::Descending;
+
+        let page = get(MailFilter::new(), date, desc, None, 32).await;
+        assert_eq!(
+            (ids(&page), page.total, page.next),
+            (vec![3, 2, 1], 3, None)
+        );
+
+        let filter = MailFilter::new().recipient("alice@EXAMPLE.test");
+        let page = get(filter, date, desc, None, 32).await;
+        assert_eq!(ids(&page), vec![3, 1]);
+        let page = get(MailFilter::new().sender(""), date, desc, None, 32).await;
+        assert_eq!(ids(&page), vec![2]);
+        let page = get(
+            MailFilter::new().subject("welcome 100%"),
+            date,
+            desc,
+            None,
+            32,
+        )
+        .await;
+        assert_eq!(ids(&page), vec![3]);
+        let page = get(
+            MailFilter::new().header("x-test", "RUN"),
+            date,
+            desc,
+            None,
+            32,
+        )
+        .await;
+        assert_eq!(ids(&page), vec![1]);
+        let filter = MailFilter::new().min_size(30).max_size(49);
+        let page = get(filter, date, desc, None, 32).await;
+        assert_eq!(ids(&page), vec![3, 1]);
+        let filter = MailFilter::new().received_after(OffsetDateTime::now_utc());
+        let page = get(filter, date, desc, None, 32).await;
+        assert_eq!((ids(&page), page.total), (vec![], 0));
+
+        // sorted by size: 49, 38 and 24 bytes
+        let page = get(MailFilter::new(), SortKey::Size, desc, None, 2).await;
+        assert_eq!((ids(&page), page.total), (vec![1, 3], 3));
+        let next = page.next.expect("no cursor for the second page");
+        assert_eq!(next.to_string().parse::<Cursor>().unwrap(), next);
+        let page = get(MailFilter::new(), SortKey::Size, desc, Some(next), 2).await;
+        assert_eq!((ids(&page), page.total, page.next), (vec![2], 3, None));
+
+        let page = get(MailFilter::new(), date, desc, None, usize::MAX).await;
+        assert_eq!((ids(&page), page.next), (vec![3, 2, 1], None));
+
+        assert!("1_x".parse::<Cursor>().is_err());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn store_and_load_envelope() {
+        let (storage, directory) = test_storage("envelope");
+
+        let mut full = envelope("app@example.test", "alice@example.test");
+        full.mail_parameters
+            .insert("BODY".to_owned(), "8BITMIME".to_owned());
+        full.recipients.push(Recipient {
+            forward_path: "bob@example.test".to_owned(),
+            rcpt_parameters: BTreeMap::from([("NOTIFY".to_owned(), "NEVER".to_owned())]),
+        });
+        full.tls = Some(TlsInfo {
+            protocol: "TLSv1.3".to_owned(),
+            cipher: "TLS13_AES_128_GCM_SHA256".to_owned(),
+        });
+        full.auth_username = Some("app".to_owned());
+        let first = store(&storage, &full, "Subject: one\r\n\r\n").await;
+        let other = envelope("", "carol@example.test");
+        let second = store(&storage, &other, "Subject: two\r\n\r\n").await;
+
+        let mail = storage.get_mail_by_id(first).await.unwrap().unwrap();
+        let loaded = mail.envelope.unwrap();
+        assert_eq!(loaded.client_addr, full.client_addr);
+        assert_eq!(loaded.helo.as_deref(), Some("client.test"));
+        assert_eq!(loaded.reverse_path, "app@example.test");
+        assert_eq!(loaded.mail_parameters, full.mail_parameters);
+        let recipients = loaded
+            .recipients
+            .iter()
+            .map(|rcpt| (rcpt.forward_path.as_str(), rcpt.rcpt_parameters.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            recipients,
+            [("alice@example.test", 0), ("bob@example.test", 1)]
+        );
+        assert_eq!(loaded.tls.unwrap().protocol, "TLSv1.3");
+        assert_eq!(loaded.auth_username.as_deref(), Some("app"));
+        assert!(storage.get_mail_by_id(MailId(99)).await.unwrap().is_none());
+
+        // recipients are loaded for every mail of a page
+        let page = storage
+            .get_mail(
+                &MailFilter::new(),
+                SortKey::Date,
+                Ordering::Ascending,
+                None,
+                32,
+            )
+            .await
+            .unwrap();
+        let recipients = page
+            .mail
+            .iter()
+            .map(|mail| (mail.id, mail.envelope.as_ref().unwrap().recipients.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(recipients, [(first, 2), (second, 1)]);
+
+        // more mails than SQLite allows query parameters
+        let mail = (1..=40_000)
+            .map(|id| StoredMail {
+                id: MailId(id),
+                headers: HeaderMap::default(),
+                created_at: OffsetDateTime::now_utc(),
+                size: None,
+                envelope: Some(envelope("", "")),
+            })
+            .collect::<Vec<_>>();
+        let mail = storage
+            .sql
+            .with(move |conn| {
+                let mut mail = mail;
+                load_recipients(conn, &mut mail).map(|()| mail)
+            })
+            .await
+            .unwrap();
+        let loaded = mail
+            .iter()
+            .map(|mail| mail.envelope.as_ref().unwrap().recipients.len())
+            .sum::<usize>();
+        assert_eq!(loaded, 3);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn delete_mail_and_data() {
+        let (storage, directory) = test_storage("delete");
+        let mut event_rx = storage.event_tx.subscribe();
+        let envelope = envelope("app@example.test", "alice@example.test");
+        let first = store(&storage, &envelope, "Subject: first\r\n\r\n").await;
+        let second = store(&storage, &envelope, "Subject: second\r\n\r\n").await;
+        let third = store(&storage, &envelope, "Subject: third\r\n\r\n").await;
+        while event_rx.try_recv().is_ok() {}
+
+        assert!(storage.delete_mail(first).await.unwrap());
+        assert!(!storage.mail_file_path(first).exists());
+        assert!(storage.get_mail_by_id(first).await.unwrap().is_none());
+        assert!(matches!(
+            event_rx.try_recv(),
+            Ok(StorageEvent::DeletedMail(ids)) if ids == [first]
+        ));
+        assert!(!storage.delete_mail(first).await.unwrap());
+        assert!(event_rx.try_recv().is_err());
+
+        // the row is deleted even if its data file can't be removed
+        let path = storage.mail_file_path(second);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::create_dir(&path).unwrap();
+        assert!(storage.delete_mail(second).await.unwrap());
+        assert!(storage.get_mail_by_id(second).await.unwrap().is_none());
+        std::fs::remove_dir(&path).unwrap();
+
+        assert_eq!(storage.delete_all().await.unwrap(), 1);
+        assert!(!storage.mail_file_path(third).exists());
+        assert_eq!(storage.delete_all().await.unwrap(), 0);
+        let (mail_rows, index_rows) = storage
+            .sql
+            .with(|conn| {
+                let count = |table| {
+                    let sql = format!("SELECT COUNT(*) FROM {table};");
+                    conn.query_row(&sql, (), |row| row.get::<_, i64>(0usize))
+                        .unwrap()
+                };
+                (count("mail"), count("mail_fts"))
+            })
+            .await;
+        assert_eq!((mail_rows, index_rows), (0, 0));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn prune_by_limits() {
+        let (storage, directory) = test_storage("prune");
+        let envelope = envelope("app@example.test", "alice@example.test");
+        for _ in 0..6 {
+            store(&storage, &envelope, "Subject: hello\r\n\r\n").await;
+        }
+        let hour_ago = OffsetDateTime::now_utc() - Duration::from_secs(3600);
+        storage
+            .sql
+            .with(move |conn| {
+                conn.execute("UPDATE mail SET file_size = 100;", ())
+                    .unwrap();
+                conn.execute("UPDATE mail SET created_at = ? WHERE id = 4;", [hour_ago])
+                    .unwrap();
+            })
+            .await;
+        let prune = |policy: RetentionConfig| {
+            let storage = storage.clone();
+            async move {
+                let ids = storage.prune(&policy).await.unwrap();
+                ids.into_iter().map(|id| id.0).collect::<Vec<_>>()
+            }
+        };
+
+        let policy = RetentionConfig {
+            max_count: Some(5),
+            ..Default::default()
+        };
+        assert_eq!(prune(policy).await, [1]);
+        assert!(!storage.mail_file_path(MailId(1)).exists());
+
+        // 100 bytes each, so the newest three mails are kept
+        let policy = RetentionConfig {
+            max_bytes: Some(350),
+            ..Default::default()
+        };
+        assert_eq!(prune(policy).await, [2, 3]);
+
+        let policy = RetentionConfig {
+            max_age_secs: Some(60),
+            ..Default::default()
+        };
+        assert_eq!(prune(policy).await, [4]);
+        let policy = RetentionConfig {
+            max_count: Some(2),
+            max_bytes: Some(200),
+            max_age_secs: Some(60),
+            ..Default::default()
+        };
+        assert!(prune(policy).await.is_empty());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::time::Duration;
+
+use serde::Deserialize;
+use tracing::{error, info, trace};
+
+use crate::mail::MailStorage;
+
+/// Limits for the amount of stored mail. When any of the limits is exceeded, the
+/// oldest mail is deleted until all limits are met again.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RetentionConfig {
+    /// Maximum number of stored mails.
+    pub(crate) max_count: Option<u64>,
+    /// Maximum total size of the compressed mail files in bytes.
+    pub(crate) max_bytes: Option<u64>,
+    /// Maximum age of stored mail in seconds.
+    pub(crate) max_age_secs: Option<u64>,
+    /// Seconds between two checks of the limits.
+    pub(crate) interval_secs: u64,
+}
+
+impl RetentionConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.max_count.is_some() || self.max_bytes.is_some() || self.max_age_secs.is_some()
+    }
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            max_count: None,
+            max_bytes: None,
+            max_age_secs: None,
+            interval_secs: 60,
+        }
+    }
+}
+
+/// Periodically prunes mail according to the retention policy.
+pub(crate) async fn run(mail: MailStorage, config: RetentionConfig) {
+    info!(
+        max_count = debug(config.max_count),
+        max_bytes = debug(config.max_bytes),
+        max_age_secs = debug(config.max_age_secs),
+        "mail retention enabled"
+    );
+
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+        match mail.prune(&config).await {
+            Ok(ids) if ids.is_empty() => trace!("no mail to prune"),
+            Ok(ids) => info!(
+                count = ids.len(),
+                oldest = display(ids[0]),
+                newest = display(ids[ids.len() - 1]),
+                "pruned mail"
+            ),
+            Err(err) => error!("error while pruning mail: {err:?}"),
+        }
+    }
+}
@@ -0,0 +1,423 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Full-text search over stored mail with an SQLite FTS5 index.
+
+use std::path::Path;
+
+use mail::{
+    header::{encoded_word, HeaderName, BCC, CC, FROM, REPLY_TO, SENDER, SUBJECT, TO},
+    Entity, HeaderMap,
+};
+use rusqlite::{Connection, Result as SqliteResult};
+use tracing::{debug, warn};
+
+use crate::mail::{mail_file_path, read_mail_file_blocking, MailId};
+
+/// The text of a mail in the columns of the `mail_fts` table.
+pub(crate) struct SearchDocument {
+    subject: String,
+    sender: String,
+    recipients: String,
+    body: String,
+    attachments: String,
+    has_attachment: bool,
+}
+
+impl SearchDocument {
+    /// Extracts the searchable text of a mail and its envelope addresses. Bodies
+    /// are only indexed if the mail can be parsed, the header fields and envelope
+    /// are always indexed.
+    pub(crate) fn new(
+        headers: &HeaderMap,
+        data: &[u8],
+        reverse_path: Option<&str>,
+        forward_paths: &[String],
+    ) -> Self {
+        let header_text = |names: &[HeaderName]| {
+            names
+                .iter()
+                .flat_map(|name| headers.get_all(name.clone()))
+                .map(|value| encoded_word::decode(value).into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        let mut sender = header_text(&[FROM, SENDER, REPLY_TO]);
+        let mut recipients = header_text(&[TO, CC, BCC]);
+        sender.extend(reverse_path.map(str::to_owned));
+        recipients.extend_from_slice(forward_paths);
+
+        let mut body = Vec::new();
+        let mut attachments = Vec::new();
+        let mut has_attachment = false;
+        if let Ok(entity) = Entity::parse(data) {
+            collect_parts(
+                data,
+                &entity,
+                &mut body,
+                &mut attachments,
+                &mut has_attachment,
+            );
+        }
+
+        SearchDocument {
+            subject: header_text(&[SUBJECT]).join("\n"),
+            sender: sender.join("\n"),
+            recipients: recipients.join("\n"),
+            body: body.join("\n"),
+            attachments: attachments.join("\n"),
+            has_attachment,
+        }
+    }
+
+    pub(crate) fn insert(self, conn: &Connection, id: MailId) -> SqliteResult<()> {
+        let sql = "INSERT INTO mail_fts (rowid, subject, sender, recipients, body, attachments, has_attachment) \
+            VALUES (?, ?, ?, ?, ?, ?, ?);";
+        conn.prepare_cached(sql)?.execute((
+            i64::from(id),
+            self.subject,
+            self.sender,
+            self.recipients,
+            self.body,
+            self.attachments,
+            self.has_attachment,
+        ))?;
+        Ok(())
+    }
+}
+
+/// Collects the decoded text bodies and the filenames of the attachments.
+fn collect_parts(
+    data: &[u8],
+    entity: &Entity,
+    body: &mut Vec<String>,
+    attachments: &mut Vec<String>,
+    has_attachment: &mut bool,
+) {
+    let single = match entity {
+        Entity::SinglePart(single) => single,
+        Entity::MultiPart(multi) => {
+            for part in &multi.parts {
+                collect_parts(data, part, body, attachments, has_attachment);
+            }
+            return;
+        }
+    };
+
+    let is_attachment = entity
+        .content_disposition()
+        .map(|cd| cd.is_attachment())
+        .unwrap_or(false);
+    let filename = entity.filename();
+    *has_attachment |= is_attachment || filename.is_some();
+    attachments.extend(filename);
+
+    let content_type = entity.content_type();
+    if is_attachment || content_type.mime_type() != "text" {
+        return;
+    }
+    let decoded = single.decode(data);
+    let charset = content_type.charset().unwrap_or("utf-8");
+    let text = mail::charset::decode(charset, &decoded);
+    match content_type.subtype() {
+        "plain" => body.push(text.into_owned()),
+        "html" => body.push(strip_tags(&text)),
+        _ => {}
+    }
+}
+
+/// Removes the markup from HTML, so tag and attribute names are not indexed.
+fn strip_tags(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for ch in html.chars() {
+        match ch {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            _ if !in_tag => text.push(ch),
+            _ => {}
+        }
+    }
+    text
+}
+
+/// Indexes all mail that is not in the index yet, used to build the index for
+/// mail stored before it existed.
+pub(crate) fn index_stored_mail(conn: &Connection, directory: &Path) -> SqliteResult<()> {
+    let sql =
+        "SELECT id, headers, reverse_path FROM mail WHERE id NOT IN (SELECT rowid FROM mail_fts);";
+    let list = conn
+        .prepare(sql)?
+        .query_map((), |row| {
+            Ok((
+                MailId::from(row.get::<_, i64>(0usize)?),
+                row.get::<_, String>(1usize)?,
+                row.get::<_, Option<String>>(2usize)?,
+            ))
+        })?
+        .collect::<SqliteResult<Vec<_>>>()?;
+
+    debug!(count = list.len(), "indexing stored mail");
+    let sql = "SELECT forward_path FROM mail_recipient WHERE mail_id = ? ORDER BY position;";
+    let mut recipients = conn.prepare(sql)?;
+    for (id, headers, reverse_path) in list {
+        let headers = serde_json::from_str(&headers).unwrap_or_else(|err| {
+            warn!(id = debug(id), "indexing mail without its headers: {err}");
+            HeaderMap::default()
+        });
+        let forward_paths = recipients
+            .query_map([i64::from(id)], |row| row.get(0usize))?
+            .collect::<SqliteResult<Vec<String>>>()?;
+        let path = mail_file_path(directory, id);
+        let data = read_mail_file_blocking(&path).unwrap_or_else(|err| {
+            warn!(path = debug(&path), "indexing mail without its data: {err}");
+            Vec::new()
+        });
+        SearchDocument::new(&headers, &data, reverse_path.as_deref(), &forward_paths)
+            .insert(conn, id)?;
+    }
+    Ok(())
+}
+
+/// A search query in a simple syntax similar to the one of common mail clients.
+///
+/// The query consists of terms separated by whitespace, all of which have to
+/// match. A term is a word or a `"quoted phrase"` and may be qualified with the
+/// field it has to appear in: `from:`, `to:`, `subject:`, `body:` or
+/// `filename:`. Unqualified terms match any field, a trailing `*` matches any
+/// word with the given prefix. `has:attachment` only matches mail with
+/// attachments.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    terms: Vec<Term>,
+    has_attachment: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Term {
+    column: Option<&'static str>,
+    text: String,
+    prefix: bool,
+}
+
+impl SearchQuery {
+    pub fn parse(query: &str) -> Self {
+        let mut search = SearchQuery::default();
+        let mut rest = query.trim_start();
+        while !rest.is_empty() {
+            let (token, remaining) = split_token(rest);
+            rest = remaining.trim_start();
+
+            let (qualifier, value) = match token.split_once(':') {
+                Some((qualifier, value)) => (qualifier.to_ascii_lowercase(), value),
+                None => (String::new(), token),
+            };
+            let column = match qualifier.as_str() {
+                "" => None,
+                "from" => Some("sender"),
+                "to" => Some("recipients"),
+                "subject" => Some("subject"),
+                "body" => Some("body"),
+                "filename" => Some("attachments"),
+                "has" if value.eq_ignore_ascii_case("attachment") => {
+                    search.has_attachment = true;
+                    continue;
+                }
+                // not a known field, so the colon is part of the text
+                _ => None,
+            };
+            let value = match column {
+                Some(_) => value,
+                None => token,
+            };
+
+            let (value, prefix) = match value.strip_suffix('*') {
+                Some(value) => (value, true),
+                None => (value, false),
+            };
+            let text = value.trim_matches('"');
+            if !text.is_empty() {
+                search.terms.push(Term {
+                    column,
+                    text: text.to_owned(),
+                    prefix,
+                });
+            }
+        }
+        search
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty() && !self.has_attachment
+    }
+
+    /// Returns an SQL condition on the `mail` table and its parameters.
+    pub(crate) fn condition(&self) -> (String, Vec<String>) {
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+        if !self.terms.is_empty() {
+            conditions.push("mail_fts MATCH ?");
+            params.push(self.match_expression());
+        }
+        if self.has_attachment {
+            conditions.push("has_attachment = 1");
+        }
+        if conditions.is_empty() {
+            return ("1".to_owned(), params);
+        }
+        let condition = format!(
+            "id IN (SELECT rowid FROM mail_fts WHERE {})",
+            conditions.join(" AND ")
+        );
+        (condition, params)
+    }
+
+    /// Builds an FTS5 query, every term is quoted so no term is interpreted as
+    /// FTS5 syntax.
+    fn match_expression(&self) -> String {
+        self.terms
+            .iter()
+            .map(|term| {
+                let mut expression = String::new();
+                if let Some(column) = term.column {
+                    expression.push_str(column);
+                    expression.push_str(" : ");
+                }
+                expression.push('"');
+                expression.push_str(&term.text.replace('"', "\"\""));
+                expression.push('"');
+                if term.prefix {
+                    expression.push_str(" *");
+                }
+                expression
+            })
+            .collect::<Vec<_>>()
+            .join(" AND ")
+    }
+}
+
+/// Splits off the first whitespace separated token, which may contain a quoted
+/// phrase with whitespace.
+fn split_token(input: &str) -> (&str, &str) {
+    let mut quoted = false;
+    for (idx, ch) in input.char_indices() {
+        match ch {
+            '"' => quoted = !quoted,
+            ch if ch.is_whitespace() && !quoted => return (&input[..idx], &input[idx..]),
+            _ => {}
+        }
+    }
+    (input, "")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_query() {
+        let query = SearchQuery::parse(
+            r#"to:alice@example.test  subject:"password reset" has:attachment rep*"#,
+        );
+        assert!(query.has_attachment);
+        assert_eq!(
+            query.match_expression(),
+            r#"recipients : "alice@example.test" AND subject : "password reset" AND "rep" *"#
+        );
+
+        let query = SearchQuery::parse(r#"cc:bob say"hi" from:"#);
+        assert!(!query.has_attachment);
+        assert_eq!(query.match_expression(), r#""cc:bob" AND "say""hi""#);
+        assert!(SearchQuery::parse("  ").is_empty());
+    }
+
+    #[test]
+    fn extract_document() {
+        let data = b"From: =?utf-8?q?J=C3=B6rg?= <joerg@example.test>\r\n\
+            To: alice@example.test\r\n\
+            Subject: Report\r\n\
+            Content-Type: multipart/mixed; boundary=b\r\n\
+            \r\n\
+            --b\r\n\
+            Content-Type: text/html\r\n\
+            \r\n\
+            <p>Quarterly <b>numbers</b></p>\r\n\
+            --b\r\n\
+            Content-Type: application/pdf; name=report.pdf\r\n\
+            Content-Disposition: attachment; filename=\"q3 report.pdf\"\r\n\
+            Content-Transfer-Encoding: base64\r\n\
+            \r\n\
+            JVBERi0=\r\n\
+            --b--\r\n";
+        let (_, headers) = HeaderMap::parse(data).unwrap();
+        let document = SearchDocument::new(&headers, data, None, &[]);
+        assert_eq!(document.subject, "Report");
+        assert_eq!(document.sender, "Jörg <joerg@example.test>");
+        assert_eq!(document.recipients, "alice@example.test");
+        assert_eq!(document.body, " Quarterly  numbers  ");
+        assert_eq!(document.attachments, "q3 report.pdf");
+        assert!(document.has_attachment);
+    }
+
+    #[test]
+    fn match_indexed_mail() {
+        let config = crate::MailStorageConfig {
+            directory: std::env::temp_dir(),
+        };
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::sqlite::migrations::migrate(&mut conn, &config).unwrap();
+
+        let mails: [(&[u8], &str); 3] = [
+            (
+                b"Subject: Password reset\r\n\r\nReset it here\r\n",
+                "alice@example.test",
+            ),
+            (
+                b"Subject: Reset your password\r\n\r\nhello\r\n",
+                "bob@example.test",
+            ),
+            (
+                b"Subject: Invoice\r\n\
+                    Content-Type: multipart/mixed; boundary=b\r\n\
+                    \r\n\
+                    --b\r\n\
+                    Content-Disposition: attachment; filename=invoice.pdf\r\n\
+                    \r\n\
+                    %PDF\r\n\
+                    --b--\r\n",
+                "alice@example.test",
+            ),
+        ];
+        for (id, (data, recipient)) in (1..).zip(mails) {
+            let sql = "INSERT INTO mail (id, headers, created_at) VALUES (?, '[]', 0);";
+            conn.execute(sql, [id]).unwrap();
+            let (_, headers) = HeaderMap::parse(data).unwrap();
+            SearchDocument::new(&headers, data, None, &[recipient.to_owned()])
+                .insert(&conn, MailId::from(id))
+                .unwrap();
+        }
+
+        let search = |query: &str| {
+            let (condition, params) = SearchQuery::parse(query).condition();
+            let sql = format!("SELECT id FROM mail WHERE {condition} ORDER BY id;");
+            conn.prepare(&sql)
+                .unwrap()
+                .query_map(rusqlite::params_from_iter(params), |row| {
+                    row.get::<_, i64>(0usize)
+                })
+                .unwrap()
+                .collect::<SqliteResult<Vec<_>>>()
+                .unwrap()
+        };
+        assert_eq!(search("to:alice@example.test"), [1, 3]);
+        assert_eq!(search(r#"subject:"password reset""#), [1]);
+        assert_eq!(search("reset"), [1, 2]);
+        assert_eq!(search("pass*"), [1, 2]);
+        assert_eq!(search("has:attachment"), [3]);
+        assert_eq!(search("to:alice has:attachment invoice"), [3]);
+        assert_eq!(search("filename:invoice.pdf"), [3]);
+        assert!(search("to:carol@example.test").is_empty());
+    }
+}
@@ -1,17 +1,30 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::error::{Error, Result};
+use crate::{
+    error::{Error, Result},
+    mail::{decoded_subject, mail_file_path, read_mail_file_blocking, MailId},
+    search, MailStorageConfig,
+};
+use mail::HeaderMap;
 use rusqlite::Connection;
 use time::OffsetDateTime;
 use tracing::{debug, trace};
 
 struct Migration {
     name: &'static str,
-    function: fn(connection: &mut Connection) -> rusqlite::Result<()>,
+    function: fn(connection: &mut Connection, config: &MailStorageConfig) -> rusqlite::Result<()>,
 }
 
+/// Creates a [`Migration`] from a function, which may take the mail storage
+/// configuration if it needs access to the stored mail data.
 macro_rules! m {
     ($f:ident) => {
+        Migration {
+            name: stringify!($f),
+            function: |conn, _config| $f(conn),
+        }
+    };
+    ($f:ident, config) => {
         Migration {
             name: stringify!($f),
             function: $f,
@@ -21,13 +34,29 @@ macro_rules! m {
 
 const MIGRATIONS: &[Migration] = &[
     m!(create_mail_table), // no fmt
+    m!(add_mail_envelope),
+    m!(autoincrement_mail_id),
+    m!(add_mail_file_size),
+    m!(add_mail_tls),
+    m!(add_mail_auth_username),
+    m!(create_mail_fts, config),
+    m!(add_mail_filter_columns, config),
+    m!(fill_mail_file_size, config),
 ];
 
-pub fn migrate(conn: &mut Connection) -> Result<()> {
+pub fn migrate(conn: &mut Connection, config: &MailStorageConfig) -> Result<()> {
+    run_migrations(conn, config, MIGRATIONS)
+}
+
+fn run_migrations(
+    conn: &mut Connection,
+    config: &MailStorageConfig,
+    migrations: &[Migration],
+) -> Result<()> {
     debug!("ensuring migrations table exists...");
     create_migrations_table(conn).map_err(|e| Error::Sqlite(e, "creating migrations table"))?;
 
-    for migration in MIGRATIONS {
+    for migration in migrations {
         let migration_done = is_migration_done(conn, migration)
             .map_err(|e| Error::Sqlite(e, "checking if migration is done"))?;
         if migration_done {
@@ -35,7 +64,7 @@ pub fn migrate(conn: &mut Connection) -> Result<()> {
             continue;
         }
         debug!("executing migration `{}`", migration.name);
-        (migration.function)(conn).map_err(|e| Error::Sqlite(e, "executing migration"))?;
+        (migration.function)(conn, config).map_err(|e| Error::Sqlite(e, "executing migration"))?;
         record_migration_done(conn, migration)
             .map_err(|e| Error::Sqlite(e, "recording migration"))?;
     }
@@ -71,3 +100,300 @@ fn create_mail_table(conn: &mut Connection) -> rusqlite::Result<()> {
     statement.execute(())?;
     Ok(())
 }
+
+fn add_mail_envelope(conn: &mut Connection) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute_batch(
+        "\
+    ALTER TABLE mail ADD COLUMN client_addr TEXT;
+    ALTER TABLE mail ADD COLUMN helo TEXT;
+    ALTER TABLE mail ADD COLUMN reverse_path TEXT;
+    ALTER TABLE mail ADD COLUMN mail_parameters TEXT;
+    CREATE TABLE mail_recipient (
+        mail_id INTEGER NOT NULL REFERENCES mail (id),
+        position INTEGER NOT NULL,
+        forward_path TEXT NOT NULL,
+        rcpt_parameters TEXT NOT NULL,
+        PRIMARY KEY (mail_id, position)
+    );",
+    )?;
+    tx.commit()
+}
+
+/// Makes sure ids of deleted mail are never reused.
+fn autoincrement_mail_id(conn: &mut Connection) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute_batch(
+        "\
+    CREATE TABLE mail_new (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        headers TEXT,
+        created_at TEXT,
+        client_addr TEXT,
+        helo TEXT,
+        reverse_path TEXT,
+        mail_parameters TEXT
+    );
+    INSERT INTO mail_new SELECT id, headers, created_at, client_addr, helo, reverse_path, mail_parameters FROM mail;
+    DROP TABLE mail;
+    ALTER TABLE mail_new RENAME TO mail;",
+    )?;
+    tx.commit()
+}
+
+fn add_mail_file_size(conn: &mut Connection) -> rusqlite::Result<()> {
+    let sql = "ALTER TABLE mail ADD COLUMN file_size INTEGER;";
+    let mut statement = conn.prepare(sql)?;
+    statement.execute(())?;
+    Ok(())
+}
+
+fn add_mail_tls(conn: &mut Connection) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute_batch(
+        "\
+    ALTER TABLE mail ADD COLUMN tls_protocol TEXT;
+    ALTER TABLE mail ADD COLUMN tls_cipher TEXT;",
+    )?;
+    tx.commit()
+}
+
+fn add_mail_auth_username(conn: &mut Connection) -> rusqlite::Result<()> {
+    let sql = "ALTER TABLE mail ADD COLUMN auth_username TEXT;";
+    let mut statement = conn.prepare(sql)?;
+    statement.execute(())?;
+    Ok(())
+}
+
+/// Creates the full-text search index and indexes all stored mail.
+fn create_mail_fts(conn: &mut Connection, config: &MailStorageConfig) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute_batch(
+        "\
+    CREATE VIRTUAL TABLE mail_fts USING fts5 (
+        subject,
+        sender,
+        recipients,
+        body,
+        attachments,
+        has_attachment UNINDEXED,
+        tokenize = 'unicode61 remove_diacritics 2'
+    );",
+    )?;
+    search::index_stored_mail(&tx, &config.directory)?;
+    tx.commit()
+}
+
+/// Adds indexed columns for the filters of the mail list and fills them for the
+/// stored mail.
+fn add_mail_filter_columns(
+    conn: &mut Connection,
+    config: &MailStorageConfig,
+) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute_batch(
+        "\
+    ALTER TABLE mail ADD COLUMN received_at INTEGER;
+    ALTER TABLE mail ADD COLUMN size INTEGER;
+    ALTER TABLE mail ADD COLUMN subject TEXT;
+    UPDATE mail SET received_at = CAST(ROUND((julianday(created_at) - 2440587.5) * 86400000) AS INTEGER);
+    CREATE INDEX mail_received_at ON mail (received_at);
+    CREATE INDEX mail_size ON mail (size);
+    CREATE INDEX mail_reverse_path ON mail (reverse_path COLLATE NOCASE);
+    CREATE INDEX mail_recipient_forward_path ON mail_recipient (forward_path COLLATE NOCASE);",
+    )?;
+
+    let mail = tx
+        .prepare("SELECT id, headers FROM mail;")?
+        .query_map((), |row| {
+            Ok((
+                MailId::from(row.get::<_, i64>(0usize)?),
+                row.get::<_, String>(1usize)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    {
+        let sql = "UPDATE mail SET size = ?, subject = ?, headers = ? WHERE id = ?;";
+        let mut statement = tx.prepare(sql)?;
+        for (id, headers_json) in mail {
+            let headers = serde_json::from_str::<HeaderMap>(&headers_json).ok();
+            let subject = headers.as_ref().and_then(decoded_subject);
+            // headers stored as an object before every field was kept are
+            // converted to the list of fields the header filter expects
+            let headers_json = match headers {
+                Some(headers) if !headers_json.starts_with('[') => serde_json::to_string(&headers)
+                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?,
+                _ => headers_json,
+            };
+            // mail without data keeps an unknown size
+            let size = read_mail_file_blocking(&mail_file_path(&config.directory, id))
+                .ok()
+                .map(|data| data.len());
+            statement.execute((size, subject, headers_json, i64::from(id)))?;
+        }
+    }
+    tx.commit()
+}
+
+/// Records the file size of mail stored before sizes were recorded, so it is
+/// counted by the retention policy.
+fn fill_mail_file_size(conn: &mut Connection, config: &MailStorageConfig) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    let ids = tx
+        .prepare("SELECT id FROM mail WHERE file_size IS NULL;")?
+        .query_map((), |row| row.get::<_, i64>(0usize).map(MailId::from))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    {
+        let mut statement = tx.prepare("UPDATE mail SET file_size = ? WHERE id = ?;")?;
+        for id in ids {
+            // mail without data keeps an unknown size
+            if let Ok(metadata) = std::fs::metadata(mail_file_path(&config.directory, id)) {
+                statement.execute((metadata.len(), i64::from(id)))?;
+            }
+        }
+    }
+    tx.commit()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, io::Write, path::PathBuf};
+
+    use flate2::{write::GzEncoder, Compression};
+    use mail::header::SUBJECT;
+    use tokio::sync::broadcast;
+
+    use super::*;
+    use crate::{
+        mail::{Envelope, MailFilter, MailStorage, Ordering, SortKey},
+        sqlite::SqliteStorage,
+    };
+
+    /// Returns a connection migrated up to, but not including, the migration
+    /// `name`, and the configuration with its own mail directory.
+    fn migrated_until(name: &str) -> (Connection, MailStorageConfig) {
+        let directory: PathBuf =
+            std::env::temp_dir().join(format!("mercury-migration-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let config = MailStorageConfig { directory };
+        let mut conn = Connection::open_in_memory().unwrap();
+        let end = MIGRATIONS
+            .iter()
+            .position(|migration| migration.name == name)
+            .unwrap();
+        run_migrations(&mut conn, &config, &MIGRATIONS[..end]).unwrap();
+        (conn, config)
+    }
+
+    fn storage(mut conn: Connection, config: MailStorageConfig) -> MailStorage {
+        migrate(&mut conn, &config).unwrap();
+        let (event_tx, _) = broadcast::channel(8);
+        MailStorage::new(SqliteStorage::new(conn), event_tx, config)
+    }
+
+    #[tokio::test]
+    async fn legacy_mail_without_envelope() {
+        let (conn, config) = migrated_until("add_mail_envelope");
+        let sql = "INSERT INTO mail (id, headers, created_at) VALUES (1, ?, ?);";
+        conn.execute(sql, (r#"{"subject":"hello"}"#, OffsetDateTime::now_utc()))
+            .unwrap();
+        let directory = config.directory.clone();
+
+        let storage = storage(conn, config);
+        let mail = storage
+            .get_mail_by_id(MailId::from(1))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(mail.envelope.is_none());
+        assert_eq!(mail.headers.get(SUBJECT), Some("hello"));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn fill_file_size() {
+        let (mut conn, config) = migrated_until("fill_mail_file_size");
+        let sql = "INSERT INTO mail (id, headers, created_at) VALUES (?, '[]', ?);";
+        for id in [1, 2] {
+            conn.execute(sql, (id, OffsetDateTime::now_utc())).unwrap();
+        }
+        std::fs::write(mail_file_path(&config.directory, MailId::from(1)), [0; 42]).unwrap();
+
+        migrate(&mut conn, &config).unwrap();
+        let sizes = conn
+            .prepare("SELECT file_size FROM mail ORDER BY id;")
+            .unwrap()
+            .query_map((), |row| row.get::<_, Option<u64>>(0usize))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(sizes, [Some(42), None]);
+        std::fs::remove_dir_all(config.directory).unwrap();
+    }
+
+    #[test]
+    fn index_mail_stored_before_search() {
+        let (mut conn, config) = migrated_until("create_mail_fts");
+        let sql = "INSERT INTO mail (id, headers, created_at, reverse_path) \
+            VALUES (1, '{\"subject\":\"hello\"}', ?, 'app@example.test');";
+        conn.execute(sql, [OffsetDateTime::now_utc()]).unwrap();
+        let sql = "INSERT INTO mail_recipient VALUES (1, 0, 'alice@example.test', '{}');";
+        conn.execute(sql, ()).unwrap();
+        let file = std::fs::File::create(mail_file_path(&config.directory, MailId::from(1)));
+        let mut encoder = GzEncoder::new(file.unwrap(), Compression::default());
+        encoder
+            .write_all(b"Subject: hello\r\n\r\nsearchable words\r\n")
+            .unwrap();
+        encoder.finish().unwrap();
+
+        migrate(&mut conn, &config).unwrap();
+        let sql = "SELECT rowid FROM mail_fts WHERE mail_fts MATCH ?;";
+        for query in [
+            "searchable",
+            "subject : hello",
+            "recipients : alice",
+            "sender : app",
+        ] {
+            let id = conn.query_row(sql, [query], |row| row.get::<_, i64>(0usize));
+            assert_eq!(id, Ok(1), "{query}");
+        }
+        std::fs::remove_dir_all(config.directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn filter_legacy_headers() {
+        let (conn, config) = migrated_until("add_mail_filter_columns");
+        let sql = "INSERT INTO mail (id, headers, created_at) VALUES (1, ?, ?);";
+        let headers = r#"{"subject":"hello","x-test":"legacy run"}"#;
+        conn.execute(sql, (headers, OffsetDateTime::now_utc()))
+            .unwrap();
+        let directory = config.directory.clone();
+
+        let storage = storage(conn, config);
+        let envelope = Envelope {
+            client_addr: "127.0.0.1:2525".parse().unwrap(),
+            helo: None,
+            reverse_path: String::new(),
+            mail_parameters: BTreeMap::new(),
+            recipients: Vec::new(),
+            tls: None,
+            auth_username: None,
+        };
+        let data = b"Subject: hi\r\nX-Test: new run\r\n\r\n";
+        let (_, headers) = HeaderMap::parse(data).unwrap();
+        storage.store_mail(&envelope, &headers, data).await.unwrap();
+
+        for (value, expected) in [("run", vec![2, 1]), ("LEGACY", vec![1]), ("new", vec![2])] {
+            let filter = MailFilter::new().header("x-test", value);
+            let page = storage
+                .get_mail(&filter, SortKey::Date, Ordering::Descending, None, 32)
+                .await
+                .unwrap();
+            let ids = page.mail.iter().map(|mail| i64::from(mail.id));
+            assert_eq!(ids.collect::<Vec<_>>(), expected);
+        }
+        let mail = storage.get_mail_by_id(MailId::from(1)).await.unwrap();
+        assert_eq!(mail.unwrap().headers.get(SUBJECT), Some("hello"));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -83,7 +83,9 @@ pub async fn mail_test() -> Result<(), smtp_server::Error> {
             Ok(_) => Ok(()),
             Err(err) => panic!("server error: {:?}", err),
         },
-        err = mailer_err_rx.recv() => panic!("mailer error: {:?}", err),
-        err = err_task => panic!("connection error: {:?}", err)
+        // the channel closes and the connection task ends once the mail was
+        // sent, only an actual error may end the test
+        Some(err) = mailer_err_rx.recv() => panic!("mailer error: {:?}", err),
+        Ok(Some(err)) = err_task => panic!("connection error: {:?}", err)
     }
 }
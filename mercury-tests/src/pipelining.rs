@@ -0,0 +1,114 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::time::Duration;
+
+use smtp_server::extension;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc,
+    task,
+};
+
+const ADDR: &str = "127.0.0.1:8027";
+
+#[tokio::test]
+pub async fn pipelining_test() -> Result<(), smtp_server::Error> {
+    crate::init();
+
+    let (mail_tx, mut mail_rx) = mpsc::channel(2);
+    let server = smtp_server::Server::builder()
+        .bind(ADDR)
+        .extension(extension::Pipelining)
+        .on_new_mail(move |mail| drop(mail_tx.try_send(mail)))
+        .build()?;
+    let handle = server.handle();
+    let server_task = task::spawn(server.run());
+    tokio::time::sleep(Duration::from_millis(10)).await; // wait for server to listen
+
+    let mut client = TcpStream::connect(ADDR).await?;
+    assert_eq!(batch(&mut client, "", 1).await, ["220"]);
+    let ehlo = read_replies(&mut client, "EHLO client.test\r\n").await;
+    assert!(ehlo.contains("250 PIPELINING\r\n"), "{ehlo}");
+
+    assert_eq!(
+        batch(
+            &mut client,
+            "MAIL FROM:<sender@example.test>\r\n\
+             RCPT TO:<first@example.test>\r\n\
+             RCPT TO:<second@example.test>\r\n\
+             DATA\r\n",
+            4
+        )
+        .await,
+        ["250", "250", "250", "354"]
+    );
+    // the end of the data may be followed by the next transaction
+    assert_eq!(
+        batch(
+            &mut client,
+            "Subject: first\r\n\r\n.\r\n\
+             MAIL FROM:<sender@example.test>\r\n\
+             RCPT TO:<third@example.test>\r\n\
+             DATA\r\n",
+            4
+        )
+        .await,
+        ["250", "250", "250", "354"]
+    );
+    assert_eq!(
+        batch(&mut client, "Subject: second\r\n\r\n.\r\nRSET\r\n", 2).await,
+        ["250", "250"]
+    );
+    // a failed command doesn't stop the rest of the group
+    assert_eq!(
+        batch(
+            &mut client,
+            "MAIL FROM:<invalid\r\n\
+             RCPT TO:<first@example.test>\r\n\
+             DATA\r\n\
+             NOOP\r\n\
+             QUIT\r\n",
+            5
+        )
+        .await,
+        ["501", "503", "503", "250", "221"]
+    );
+
+    let mail = mail_rx.recv().await.expect("no mail received");
+    assert_eq!(
+        mail.forward_path,
+        ["first@example.test", "second@example.test"]
+    );
+    assert_eq!(mail.data, b"Subject: first\r\n\r\n");
+    let mail = mail_rx.recv().await.expect("no mail received");
+    assert_eq!(mail.forward_path, ["third@example.test"]);
+
+    handle.stop();
+    server_task.await.expect("server task panicked")?;
+    Ok(())
+}
+
+/// Sends a group of commands in one write and returns the codes of the
+/// `count` replies.
+async fn batch(client: &mut TcpStream, commands: &str, count: usize) -> Vec<String> {
+    let replies = read_replies(client, commands).await;
+    let codes = replies
+        .lines()
+        .map(|line| line[..3].to_owned())
+        .collect::<Vec<_>>();
+    assert_eq!(codes.len(), count, "{replies}");
+    codes
+}
+
+/// Sends the commands and reads the replies, which are expected to arrive
+/// together.
+async fn read_replies(client: &mut TcpStream, commands: &str) -> String {
+    client.write_all(commands.as_bytes()).await.unwrap();
+    let mut buffer = vec![0; 4096];
+    let count = tokio::time::timeout(Duration::from_secs(1), client.read(&mut buffer))
+        .await
+        .expect("no reply received")
+        .unwrap();
+    String::from_utf8(buffer[..count].to_vec()).unwrap()
+}
@@ -1,11 +1,17 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 mod email;
+mod pipelining;
+mod tls;
 
 fn init() {
-    let subscriber = tracing_subscriber::FmtSubscriber::builder()
-        .with_writer(tracing_subscriber::fmt::TestWriter::new())
-        .with_max_level(tracing::Level::TRACE)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber).expect("failed to set tracing subscriber");
+    static INIT: std::sync::Once = std::sync::Once::new();
+    INIT.call_once(|| {
+        let subscriber = tracing_subscriber::FmtSubscriber::builder()
+            .with_writer(tracing_subscriber::fmt::TestWriter::new())
+            .with_max_level(tracing::Level::TRACE)
+            .finish();
+        tracing::subscriber::set_global_default(subscriber)
+            .expect("failed to set tracing subscriber");
+    });
 }
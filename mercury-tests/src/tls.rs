@@ -0,0 +1,146 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use smtp_server::{extension, RawMail, TlsConfig};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufStream},
+    net::TcpStream,
+    sync::mpsc,
+    task,
+};
+use tokio_rustls::{
+    rustls::{self, Certificate, RootCertStore, ServerName},
+    TlsConnector,
+};
+
+const STARTTLS_ADDR: &str = "127.0.0.1:8026";
+const IMPLICIT_TLS_ADDR: &str = "127.0.0.1:8465";
+
+#[tokio::test]
+pub async fn tls_test() -> Result<(), smtp_server::Error> {
+    crate::init();
+
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+    let (cert_path, key_path) = write_pem_files(&cert);
+    let tls = TlsConfig::from_pem_files(&cert_path, &key_path)?;
+
+    let (mail_tx, mut mail_rx) = mpsc::channel(2);
+    let server = smtp_server::Server::builder()
+        .bind(STARTTLS_ADDR)
+        .bind_tls(IMPLICIT_TLS_ADDR)
+        .tls(tls)
+        .extension(extension::StartTls)
+        .on_new_mail(move |mail| drop(mail_tx.try_send(mail)))
+        .build()?;
+    let handle = server.handle();
+    let server_task = task::spawn(server.run());
+    tokio::time::sleep(Duration::from_millis(10)).await; // wait for server to listen
+
+    let mut roots = RootCertStore::empty();
+    roots
+        .add(&Certificate(cert.serialize_der().unwrap()))
+        .unwrap();
+    let connector = TlsConnector::from(Arc::new(
+        rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth(),
+    ));
+    let server_name = ServerName::try_from("localhost").unwrap();
+
+    // STARTTLS
+    let mut client = BufStream::new(TcpStream::connect(STARTTLS_ADDR).await?);
+    assert!(read_reply(&mut client).await.starts_with("220 "));
+    let ehlo = command(&mut client, "EHLO client.test").await;
+    assert!(ehlo.contains("250 STARTTLS\r\n"), "{ehlo}");
+    assert!(command(&mut client, "MAIL FROM:<sender@example.test>")
+        .await
+        .starts_with("250 "));
+    assert!(command(&mut client, "STARTTLS").await.starts_with("220 "));
+    let stream = connector
+        .connect(server_name.clone(), client.into_inner())
+        .await?;
+    let mut client = BufStream::new(stream);
+    // the transaction started before STARTTLS is gone
+    assert!(command(&mut client, "RCPT TO:<rcpt@example.test>")
+        .await
+        .starts_with("503 "));
+    let ehlo = command(&mut client, "EHLO client.test").await;
+    assert!(!ehlo.contains("STARTTLS"), "{ehlo}");
+    assert!(command(&mut client, "STARTTLS").await.starts_with("503 "));
+    send_mail(&mut client).await;
+
+    let mail = mail_rx.recv().await.expect("no mail received");
+    assert_tls(&mail);
+
+    // implicit TLS
+    let stream = TcpStream::connect(IMPLICIT_TLS_ADDR).await?;
+    let stream = connector.connect(server_name, stream).await?;
+    let mut client = BufStream::new(stream);
+    assert!(read_reply(&mut client).await.starts_with("220 "));
+    let ehlo = command(&mut client, "EHLO client.test").await;
+    assert!(!ehlo.contains("STARTTLS"), "{ehlo}");
+    send_mail(&mut client).await;
+
+    let mail = mail_rx.recv().await.expect("no mail received");
+    assert_tls(&mail);
+
+    handle.stop();
+    server_task.await.expect("server task panicked")?;
+    let _ = std::fs::remove_file(cert_path);
+    let _ = std::fs::remove_file(key_path);
+    Ok(())
+}
+
+fn write_pem_files(cert: &rcgen::Certificate) -> (PathBuf, PathBuf) {
+    let dir = std::env::temp_dir();
+    let cert_path = dir.join(format!("mercury-test-{}.crt", std::process::id()));
+    let key_path = dir.join(format!("mercury-test-{}.key", std::process::id()));
+    std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+    std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+    (cert_path, key_path)
+}
+
+fn assert_tls(mail: &RawMail) {
+    let tls = mail.tls.as_ref().expect("mail not received over TLS");
+    assert_eq!(tls.protocol, "TLSv1_3");
+    assert!(tls.cipher.starts_with("TLS13_"), "{}", tls.cipher);
+}
+
+async fn send_mail<S: AsyncRead + AsyncWrite + Unpin>(client: &mut BufStream<S>) {
+    assert!(command(client, "MAIL FROM:<sender@example.test>")
+        .await
+        .starts_with("250 "));
+    assert!(command(client, "RCPT TO:<rcpt@example.test>")
+        .await
+        .starts_with("250 "));
+    assert!(command(client, "DATA").await.starts_with("354 "));
+    assert!(command(client, "Subject: TLS\r\n\r\nencrypted\r\n.")
+        .await
+        .starts_with("250 "));
+    assert!(command(client, "QUIT").await.starts_with("221 "));
+}
+
+async fn command<S: AsyncRead + AsyncWrite + Unpin>(
+    client: &mut BufStream<S>,
+    line: &str,
+) -> String {
+    client.write_all(line.as_bytes()).await.unwrap();
+    client.write_all(b"\r\n").await.unwrap();
+    client.flush().await.unwrap();
+    read_reply(client).await
+}
+
+/// Reads a possibly multiline reply.
+async fn read_reply<S: AsyncRead + AsyncWrite + Unpin>(client: &mut BufStream<S>) -> String {
+    let mut reply = String::new();
+    loop {
+        let start = reply.len();
+        let count = client.read_line(&mut reply).await.unwrap();
+        assert!(count > 0, "connection closed");
+        if reply.as_bytes().get(start + 3) != Some(&b'-') {
+            return reply;
+        }
+    }
+}
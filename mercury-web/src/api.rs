@@ -1,5 +1,9 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
+mod detail;
+mod events;
 mod listen;
+mod parts;
+mod wait;
 
 use async_compression::tokio::bufread::GzipDecoder;
 use axum::{
@@ -10,24 +14,29 @@ use axum::{
     routing::get,
     Extension, Json, Router,
 };
-use http::header;
+use http::header::{self, HeaderName, HeaderValue};
 use mail::{header::typed, HeaderMap};
 use serde::Deserialize;
 use serde_json::{Map, Number, Value};
 use storage::{
-    mail::{MailId, Ordering},
-    Storage,
+    mail::{Cursor, MailFilter, MailId, Ordering, SortKey, StoredMail},
+    SearchQuery, Storage,
 };
-use time::format_description::well_known::Iso8601;
+use time::{format_description::well_known::Iso8601, OffsetDateTime};
 use tokio_util::io::ReaderStream;
 use tower_http::cors::CorsLayer;
-use tracing::error;
+use tracing::{debug, error};
 
 pub fn routes() -> Router {
     Router::new()
-        .route("/mail", get(mail_list))
+        .route("/mail", get(mail_list).delete(delete_all_mail))
+        .route("/mail/wait", get(wait::wait_for_mail))
+        .route("/mail/:id", get(detail::mail_detail).delete(delete_mail))
         .route("/mail/:id/raw", get(raw_mail))
+        .route("/mail/:id/parts", get(parts::part_list))
+        .route("/mail/:id/parts/:part_id", get(parts::part_content))
         .route("/listen", get(listen::listen))
+        .route("/events", get(events::events))
         .layer(CorsLayer::new())
 }
 
@@ -51,21 +60,142 @@ async fn raw_mail(Path(mail_id): Path<MailId>, storage: Extension<Storage>) -> i
     Ok((headers, body))
 }
 
+async fn delete_mail(
+    Path(mail_id): Path<MailId>,
+    storage: Extension<Storage>,
+) -> Result<StatusCode, (StatusCode, &'static str)> {
+    let deleted = storage.mail().delete_mail(mail_id).await.map_err(|err| {
+        let err = anyhow::Error::from(err);
+        error!("error while deleting mail: {err:?}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "error occurred while deleting mail",
+        )
+    })?;
+
+    if deleted {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err((StatusCode::NOT_FOUND, "mail not found"))
+    }
+}
+
+async fn delete_all_mail(
+    storage: Extension<Storage>,
+) -> Result<StatusCode, (StatusCode, &'static str)> {
+    storage.mail().delete_all().await.map_err(|err| {
+        let err = anyhow::Error::from(err);
+        error!("error while deleting all mail: {err:?}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "error occurred while deleting mail",
+        )
+    })?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The maximum number of mails listed at once.
+const MAX_PAGE_SIZE: usize = 256;
+
 #[derive(Deserialize)]
 struct MailListQuery {
+    /// The number of mails on a page, at most [`MAX_PAGE_SIZE`].
     max: Option<usize>,
     before: Option<MailId>,
     after: Option<MailId>,
+    /// A full-text search query, see [`SearchQuery`].
+    search: Option<String>,
+    /// An envelope recipient.
+    recipient: Option<String>,
+    /// The envelope sender.
+    sender: Option<String>,
+    /// Text the subject has to contain.
+    subject: Option<String>,
+    /// An ISO 8601 date and time.
+    received_after: Option<String>,
+    /// An ISO 8601 date and time.
+    received_before: Option<String>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    /// A header field in the form `name:value`, where the value of the field has
+    /// to contain `value`.
+    header: Option<String>,
+    #[serde(default)]
+    sort: SortKey,
+    #[serde(default)]
+    order: Ordering,
+    /// The `X-Next-Cursor` of the previous page.
+    cursor: Option<String>,
 }
 
+impl MailListQuery {
+    fn filter(&self) -> Result<MailFilter, (StatusCode, &'static str)> {
+        let mut filter = MailFilter::new();
+        if let Some(id) = self.before {
+            filter = filter.before(id);
+        }
+        if let Some(id) = self.after {
+            filter = filter.after(id);
+        }
+        if let Some(search) = self.search.as_deref().map(SearchQuery::parse) {
+            if !search.is_empty() {
+                filter = filter.search(search);
+            }
+        }
+        if let Some(recipient) = &self.recipient {
+            filter = filter.recipient(recipient);
+        }
+        if let Some(sender) = &self.sender {
+            filter = filter.sender(sender);
+        }
+        if let Some(subject) = &self.subject {
+            filter = filter.subject(subject);
+        }
+        if let Some(time) = &self.received_after {
+            filter = filter.received_after(parse_time(time)?);
+        }
+        if let Some(time) = &self.received_before {
+            filter = filter.received_before(parse_time(time)?);
+        }
+        if let Some(size) = self.min_size {
+            filter = filter.min_size(size);
+        }
+        if let Some(size) = self.max_size {
+            filter = filter.max_size(size);
+        }
+        if let Some(header) = &self.header {
+            // a header without a value only has to be present
+            let (name, value) = header.split_once(':').unwrap_or((header, ""));
+            filter = filter.header(name.trim(), value.trim());
+        }
+        Ok(filter)
+    }
+}
+
+fn parse_time(time: &str) -> Result<OffsetDateTime, (StatusCode, &'static str)> {
+    OffsetDateTime::parse(time, &Iso8601::DEFAULT)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "invalid date and time"))
+}
+
+/// Lists the mail matching the query parameters. The total number of matching
+/// mails is sent in the `X-Total-Count` header and the cursor for the next page,
+/// if there is one, in the `X-Next-Cursor` header.
 async fn mail_list(
     Query(params): Query<MailListQuery>,
     storage: Extension<Storage>,
-) -> Result<Json<Value>, (StatusCode, &'static str)> {
-    let max = params.max.unwrap_or(32);
-    let list = storage
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    let max = params.max.unwrap_or(32).min(MAX_PAGE_SIZE);
+    let filter = params.filter()?;
+    let cursor = params
+        .cursor
+        .as_deref()
+        .map(str::parse::<Cursor>)
+        .transpose()
+        .map_err(|_| (StatusCode::BAD_REQUEST, "invalid cursor"))?;
+    let page = storage
         .mail()
-        .get_mail(max, params.before, params.after, Ordering::Descending)
+        .get_mail(&filter, params.sort, params.order, cursor, max)
         .await
         .map_err(|err| {
             let err = anyhow::Error::from(err);
@@ -78,32 +208,46 @@ async fn mail_list(
 
     let mut resp = Vec::new();
 
-    for mail in list {
-        let mut item = Map::<String, Value>::with_capacity(16);
-        item.insert("id".to_owned(), Number::from(i64::from(mail.id)).into());
-
-        let created_at =
-            Value::String(mail.created_at.format(&Iso8601::DEFAULT).map_err(|err| {
-                let err = anyhow::Error::from(err);
-                error!("error while formatting created_at: {err:?}");
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "error occurred while fetching list",
-                )
-            })?);
-        item.insert("created_at".to_owned(), created_at);
-
-        if let Err(err) = serialize_mail_item_headers(&mail.headers, &mut item) {
-            error!("error while serializing mail item headers: {err}");
-            return Err((
+    for mail in &page.mail {
+        let item = serialize_mail_item(mail).map_err(|err| {
+            error!("error while serializing mail item: {err}");
+            (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "error occurred while fetching list",
-            ));
-        }
+            )
+        })?;
         resp.push(Value::Object(item));
     }
 
-    Ok(Json(Value::Array(resp)))
+    let mut headers = http::HeaderMap::new();
+    headers.insert(X_TOTAL_COUNT, HeaderValue::from(page.total));
+    if let Some(next) = page.next {
+        let next = HeaderValue::try_from(next.to_string()).expect("cursors are valid headers");
+        headers.insert(X_NEXT_CURSOR, next);
+    }
+    Ok((headers, Json(Value::Array(resp))))
+}
+
+const X_TOTAL_COUNT: HeaderName = HeaderName::from_static("x-total-count");
+const X_NEXT_CURSOR: HeaderName = HeaderName::from_static("x-next-cursor");
+
+fn serialize_mail_item(mail: &StoredMail) -> Result<Map<String, Value>, &'static str> {
+    let mut item = Map::<String, Value>::with_capacity(16);
+    item.insert("id".to_owned(), Number::from(i64::from(mail.id)).into());
+
+    let created_at = mail
+        .created_at
+        .format(&Iso8601::DEFAULT)
+        .map_err(|_| "failed to format created_at")?;
+    item.insert("created_at".to_owned(), Value::String(created_at));
+    item.insert("size".to_owned(), mail.size.into());
+
+    let envelope =
+        serde_json::to_value(&mail.envelope).map_err(|_| "failed to serialize envelope")?;
+    item.insert("envelope".to_owned(), envelope);
+
+    serialize_mail_item_headers(&mail.headers, &mut item)?;
+    Ok(item)
 }
 
 fn serialize_mail_item_headers(
@@ -112,21 +256,75 @@ fn serialize_mail_item_headers(
 ) -> Result<(), &'static str> {
     macro_rules! insert_header {
         ($HeaderType:ty, $header_name:literal) => {
-            if let Some(value) = headers
-                .get_typed::<$HeaderType>()
-                .map_err(|_| concat!("invalid ", $header_name, " header"))?
-            {
-                let value = serde_json::to_value(value)
-                    .map_err(|_| concat!("failed to serialize ", $header_name, "header"))?;
-                item.insert($header_name.to_owned(), value);
+            match headers.get_typed::<$HeaderType>() {
+                Ok(Some(value)) => {
+                    let value = serde_json::to_value(value)
+                        .map_err(|_| concat!("failed to serialize ", $header_name, " header"))?;
+                    item.insert($header_name.to_owned(), value);
+                }
+                Ok(None) => {}
+                // one malformed header should not hide the entire mail
+                Err(err) => debug!("skipping invalid {} header: {err}", $header_name),
             }
         };
     }
 
     insert_header!(typed::From, "from");
     insert_header!(typed::Sender, "sender");
+    insert_header!(typed::ReplyTo, "reply_to");
     insert_header!(typed::To, "to");
+    insert_header!(typed::Cc, "cc");
+    insert_header!(typed::Bcc, "bcc");
     insert_header!(typed::Subject, "subject");
+    insert_header!(typed::Date, "date");
+    insert_header!(typed::MessageId, "message_id");
+    insert_header!(typed::InReplyTo, "in_reply_to");
+    insert_header!(typed::References, "references");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, path::PathBuf};
+
+    use storage::mail::{Envelope, Recipient};
+
+    use super::*;
+
+    /// Opens a storage with its own database and mail directory.
+    pub(super) fn test_storage(name: &str) -> (Storage, PathBuf) {
+        let directory =
+            std::env::temp_dir().join(format!("mercury-web-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        let config = serde_json::json!({
+            "sqlite": { "path": directory.join("mercury.db") },
+            "mail": { "directory": directory.join("mail") },
+        });
+        let storage = Storage::new(serde_json::from_value(config).unwrap()).unwrap();
+        (storage, directory)
+    }
+
+    /// Stores a mail for the envelope recipient `to`.
+    pub(super) async fn store(storage: &Storage, to: &str, subject: &str) -> MailId {
+        let envelope = Envelope {
+            client_addr: "127.0.0.1:2525".parse().unwrap(),
+            helo: None,
+            reverse_path: "app@example.test".to_owned(),
+            mail_parameters: BTreeMap::new(),
+            recipients: vec![Recipient {
+                forward_path: to.to_owned(),
+                rcpt_parameters: BTreeMap::new(),
+            }],
+            tls: None,
+            auth_username: None,
+        };
+        let data = format!("Subject: {subject}\r\n\r\nhello\r\n");
+        let (_, headers) = HeaderMap::parse(data.as_bytes()).unwrap();
+        storage
+            .mail()
+            .store_mail(&envelope, &headers, data.as_bytes())
+            .await
+            .unwrap()
+    }
+}
@@ -0,0 +1,107 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use axum::{extract::Path, http::StatusCode, Extension, Json};
+use mail::Entity;
+use serde_json::{Number, Value};
+use storage::{mail::MailId, Storage};
+use tracing::{debug, error};
+
+use super::{parts::serialize_part_info, serialize_mail_item};
+
+pub async fn mail_detail(
+    Path(mail_id): Path<MailId>,
+    storage: Extension<Storage>,
+) -> Result<Json<Value>, (StatusCode, &'static str)> {
+    let internal_error = |err: anyhow::Error| {
+        error!("error while fetching mail details: {err:?}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "error occurred while fetching mail",
+        )
+    };
+
+    let mail = storage
+        .mail()
+        .get_mail_by_id(mail_id)
+        .await
+        .map_err(|err| internal_error(err.into()))?
+        .ok_or((StatusCode::NOT_FOUND, "mail not found"))?;
+    let data = storage
+        .mail()
+        .read_mail_data(mail_id)
+        .await
+        .map_err(|err| internal_error(err.into()))?;
+
+    let mut item =
+        serialize_mail_item(&mail).map_err(|err| internal_error(anyhow::anyhow!(err)))?;
+    item.insert("size".to_owned(), Number::from(data.len()).into());
+
+    match Entity::parse(&data) {
+        Ok(entity) => {
+            let headers =
+                serde_json::to_value(entity.header()).map_err(|err| internal_error(err.into()))?;
+            item.insert("headers".to_owned(), headers);
+            item.insert("body".to_owned(), serialize_entity(&data, &entity, None));
+        }
+
+        Err(err) => {
+            // fall back to the stored headers if the message itself cannot be parsed
+            debug!(id = display(mail_id), "failed to parse mail entity: {err}");
+            let headers =
+                serde_json::to_value(&mail.headers).map_err(|err| internal_error(err.into()))?;
+            item.insert("headers".to_owned(), headers);
+            item.insert("body".to_owned(), Value::Null);
+        }
+    }
+
+    Ok(Json(Value::Object(item)))
+}
+
+/// Serializes an entity and all of its parts. `id` is the part id of the entity,
+/// which is `None` for the root of the tree (see [`Entity::part`]).
+fn serialize_entity(data: &[u8], entity: &Entity, id: Option<&str>) -> Value {
+    // the body of a message that is not multipart is also part `1`
+    let id = match (id, entity) {
+        (None, Entity::SinglePart(_)) => Some("1"),
+        (id, _) => id,
+    };
+    let mut part = serialize_part_info(entity, id);
+
+    match entity {
+        Entity::SinglePart(single) => {
+            let decoded = single.decode(data);
+            part.insert(
+                "decoded_size".to_owned(),
+                Number::from(decoded.len()).into(),
+            );
+
+            let content_type = entity.content_type();
+            let is_attachment = entity
+                .content_disposition()
+                .map(|cd| cd.is_attachment())
+                .unwrap_or(false);
+            let is_text = content_type.mime_type() == "text"
+                && matches!(content_type.subtype(), "plain" | "html");
+            if is_text && !is_attachment {
+                let charset = content_type.charset().unwrap_or("utf-8");
+                let text = mail::charset::decode(charset, &decoded);
+                part.insert("text".to_owned(), Value::String(text.into_owned()));
+            }
+        }
+
+        Entity::MultiPart(multi) => {
+            let parts = multi
+                .parts
+                .iter()
+                .enumerate()
+                .map(|(index, child)| {
+                    let child_id = Entity::child_part_id(id, index);
+                    serialize_entity(data, child, Some(&child_id))
+                })
+                .collect();
+            part.insert("parts".to_owned(), Value::Array(parts));
+        }
+    }
+
+    Value::Object(part)
+}
@@ -0,0 +1,237 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{collections::VecDeque, convert::Infallible};
+
+use axum::{
+    http::{HeaderMap, StatusCode},
+    response::sse::{Event, KeepAlive, Sse},
+    Extension,
+};
+use futures_util::stream::{self, Stream};
+use storage::{
+    mail::{MailFilter, MailId},
+    Storage, StorageEvent,
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, error};
+
+use super::listen::{new_mail_message, ToClientMessage};
+
+/// The number of missed mails fetched at once when replaying.
+const REPLAY_PAGE_SIZE: usize = 64;
+
+/// Streams the storage events as Server-Sent Events. The event name is the
+/// message type and the data is the message as it is sent over the WebSocket of
+/// [`super::listen`].
+///
+/// New mail events have the mail id as their event id, so a client reconnecting
+/// with a `Last-Event-ID` header is sent all mail stored after it first.
+pub async fn events(
+    headers: HeaderMap,
+    storage: Extension<Storage>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, &'static str)> {
+    let last_event_id = headers
+        .get("last-event-id")
+        .map(|id| {
+            id.to_str()
+                .ok()
+                .and_then(|id| id.trim().parse::<i64>().ok())
+                .map(MailId::from)
+                .ok_or((StatusCode::BAD_REQUEST, "invalid Last-Event-ID"))
+        })
+        .transpose()?;
+    debug!(last_event_id = debug(last_event_id), "event stream opened");
+
+    let state = EventStream::new(storage.0, last_event_id)
+        .await
+        .map_err(|err| {
+            error!("error while opening event stream: {err:?}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "error occurred while opening event stream",
+            )
+        })?;
+    let stream = stream::unfold(state, |mut state| async move {
+        let msg = state.next_message().await?;
+        Some((Ok(event(&msg)), state))
+    });
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+struct EventStream {
+    storage: Storage,
+    event_rx: broadcast::Receiver<StorageEvent>,
+    pending: VecDeque<ToClientMessage>,
+    /// Mail stored after `last_sent` still has to be sent from the storage.
+    replay: bool,
+    /// The newest mail the client knows about, either because it was sent to
+    /// it or because it was stored before the stream was opened.
+    last_sent: Option<MailId>,
+}
+
+impl EventStream {
+    /// Opens a stream of the events after the mail `last_event_id`, or after
+    /// the newest mail if it is not given.
+    async fn new(storage: Storage, last_event_id: Option<MailId>) -> anyhow::Result<EventStream> {
+        // subscribe before looking up where to start, so no mail is missed in
+        // between
+        let event_rx = storage.subscribe();
+        let last_sent = match last_event_id {
+            Some(id) => Some(id),
+            None => storage.mail().latest_mail_id().await?,
+        };
+        Ok(EventStream {
+            storage,
+            event_rx,
+            pending: VecDeque::new(),
+            replay: last_event_id.is_some(),
+            last_sent,
+        })
+    }
+
+    /// Returns the next message, or `None` when the storage shuts down.
+    async fn next_message(&mut self) -> Option<ToClientMessage> {
+        loop {
+            if let Some(msg) = self.pending.pop_front() {
+                return Some(msg);
+            }
+            if self.replay {
+                self.replay_page().await;
+                continue;
+            }
+
+            match self.event_rx.recv().await {
+                Ok(StorageEvent::NewMail(id)) => {
+                    // the mail was already sent while replaying
+                    if self.last_sent >= Some(id) {
+                        continue;
+                    }
+                    self.new_mail(id).await;
+                }
+                Ok(StorageEvent::DeletedMail(ids)) => {
+                    self.pending.push_back(ToClientMessage::MailDeleted { ids });
+                }
+                // events were dropped, but new mail can be replayed from the storage
+                Err(RecvError::Lagged(count)) => {
+                    debug!(count, "event stream lagged behind");
+                    self.replay = true;
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    async fn new_mail(&mut self, id: MailId) {
+        match self.storage.mail().get_mail_by_id(id).await {
+            Ok(Some(mail)) => {
+                self.pending.push_back(new_mail_message(&mail));
+                self.last_sent = Some(id);
+            }
+            // the mail was deleted in the meantime
+            Ok(None) => {}
+            Err(err) => error!(
+                "error while fetching new mail: {:?}",
+                anyhow::Error::from(err)
+            ),
+        }
+    }
+
+    async fn replay_page(&mut self) {
+        let mut filter = MailFilter::new();
+        if let Some(last_sent) = self.last_sent {
+            filter = filter.after(last_sent);
+        }
+        match self
+            .storage
+            .mail()
+            .first_mail(&filter, REPLAY_PAGE_SIZE)
+            .await
+        {
+            Ok(page) => {
+                if let Some(last) = page.last() {
+                    self.last_sent = Some(last.id);
+                }
+                self.replay = page.len() == REPLAY_PAGE_SIZE;
+                self.pending.extend(page.iter().map(new_mail_message));
+            }
+            Err(err) => {
+                error!("error while replaying mail: {:?}", anyhow::Error::from(err));
+                self.replay = false;
+            }
+        }
+    }
+}
+
+fn event(msg: &ToClientMessage) -> Event {
+    let event = Event::default()
+        .event(msg.event_name())
+        .json_data(msg)
+        .expect("serialization error");
+    match msg {
+        ToClientMessage::NewMail { id, .. } => event.id(id.to_string()),
+        _ => event,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::api::test::{store, test_storage};
+
+    async fn next_mail_id(stream: &mut EventStream) -> MailId {
+        let next = tokio::time::timeout(Duration::from_secs(5), stream.next_message());
+        match next.await.expect("no message was sent").unwrap() {
+            ToClientMessage::NewMail { id, .. } => id,
+            msg => panic!("expected NewMail, got {}", msg.event_name()),
+        }
+    }
+
+    /// Asserts that no message is sent for the events that are left.
+    async fn assert_no_message(stream: &mut EventStream) {
+        let next = tokio::time::timeout(Duration::from_millis(100), stream.next_message()).await;
+        assert!(next.is_err());
+    }
+
+    #[tokio::test]
+    async fn replay_after_last_event_id() {
+        let (storage, directory) = test_storage("events");
+        let first = store(&storage, "alice@example.test", "First").await;
+        let second = store(&storage, "bob@example.test", "Second").await;
+
+        let mut stream = EventStream::new(storage.clone(), Some(first))
+            .await
+            .unwrap();
+        let third = store(&storage, "alice@example.test", "Third").await;
+        assert_eq!(next_mail_id(&mut stream).await, second);
+        assert_eq!(next_mail_id(&mut stream).await, third);
+        assert_no_message(&mut stream).await;
+
+        // without Last-Event-ID only mail stored afterwards is sent
+        let mut stream = EventStream::new(storage.clone(), None).await.unwrap();
+        let fourth = store(&storage, "alice@example.test", "Fourth").await;
+        assert_eq!(next_mail_id(&mut stream).await, fourth);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replay_after_lagging() {
+        let (storage, directory) = test_storage("events-lag");
+        store(&storage, "alice@example.test", "Old").await;
+
+        // more mail than the event channel holds arrives before the first event
+        let mut stream = EventStream::new(storage.clone(), None).await.unwrap();
+        let mut stored = Vec::new();
+        for _ in 0..(REPLAY_PAGE_SIZE + 2) {
+            stored.push(store(&storage, "alice@example.test", "New").await);
+        }
+        let mut sent = Vec::new();
+        for _ in 0..stored.len() {
+            sent.push(next_mail_id(&mut stream).await);
+        }
+        assert_eq!(sent, stored);
+        assert_no_message(&mut stream).await;
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -9,9 +9,19 @@ use axum::{
     Extension,
 };
 use serde::{Deserialize, Serialize};
-use storage::{Storage, StorageEvent};
+use serde_json::{Map, Value};
+use storage::{
+    mail::{MailFilter, MailId, StoredMail},
+    Storage, StorageEvent,
+};
+use tokio::sync::broadcast::error::RecvError;
 use tracing::{debug, error, trace};
 
+use super::serialize_mail_item;
+
+/// The number of missed mails fetched at once when a client resumes.
+const RESUME_PAGE_SIZE: usize = 64;
+
 pub async fn listen(ws: WebSocketUpgrade, storage: Extension<Storage>) -> impl IntoResponse {
     ws.on_upgrade(move |socket| handle_socket(socket, storage.0))
 }
@@ -29,7 +39,7 @@ async fn handle_socket(mut socket: WebSocket, storage: Storage) {
                 if let Some(msg) = maybe_msg {
                     state.active = true;
                     match msg {
-                        Ok(msg) => on_recv_ws_message(&mut socket, msg, &mut state).await,
+                        Ok(msg) => on_recv_ws_message(&mut socket, &storage, msg, &mut state).await,
                         Err(err) => {
                             error!(error = debug(err), "websocket error");
                             break 'select_loop;
@@ -40,11 +50,14 @@ async fn handle_socket(mut socket: WebSocket, storage: Storage) {
                 }
             },
 
-            store_event = event_rx.recv() => {
-                if let Ok(event) = store_event {
-                    on_recv_storage_event(&mut socket, event, &mut state).await;
-                } else {
-                    error!("storage event recv error");
+            store_event = event_rx.recv() => match store_event {
+                Ok(event) => on_recv_storage_event(&mut socket, &storage, event, &mut state).await,
+                Err(RecvError::Lagged(count)) => {
+                    debug!(count, "missed storage events");
+                    on_missed_storage_events(&mut socket, &storage, &mut state).await;
+                }
+                Err(RecvError::Closed) => {
+                    error!("storage event channel closed");
                     break 'select_loop;
                 }
             },
@@ -74,23 +87,88 @@ async fn handle_socket(mut socket: WebSocket, storage: Storage) {
 
 async fn on_recv_storage_event(
     socket: &mut WebSocket,
+    storage: &Storage,
     event: StorageEvent,
     state: &mut SocketState,
 ) {
-    debug!(event = debug(event), "received storage event");
+    debug!(event = debug(&event), "received storage event");
 
-    if state.listen_for_new_mail {
-        let msg =
-            serde_json::to_string(&ToClientMessage::NewMailAvailable).expect("serialization error");
+    let subscription = match &mut state.subscription {
+        Some(subscription) => subscription,
+        None => return,
+    };
+    match subscription.message_for_event(storage, event).await {
+        Ok(Some(msg)) => {
+            send_message(socket, &msg).await;
+            state.active = true;
+        }
+        Ok(None) => {}
+        Err(err) => error!("error while fetching new mail: {err:?}"),
+    }
+}
 
-        if let Err(error) = socket.send(Message::Text(msg)).await {
-            error!(error = debug(error), "socket send error");
+/// Catches up after storage events were dropped because the client could not
+/// keep up with them.
+async fn on_missed_storage_events(
+    socket: &mut WebSocket,
+    storage: &Storage,
+    state: &mut SocketState,
+) {
+    let subscription = match &mut state.subscription {
+        Some(subscription) => subscription,
+        None => return,
+    };
+    // deleted mail can't be found anymore, so only new mail is caught up on
+    if subscription.legacy {
+        send_message(socket, &ToClientMessage::NewMailAvailable).await;
+    } else if let Err(err) = send_missed_mail(socket, storage, subscription).await {
+        error!("error while sending missed mail: {err:?}");
+    }
+    state.active = true;
+}
+
+/// Sends all mail matching the subscription that was stored after the last
+/// mail the client knows about.
+async fn send_missed_mail(
+    socket: &mut WebSocket,
+    storage: &Storage,
+    subscription: &mut Subscription,
+) -> anyhow::Result<()> {
+    loop {
+        let messages = subscription.missed_mail(storage).await?;
+        if messages.is_empty() {
+            return Ok(());
         }
-        state.active = true;
+        for msg in &messages {
+            send_message(socket, msg).await;
+        }
+    }
+}
+
+pub(super) fn new_mail_message(mail: &StoredMail) -> ToClientMessage {
+    let summary = serialize_mail_item(mail).unwrap_or_else(|err| {
+        error!("error while serializing mail item: {err}");
+        Map::new()
+    });
+    ToClientMessage::NewMail {
+        id: mail.id,
+        mail: summary,
+    }
+}
+
+async fn send_message(socket: &mut WebSocket, msg: &ToClientMessage) {
+    let msg = serde_json::to_string(msg).expect("serialization error");
+    if let Err(error) = socket.send(Message::Text(msg)).await {
+        error!(error = debug(error), "socket send error");
     }
 }
 
-async fn on_recv_ws_message(_socket: &mut WebSocket, msg: Message, state: &mut SocketState) {
+async fn on_recv_ws_message(
+    socket: &mut WebSocket,
+    storage: &Storage,
+    msg: Message,
+    state: &mut SocketState,
+) {
     trace!(msg = debug(&msg), "received websocket message");
 
     let msg: FromClientMessage = match msg {
@@ -122,7 +200,39 @@ async fn on_recv_ws_message(_socket: &mut WebSocket, msg: Message, state: &mut S
     match msg {
         FromClientMessage::ListenForNewMail => {
             debug!("client is now listening for new mail");
-            state.listen_for_new_mail = true;
+            state.subscription = Some(Subscription {
+                filter: MailFilter::new(),
+                legacy: true,
+                last_sent: None,
+            });
+        }
+        FromClientMessage::Subscribe { to, subject, after } => {
+            debug!(
+                to = debug(&to),
+                subject = debug(&subject),
+                after = debug(after),
+                "client subscribed"
+            );
+            let mut filter = MailFilter::new();
+            if let Some(to) = to {
+                filter = filter.recipient(to);
+            }
+            if let Some(subject) = subject {
+                filter = filter.subject(subject);
+            }
+            let mut subscription = match Subscription::new(storage, filter, after).await {
+                Ok(subscription) => subscription,
+                Err(err) => {
+                    error!("error while subscribing: {err:?}");
+                    return;
+                }
+            };
+            if after.is_some() {
+                if let Err(err) = send_missed_mail(socket, storage, &mut subscription).await {
+                    error!("error while sending missed mail: {err:?}");
+                }
+            }
+            state.subscription = Some(subscription);
         }
         FromClientMessage::Heartbeat => trace!("received heartbeat message"),
     }
@@ -130,20 +240,220 @@ async fn on_recv_ws_message(_socket: &mut WebSocket, msg: Message, state: &mut S
 
 #[derive(Default)]
 pub struct SocketState {
-    listen_for_new_mail: bool,
+    subscription: Option<Subscription>,
     active: bool,
     closed: bool,
 }
 
+pub struct Subscription {
+    filter: MailFilter,
+    /// Only `NewMailAvailable` is sent for new mail, for clients that use
+    /// `ListenForNewMail`.
+    legacy: bool,
+    /// The newest mail the client knows about, either because it was sent to
+    /// it or because it was stored before the client subscribed.
+    last_sent: Option<MailId>,
+}
+
+impl Subscription {
+    /// Creates a subscription for mail stored after `after`, or after the
+    /// newest mail if the client does not resume.
+    async fn new(
+        storage: &Storage,
+        filter: MailFilter,
+        after: Option<MailId>,
+    ) -> anyhow::Result<Subscription> {
+        let last_sent = match after {
+            Some(after) => Some(after),
+            None => storage.mail().latest_mail_id().await?,
+        };
+        Ok(Subscription {
+            filter,
+            legacy: false,
+            last_sent,
+        })
+    }
+
+    /// Returns the message to send for a storage event, if any.
+    async fn message_for_event(
+        &mut self,
+        storage: &Storage,
+        event: StorageEvent,
+    ) -> anyhow::Result<Option<ToClientMessage>> {
+        let id = match event {
+            StorageEvent::NewMail(_) if self.legacy => {
+                return Ok(Some(ToClientMessage::NewMailAvailable))
+            }
+            StorageEvent::NewMail(id) => id,
+            // deleted mail can't be matched against the filter anymore
+            StorageEvent::DeletedMail(ids) => {
+                return Ok(Some(ToClientMessage::MailDeleted { ids }))
+            }
+        };
+        // the mail was already sent while catching up
+        if self.last_sent >= Some(id) {
+            return Ok(None);
+        }
+        let filter = self.filter.clone().id(id);
+        let mail = storage.mail().first_mail(&filter, 1).await?.pop();
+        Ok(mail.map(|mail| {
+            self.last_sent = Some(id);
+            new_mail_message(&mail)
+        }))
+    }
+
+    /// Returns the next page of mail matching the subscription that was stored
+    /// after the last mail the client knows about, empty once it caught up.
+    async fn missed_mail(&mut self, storage: &Storage) -> anyhow::Result<Vec<ToClientMessage>> {
+        let mut filter = self.filter.clone();
+        if let Some(last_sent) = self.last_sent {
+            filter = filter.after(last_sent);
+        }
+        let mail = storage.mail().first_mail(&filter, RESUME_PAGE_SIZE).await?;
+        if let Some(last) = mail.last() {
+            self.last_sent = Some(last.id);
+        }
+        Ok(mail.iter().map(new_mail_message).collect())
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(tag = "type")]
 pub enum FromClientMessage {
+    /// Subscribes to `NewMailAvailable` and `MailDeleted` messages.
     ListenForNewMail,
+    /// Subscribes to `NewMail` messages for mail matching the filters and to
+    /// `MailDeleted` messages. A reconnecting client can resume with the id of the
+    /// last mail it received in `after` to be sent all mail it missed.
+    Subscribe {
+        /// An envelope recipient.
+        to: Option<String>,
+        /// Text the subject has to contain.
+        subject: Option<String>,
+        after: Option<MailId>,
+    },
     Heartbeat,
 }
 
+/// There is no message for changed mail, as stored mail is never modified.
 #[derive(Serialize)]
 #[serde(tag = "type")]
 pub enum ToClientMessage {
     NewMailAvailable,
+    /// A new mail with the same summary as in the mail list.
+    NewMail {
+        id: MailId,
+        mail: Map<String, Value>,
+    },
+    MailDeleted {
+        ids: Vec<MailId>,
+    },
+}
+
+impl ToClientMessage {
+    /// The name of the message type, as in its `type` field.
+    pub fn event_name(&self) -> &'static str {
+        match self {
+            ToClientMessage::NewMailAvailable => "NewMailAvailable",
+            ToClientMessage::NewMail { .. } => "NewMail",
+            ToClientMessage::MailDeleted { .. } => "MailDeleted",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::test::{store, test_storage};
+
+    fn alice_code() -> MailFilter {
+        MailFilter::new()
+            .recipient("alice@example.test".to_owned())
+            .subject("code".to_owned())
+    }
+
+    fn new_mail_id(msg: &ToClientMessage) -> MailId {
+        match msg {
+            ToClientMessage::NewMail { id, .. } => *id,
+            _ => panic!("expected NewMail, got {}", msg.event_name()),
+        }
+    }
+
+    #[tokio::test]
+    async fn subscribe_to_matching_mail() {
+        let (storage, directory) = test_storage("listen");
+        store(&storage, "alice@example.test", "Your code").await;
+        let mut event_rx = storage.subscribe();
+        let mut subscription = Subscription::new(&storage, alice_code(), None)
+            .await
+            .unwrap();
+
+        // mail stored before subscribing is not sent
+        assert!(subscription.missed_mail(&storage).await.unwrap().is_empty());
+
+        store(&storage, "bob@example.test", "Your code").await;
+        store(&storage, "alice@example.test", "Welcome").await;
+        let matching = store(&storage, "ALICE@example.test", "Your CODE").await;
+        let deleted = store(&storage, "alice@example.test", "Your code").await;
+        storage.mail().delete_mail(deleted).await.unwrap();
+        let mut messages = Vec::new();
+        for _ in 0..5 {
+            let event = event_rx.recv().await.unwrap();
+            messages.extend(
+                subscription
+                    .message_for_event(&storage, event)
+                    .await
+                    .unwrap(),
+            );
+        }
+        assert_eq!(messages.len(), 2);
+        assert_eq!(new_mail_id(&messages[0]), matching);
+        assert!(matches!(&messages[1], ToClientMessage::MailDeleted { ids } if ids == &[deleted]));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resume_and_catch_up() {
+        let (storage, directory) = test_storage("listen-resume");
+        let first = store(&storage, "alice@example.test", "Your code").await;
+        store(&storage, "bob@example.test", "Your code").await;
+        let missed = store(&storage, "alice@example.test", "New code").await;
+
+        let mut subscription = Subscription::new(&storage, alice_code(), Some(first))
+            .await
+            .unwrap();
+        let messages = subscription.missed_mail(&storage).await.unwrap();
+        assert_eq!(
+            messages.iter().map(new_mail_id).collect::<Vec<_>>(),
+            [missed]
+        );
+        assert!(subscription.missed_mail(&storage).await.unwrap().is_empty());
+
+        // more mail than the event channel holds is caught up on after lagging
+        let mut event_rx = storage.subscribe();
+        let mut stored = Vec::new();
+        for _ in 0..(RESUME_PAGE_SIZE + 2) {
+            stored.push(store(&storage, "alice@example.test", "Your code").await);
+        }
+        assert!(matches!(event_rx.recv().await, Err(RecvError::Lagged(_))));
+        let mut sent = Vec::new();
+        loop {
+            let messages = subscription.missed_mail(&storage).await.unwrap();
+            if messages.is_empty() {
+                break;
+            }
+            sent.extend(messages.iter().map(new_mail_id));
+        }
+        assert_eq!(sent, stored);
+
+        // the events that were kept are not sent again
+        while let Ok(event) = event_rx.try_recv() {
+            let msg = subscription
+                .message_for_event(&storage, event)
+                .await
+                .unwrap();
+            assert!(msg.is_none());
+        }
+        std::fs::remove_dir_all(directory).unwrap();
+    }
 }
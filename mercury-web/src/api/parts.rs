@@ -0,0 +1,291 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::{
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use axum::{
+    body::StreamBody,
+    extract::Path,
+    http::{HeaderValue, StatusCode},
+    response::{AppendHeaders, IntoResponse},
+    Extension, Json,
+};
+use http::header;
+use mail::{header::CONTENT_ID, Decoder, Encoding, Entity};
+use serde_json::{Map, Number, Value};
+use storage::{mail::MailId, Storage};
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+use tokio_util::io::ReaderStream;
+use tracing::error;
+
+pub async fn part_list(
+    Path(mail_id): Path<MailId>,
+    storage: Extension<Storage>,
+) -> Result<Json<Value>, (StatusCode, &'static str)> {
+    let (_data, entity) = load_entity(&storage, mail_id).await?;
+
+    let mut parts = Vec::new();
+    collect_leaf_parts(&entity, None, &mut parts);
+    Ok(Json(Value::Array(parts)))
+}
+
+fn collect_leaf_parts(entity: &Entity, id: Option<&str>, parts: &mut Vec<Value>) {
+    match entity {
+        Entity::SinglePart(_) => {
+            let id = id.unwrap_or("1");
+            parts.push(Value::Object(serialize_part_info(entity, Some(id))));
+        }
+        Entity::MultiPart(multi) => {
+            for (index, child) in multi.parts.iter().enumerate() {
+                let child_id = Entity::child_part_id(id, index);
+                collect_leaf_parts(child, Some(&child_id), parts);
+            }
+        }
+    }
+}
+
+pub async fn part_content(
+    Path((mail_id, part_id)): Path<(MailId, String)>,
+    storage: Extension<Storage>,
+) -> Result<impl IntoResponse, (StatusCode, &'static str)> {
+    let (data, entity) = load_entity(&storage, mail_id).await?;
+    let part = entity
+        .part(&part_id)
+        .ok_or((StatusCode::NOT_FOUND, "part not found"))?;
+
+    let range = part.body();
+    let decoder = match part {
+        Entity::SinglePart(single) => single.decoder(),
+        // multipart bodies are never transfer encoded, so these are sent as they are
+        Entity::MultiPart(_) => Encoding::default().decoder(),
+    };
+    let content_type = content_type_header(part);
+    let content_disposition = content_disposition_header(part);
+
+    // the body is decoded while it is sent, so only the encoded message is kept
+    // in memory
+    let mut reader = std::io::Cursor::new(data);
+    reader.set_position(range.start as u64);
+    let reader = DecodingReader::new(reader.take(range.len() as u64), decoder);
+    let body = StreamBody::new(ReaderStream::new(reader));
+
+    let headers = AppendHeaders([
+        (header::CONTENT_TYPE, content_type),
+        (header::CONTENT_DISPOSITION, content_disposition),
+    ]);
+
+    Ok((headers, body))
+}
+
+/// Loads and parses a stored mail.
+pub(super) async fn load_entity(
+    storage: &Storage,
+    mail_id: MailId,
+) -> Result<(Vec<u8>, Entity), (StatusCode, &'static str)> {
+    let internal_error = |err: anyhow::Error| {
+        error!("error while loading mail: {err:?}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "error occurred while loading mail",
+        )
+    };
+
+    storage
+        .mail()
+        .get_mail_by_id(mail_id)
+        .await
+        .map_err(|err| internal_error(err.into()))?
+        .ok_or((StatusCode::NOT_FOUND, "mail not found"))?;
+    let data = storage
+        .mail()
+        .read_mail_data(mail_id)
+        .await
+        .map_err(|err| internal_error(err.into()))?;
+    let entity = Entity::parse(&data)
+        .map_err(|_| (StatusCode::UNPROCESSABLE_ENTITY, "mail could not be parsed"))?;
+
+    Ok((data, entity))
+}
+
+/// Serializes the metadata of a body part, without its content or child parts.
+pub(super) fn serialize_part_info(entity: &Entity, id: Option<&str>) -> Map<String, Value> {
+    let mut part = Map::<String, Value>::with_capacity(12);
+    part.insert("id".to_owned(), id.map(str::to_owned).into());
+
+    let content_type = entity.content_type();
+    part.insert(
+        "content_type".to_owned(),
+        Value::String(content_type.essence()),
+    );
+    part.insert(
+        "content_type_parameters".to_owned(),
+        content_type
+            .parameters()
+            .map(|(name, value)| (name.to_owned(), Value::String(value.to_owned())))
+            .collect::<Map<_, _>>()
+            .into(),
+    );
+
+    part.insert(
+        "disposition".to_owned(),
+        entity
+            .content_disposition()
+            .map(|cd| cd.disposition().to_owned())
+            .into(),
+    );
+    part.insert("filename".to_owned(), entity.filename().into());
+    part.insert(
+        "content_id".to_owned(),
+        entity
+            .header()
+            .get(CONTENT_ID)
+            .map(|cid| cid.trim_start_matches('<').trim_end_matches('>').to_owned())
+            .into(),
+    );
+    part.insert("size".to_owned(), Number::from(entity.body().len()).into());
+
+    if let Entity::SinglePart(single) = entity {
+        part.insert("encoding".to_owned(), single.encoding.as_str().into());
+    }
+
+    part
+}
+
+fn content_type_header(entity: &Entity) -> HeaderValue {
+    let content_type = entity.content_type();
+    let value = match content_type.charset() {
+        Some(charset) if content_type.mime_type() == "text" => {
+            format!("{}; charset={}", content_type.essence(), charset)
+        }
+        _ => content_type.essence(),
+    };
+    HeaderValue::from_str(&value)
+        .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream"))
+}
+
+/// Builds a Content-Disposition header with an ASCII fallback filename and the
+/// original filename as an RFC 5987 extended value.
+fn content_disposition_header(entity: &Entity) -> HeaderValue {
+    let disposition = match entity.content_disposition() {
+        Some(cd) if cd.disposition() == "inline" => "inline",
+        _ => "attachment",
+    };
+    let value = match entity.filename() {
+        Some(filename) => {
+            let fallback = filename
+                .chars()
+                .map(|ch| match ch {
+                    ' '..='~' if ch != '"' && ch != '\\' => ch,
+                    _ => '_',
+                })
+                .collect::<String>();
+            format!(
+                "{disposition}; filename=\"{fallback}\"; filename*=UTF-8''{}",
+                percent_encode(&filename)
+            )
+        }
+        None => disposition.to_owned(),
+    };
+    HeaderValue::from_str(&value).unwrap_or_else(|_| HeaderValue::from_static("attachment"))
+}
+
+/// Percent-encodes everything except RFC 5987 `attr-char`s.
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'a'..=b'z'
+            | b'A'..=b'Z'
+            | b'0'..=b'9'
+            | b'!'
+            | b'#'
+            | b'$'
+            | b'&'
+            | b'+'
+            | b'-'
+            | b'.'
+            | b'^'
+            | b'_'
+            | b'`'
+            | b'|'
+            | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Decodes the Content-Transfer-Encoding of everything read from `inner`.
+struct DecodingReader<R> {
+    inner: R,
+    decoder: Option<Decoder>,
+    decoded: Vec<u8>,
+    position: usize,
+}
+
+impl<R> DecodingReader<R> {
+    fn new(inner: R, decoder: Decoder) -> Self {
+        DecodingReader {
+            inner,
+            decoder: Some(decoder),
+            decoded: Vec::new(),
+            position: 0,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for DecodingReader<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.position < this.decoded.len() {
+                let count = buf.remaining().min(this.decoded.len() - this.position);
+                buf.put_slice(&this.decoded[this.position..(this.position + count)]);
+                this.position += count;
+                return Poll::Ready(Ok(()));
+            }
+
+            this.decoded.clear();
+            this.position = 0;
+            if this.decoder.is_none() {
+                return Poll::Ready(Ok(()));
+            }
+
+            let mut chunk = [0u8; 8192];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk))?;
+            if chunk.filled().is_empty() {
+                if let Some(decoder) = this.decoder.take() {
+                    decoder.finish(&mut this.decoded);
+                }
+            } else if let Some(decoder) = &mut this.decoder {
+                decoder.decode(chunk.filled(), &mut this.decoded);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn decoding_reader() {
+        let encoded = b"SGVsbG8g\r\nd29ybGQh\r\n".repeat(2000);
+        let mut reader = DecodingReader::new(&encoded[..], Encoding::Base64.decoder());
+        let mut decoded = Vec::new();
+        reader.read_to_end(&mut decoded).await.unwrap();
+        assert_eq!(decoded, b"Hello world!".repeat(2000));
+    }
+
+    #[test]
+    fn encode_filename() {
+        assert_eq!(percent_encode("€ rates.pdf"), "%E2%82%AC%20rates.pdf");
+    }
+}
@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use std::time::Duration;
+
+use axum::{extract::Query, http::StatusCode, Extension, Json};
+use serde::Deserialize;
+use serde_json::Value;
+use storage::{
+    mail::{MailFilter, MailId, StoredMail},
+    Storage, StorageEvent,
+};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, error};
+
+use super::serialize_mail_item;
+
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const MAX_TIMEOUT_SECS: u64 = 300;
+
+#[derive(Deserialize)]
+pub struct WaitQuery {
+    /// An envelope recipient.
+    to: Option<String>,
+    /// Text the subject has to contain.
+    subject: Option<String>,
+    /// The maximum time to wait in seconds.
+    timeout: Option<u64>,
+    /// Mail stored after this id matches as well, even if it arrived before the
+    /// request.
+    after: Option<MailId>,
+}
+
+/// Waits for a mail matching the query and responds with it, or with `408 Request
+/// Timeout` if none arrives in time.
+pub async fn wait_for_mail(
+    Query(params): Query<WaitQuery>,
+    storage: Extension<Storage>,
+) -> Result<Json<Value>, (StatusCode, &'static str)> {
+    let internal_error = |err: anyhow::Error| {
+        error!("error while waiting for mail: {err:?}");
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "error occurred while waiting for mail",
+        )
+    };
+
+    let mut filter = MailFilter::new();
+    if let Some(to) = &params.to {
+        filter = filter.recipient(to);
+    }
+    if let Some(subject) = &params.subject {
+        filter = filter.subject(subject);
+    }
+    let timeout = params
+        .timeout
+        .unwrap_or(DEFAULT_TIMEOUT_SECS)
+        .min(MAX_TIMEOUT_SECS);
+
+    // subscribe before looking at the stored mail, so no mail is missed in between
+    let mut event_rx = storage.subscribe();
+    let after = match params.after {
+        Some(after) => Some(after),
+        None => storage
+            .mail()
+            .latest_mail_id()
+            .await
+            .map_err(|err| internal_error(err.into()))?,
+    };
+    if let Some(after) = after {
+        filter = filter.after(after);
+    }
+
+    let wait = async {
+        loop {
+            if let Some(mail) = first_mail(&storage, &filter).await? {
+                return Ok(Some(mail));
+            }
+            loop {
+                match event_rx.recv().await {
+                    Ok(StorageEvent::NewMail(_)) | Err(RecvError::Lagged(_)) => break,
+                    Ok(_) => continue,
+                    Err(RecvError::Closed) => return Ok(None),
+                }
+            }
+        }
+    };
+    let mail = match tokio::time::timeout(Duration::from_secs(timeout), wait).await {
+        Ok(result) => result.map_err(internal_error)?,
+        Err(_) => {
+            debug!(timeout, "no matching mail arrived in time");
+            return Err((StatusCode::REQUEST_TIMEOUT, "no matching mail arrived"));
+        }
+    };
+    let mail = mail.ok_or((StatusCode::SERVICE_UNAVAILABLE, "storage is shutting down"))?;
+
+    let item = serialize_mail_item(&mail).map_err(|err| internal_error(anyhow::anyhow!(err)))?;
+    Ok(Json(Value::Object(item)))
+}
+
+async fn first_mail(storage: &Storage, filter: &MailFilter) -> anyhow::Result<Option<StoredMail>> {
+    Ok(storage.mail().first_mail(filter, 1).await?.pop())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::api::test::{store, test_storage};
+
+    fn query(after: Option<MailId>, timeout: u64) -> Query<WaitQuery> {
+        Query(WaitQuery {
+            to: Some("alice@example.test".to_owned()),
+            subject: Some("code".to_owned()),
+            timeout: Some(timeout),
+            after,
+        })
+    }
+
+    #[tokio::test]
+    async fn wait_for_matching_mail() {
+        let (storage, directory) = test_storage("wait");
+        let first = store(&storage, "alice@example.test", "Your code").await;
+
+        // mail stored after the given id matches even if it is already stored
+        let Json(mail) = wait_for_mail(query(Some(MailId::from(0)), 5), Extension(storage.clone()))
+            .await
+            .unwrap();
+        assert_eq!(mail["id"], i64::from(first));
+
+        let wait = tokio::spawn(wait_for_mail(
+            query(Some(first), 5),
+            Extension(storage.clone()),
+        ));
+        store(&storage, "bob@example.test", "Your code").await;
+        store(&storage, "alice@example.test", "Welcome").await;
+        let matching = store(&storage, "ALICE@example.test", "Your CODE").await;
+        let Json(mail) = wait.await.unwrap().unwrap();
+        assert_eq!(mail["id"], i64::from(matching));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn time_out_without_new_mail() {
+        let (storage, directory) = test_storage("wait-timeout");
+        // only mail arriving during the request matches by default
+        store(&storage, "alice@example.test", "Your code").await;
+        let result = wait_for_mail(query(None, 1), Extension(storage)).await;
+        assert_eq!(result.unwrap_err().0, StatusCode::REQUEST_TIMEOUT);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
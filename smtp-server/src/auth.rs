@@ -0,0 +1,244 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! SMTP authentication (RFC 4954) with the PLAIN, LOGIN and CRAM-MD5 SASL
+//! mechanisms.
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use hmac::{Hmac, Mac};
+use md5::Md5;
+
+use crate::session::reply::Code;
+
+/// The SASL mechanisms offered when authentication is enabled.
+pub(crate) const MECHANISMS: [&str; 3] = ["PLAIN", "LOGIN", "CRAM-MD5"];
+
+/// The credentials accepted by the server.
+#[derive(Debug, Clone)]
+pub enum Credentials {
+    /// Any username and password is accepted.
+    Any,
+    /// Only the given usernames with their passwords are accepted.
+    Users(HashMap<String, String>),
+}
+
+impl Credentials {
+    fn check(&self, username: &str, password: &str) -> bool {
+        match self {
+            Credentials::Any => true,
+            Credentials::Users(users) => users.get(username).is_some_and(|p| p == password),
+        }
+    }
+
+    /// Checks a CRAM-MD5 response, the hex encoded HMAC-MD5 of the challenge
+    /// keyed with the password.
+    fn check_digest(&self, username: &str, challenge: &str, digest: &str) -> bool {
+        let password = match self {
+            Credentials::Any => return true,
+            Credentials::Users(users) => match users.get(username) {
+                Some(password) => password,
+                None => return false,
+            },
+        };
+        let mut mac = Hmac::<Md5>::new_from_slice(password.as_bytes())
+            .expect("HMAC accepts keys of any length");
+        mac.update(challenge.as_bytes());
+        let expected = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        expected.eq_ignore_ascii_case(digest)
+    }
+}
+
+/// The state of an authentication exchange that waits for a client response.
+pub(crate) enum Exchange {
+    Plain,
+    LoginUsername,
+    LoginPassword { username: String },
+    CramMd5 { challenge: String },
+}
+
+/// What happens after a step of an authentication exchange.
+pub(crate) enum Step {
+    /// The exchange continues, the challenge is sent base64 encoded in a `334`
+    /// reply.
+    Challenge(Exchange, Vec<u8>),
+    /// The client is authenticated as the given user.
+    Success(String),
+    Failure(Code),
+}
+
+impl Exchange {
+    /// Starts an exchange for the AUTH command.
+    pub(crate) fn start(
+        mechanism: &str,
+        initial_response: Option<&str>,
+        credentials: &Credentials,
+        hostname: &str,
+    ) -> Step {
+        let exchange = match mechanism {
+            "PLAIN" => Exchange::Plain,
+            "LOGIN" => Exchange::LoginUsername,
+            // the server has to send its challenge first
+            "CRAM-MD5" if initial_response.is_some() => return Step::Failure(Code::BAD_PARAMETER),
+            "CRAM-MD5" => Exchange::CramMd5 {
+                challenge: new_challenge(hostname),
+            },
+            _ => return Step::Failure(Code::PARAMETER_NOT_IMPLEMENTED),
+        };
+        match initial_response {
+            Some(response) => exchange.respond(response, credentials),
+            None => exchange.challenge(),
+        }
+    }
+
+    fn challenge(self) -> Step {
+        let challenge = match &self {
+            Exchange::Plain => Vec::new(),
+            Exchange::LoginUsername => b"Username:".to_vec(),
+            Exchange::LoginPassword { .. } => b"Password:".to_vec(),
+            Exchange::CramMd5 { challenge } => challenge.as_bytes().to_vec(),
+        };
+        Step::Challenge(self, challenge)
+    }
+
+    /// Continues the exchange with a base64 encoded client response.
+    pub(crate) fn respond(self, response: &str, credentials: &Credentials) -> Step {
+        let response = match response {
+            // the client cancelled the exchange
+            "*" => return Step::Failure(Code::BAD_PARAMETER),
+            // an empty initial response
+            "=" => Vec::new(),
+            response => match STANDARD.decode(response) {
+                Ok(response) => response,
+                Err(_) => return Step::Failure(Code::BAD_PARAMETER),
+            },
+        };
+        let response = String::from_utf8_lossy(&response);
+
+        match self {
+            Exchange::Plain => {
+                // [authzid] NUL authcid NUL passwd
+                let mut fields = response.splitn(3, '\0');
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some(_authzid), Some(username), Some(password)) => {
+                        authenticate(credentials.check(username, password), username)
+                    }
+                    _ => Step::Failure(Code::BAD_PARAMETER),
+                }
+            }
+            Exchange::LoginUsername => Exchange::LoginPassword {
+                username: response.into_owned(),
+            }
+            .challenge(),
+            Exchange::LoginPassword { username } => {
+                authenticate(credentials.check(&username, &response), &username)
+            }
+            Exchange::CramMd5 { challenge } => match response.rsplit_once(' ') {
+                Some((username, digest)) => authenticate(
+                    credentials.check_digest(username, &challenge, digest),
+                    username,
+                ),
+                None => Step::Failure(Code::BAD_PARAMETER),
+            },
+        }
+    }
+}
+
+fn authenticate(valid: bool, username: &str) -> Step {
+    match valid {
+        true => Step::Success(username.to_owned()),
+        false => Step::Failure(Code::AUTH_CREDENTIALS_INVALID),
+    }
+}
+
+/// Creates a unique CRAM-MD5 challenge in the form of a message id.
+fn new_challenge(hostname: &str) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_micros())
+        .unwrap_or_default();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("<{}.{timestamp}.{count}@{hostname}>", std::process::id())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn users() -> Credentials {
+        Credentials::Users([("alice".to_owned(), "secret".to_owned())].into())
+    }
+
+    fn username(step: Step) -> Result<String, Code> {
+        match step {
+            Step::Success(username) => Ok(username),
+            Step::Failure(code) => Err(code),
+            Step::Challenge(..) => panic!("unexpected challenge"),
+        }
+    }
+
+    #[test]
+    fn plain() {
+        let response = STANDARD.encode("\0alice\0secret");
+        let step = Exchange::start("PLAIN", Some(&response), &users(), "mercury.test");
+        assert_eq!(username(step), Ok("alice".to_owned()));
+
+        let response = STANDARD.encode("\0alice\0wrong");
+        let step = Exchange::start("PLAIN", Some(&response), &users(), "mercury.test");
+        assert_eq!(username(step), Err(Code::AUTH_CREDENTIALS_INVALID));
+
+        let step = Exchange::start(
+            "PLAIN",
+            Some("not base64!"),
+            &Credentials::Any,
+            "mercury.test",
+        );
+        assert_eq!(username(step), Err(Code::BAD_PARAMETER));
+    }
+
+    #[test]
+    fn login() {
+        let step = Exchange::start("LOGIN", None, &users(), "mercury.test");
+        let Step::Challenge(exchange, challenge) = step else {
+            panic!("expected challenge")
+        };
+        assert_eq!(challenge, b"Username:");
+        let Step::Challenge(exchange, challenge) = exchange.respond("YWxpY2U=", &users()) else {
+            panic!("expected challenge")
+        };
+        assert_eq!(challenge, b"Password:");
+        let step = exchange.respond(&STANDARD.encode("secret"), &users());
+        assert_eq!(username(step), Ok("alice".to_owned()));
+    }
+
+    #[test]
+    fn cram_md5() {
+        // example from RFC 2195
+        let credentials =
+            Credentials::Users([("tim".to_owned(), "tanstaaftanstaaf".to_owned())].into());
+        let exchange = Exchange::CramMd5 {
+            challenge: "<1896.697170952@postoffice.reston.mci.net>".to_owned(),
+        };
+        let step = exchange.respond(
+            "dGltIGI5MTNhNjAyYzdlZGE3YTQ5NWI0ZTZlNzMzNGQzODkw",
+            &credentials,
+        );
+        assert_eq!(username(step), Ok("tim".to_owned()));
+    }
+
+    #[test]
+    fn cancel() {
+        let step = Exchange::LoginUsername.respond("*", &Credentials::Any);
+        assert_eq!(username(step), Err(Code::BAD_PARAMETER));
+    }
+}
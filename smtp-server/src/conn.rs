@@ -2,48 +2,123 @@
 
 use std::time::Duration;
 
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt as _, BufStream};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt as _, AsyncWriteExt as _, BufReader, BufWriter};
 use tokio::net::TcpStream;
 use tracing::debug;
 
 use crate::error::Result;
 use crate::session::reply::Reply;
+use crate::tls::Stream;
 use crate::{Error, Session};
 
+/// The maximum number of bytes of a BDAT chunk that is read at once.
+const CHUNK_READ_SIZE: usize = 64 * 1024;
+
 pub struct Connection {
-    stream: BufStream<TcpStream>,
+    stream: BufReader<BufWriter<Stream>>,
     session: Session,
     read_timeout: Duration,
     write_timeout: Duration,
+    implicit_tls: bool,
 }
 
 impl Connection {
     pub fn new(stream: TcpStream, session: Session) -> Self {
         Connection {
-            stream: BufStream::new(stream),
+            stream: BufReader::new(BufWriter::new(Stream::Plain(stream))),
             session,
             read_timeout: Duration::from_secs(5),
             write_timeout: Duration::from_secs(5),
+            implicit_tls: false,
+        }
+    }
+
+    /// Creates a connection that starts with the TLS handshake, before the
+    /// greeting is sent.
+    pub fn new_implicit_tls(stream: TcpStream, session: Session) -> Self {
+        Connection {
+            implicit_tls: true,
+            ..Self::new(stream, session)
         }
     }
 
     pub async fn run(mut self) -> Result<()> {
+        if self.implicit_tls {
+            self = self.start_tls().await?;
+        }
+
         let mut reply = Reply::default();
 
         loop {
             self.session.on_recv(&mut reply);
-            reply.finish();
-            self.write_reply(&reply).await?;
+            // lines of the mail data are not answered individually
+            if !reply.is_empty() {
+                reply.finish();
+                self.write_reply(&reply).await?;
+            }
             if self.session.closed() {
+                self.flush().await?;
+                break;
+            }
+            if self.session.starttls_pending() {
+                self.flush().await?;
+                self = self.start_tls().await?;
+            }
+            // with pipelining (RFC 2920) the replies to a group of commands
+            // are sent together once all buffered commands are processed
+            if self.stream.buffer().is_empty() {
+                self.flush().await?;
+            }
+            let received = match self.session.chunk_remaining() {
+                Some(remaining) => self.read_chunk(remaining).await?,
+                None => self.read_line().await?,
+            };
+            if !received {
+                debug!("connection closed by client");
                 break;
             }
-            self.read_line().await?;
             reply.clear();
         }
 
         Ok(())
     }
 
+    async fn start_tls(self) -> Result<Self> {
+        let Connection {
+            stream,
+            mut session,
+            read_timeout,
+            write_timeout,
+            implicit_tls,
+        } = self;
+        let config = session
+            .tls_config()
+            .cloned()
+            .expect("TLS must be configured to start TLS");
+
+        // anything the client sent before the handshake is discarded with the
+        // buffer, so it can't be injected into the encrypted session
+        let stream = stream.into_inner().into_inner();
+        let (stream, info) = tokio::time::timeout(read_timeout, stream.accept(&config))
+            .await
+            .map_err(|_| Error::ReadTimeout)??;
+        debug!(
+            protocol = display(&info.protocol),
+            cipher = display(&info.cipher),
+            "TLS established"
+        );
+        session.on_tls(info);
+
+        Ok(Connection {
+            stream: BufReader::new(BufWriter::new(stream)),
+            session,
+            read_timeout,
+            write_timeout,
+            implicit_tls,
+        })
+    }
+
+    /// Writes a reply into the send buffer.
     async fn write_reply(&mut self, reply: &Reply) -> Result<()> {
         debug!(
             count = display(reply.data().len()),
@@ -51,46 +126,316 @@ impl Connection {
             "sending",
         );
 
-        tokio::time::timeout(self.write_timeout, async move {
-            self.stream.get_mut().writable().await?;
-            self.stream.write_all(reply.data()).await?;
-            self.stream.flush().await?;
-            Result::<()>::Ok(())
+        tokio::time::timeout(self.write_timeout, self.stream.write_all(reply.data()))
+            .await
+            .map_err(|_| Error::WriteTimeout)??;
+
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        tokio::time::timeout(self.write_timeout, self.stream.flush())
+            .await
+            .map_err(|_| Error::WriteTimeout)??;
+
+        Ok(())
+    }
+
+    /// Reads a line up to LF into the session buffer. Returns `false` once the
+    /// client closed the connection.
+    async fn read_line(&mut self) -> Result<bool> {
+        let limit = self.session.max_line_length();
+        let buffer = self.session.buffer_mut();
+
+        let count = tokio::time::timeout(self.read_timeout, {
+            (&mut self.stream)
+                .take(limit as u64)
+                .read_until(b'\n', buffer)
         })
         .await
-        .map_err(|_| Error::WriteTimeout)??;
+        .map_err(|_| Error::ReadTimeout)??;
 
-        Ok(())
+        debug!(
+            count = display(count),
+            bytes = debug(String::from_utf8_lossy(&buffer[(buffer.len() - count)..])),
+            "received"
+        );
+
+        if count == limit && !buffer.ends_with(b"\n") {
+            buffer.clear();
+            self.discard_line().await?;
+            self.session.line_too_long();
+        }
+        Ok(count > 0)
     }
 
-    async fn read_line(&mut self) -> Result<()> {
-        let terminator = self.session.terminator();
-        let terminator_end = *terminator
-            .last()
-            .expect("terminator must be at least 1 byte in length");
+    /// Reads the next part of a BDAT chunk into the session buffer, at most
+    /// `remaining` bytes. Returns `false` once the client closed the connection.
+    async fn read_chunk(&mut self, remaining: usize) -> Result<bool> {
+        let limit = remaining.min(CHUNK_READ_SIZE);
         let buffer = self.session.buffer_mut();
 
-        tokio::time::timeout(self.read_timeout, self.stream.get_mut().readable())
-            .await
-            .map_err(|_| Error::ReadTimeout)??;
+        let count = tokio::time::timeout(self.read_timeout, {
+            (&mut self.stream).take(limit as u64).read_to_end(buffer)
+        })
+        .await
+        .map_err(|_| Error::ReadTimeout)??;
 
+        debug!(count = display(count), "received chunk data");
+        Ok(count == limit)
+    }
+
+    /// Skips the rest of an overlong line.
+    async fn discard_line(&mut self) -> Result<()> {
         loop {
-            let count = tokio::time::timeout(self.read_timeout, {
-                self.stream.read_until(terminator_end, buffer)
-            })
+            let buffer = tokio::time::timeout(self.read_timeout, self.stream.fill_buf())
+                .await
+                .map_err(|_| Error::ReadTimeout)??;
+            if buffer.is_empty() {
+                return Ok(());
+            }
+            match buffer.iter().position(|&ch| ch == b'\n') {
+                Some(end) => {
+                    self.stream.consume(end + 1);
+                    return Ok(());
+                }
+                None => {
+                    let len = buffer.len();
+                    self.stream.consume(len);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+
+    use tokio::{io::AsyncReadExt as _, net::TcpListener};
+
+    use super::*;
+    use crate::{
+        extension::{BinaryMime, Chunking},
+        BareLineEndings, RawMail, Server, ServerBuilder,
+    };
+
+    /// Sends `input` over a connection with a server built by `builder`, and
+    /// returns everything the server replied and the received mail.
+    async fn exchange(builder: ServerBuilder, input: &[u8]) -> (String, Vec<RawMail>) {
+        let (mail_tx, mail_rx) = mpsc::channel();
+        let server = builder
+            .on_new_mail(move |mail| mail_tx.send(mail).unwrap())
+            .build()
+            .unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
             .await
-            .map_err(|_| Error::ReadTimeout)??;
+            .unwrap();
+        let (stream, addr) = listener.accept().await.unwrap();
+        let conn = Connection::new(stream, Session::new(server.config.clone(), addr));
+        let conn = tokio::spawn(conn.run());
 
-            debug!(
-                count = display(count),
-                bytes = debug(String::from_utf8_lossy(&buffer[(buffer.len() - count)..])),
-                "received"
-            );
+        client.write_all(input).await.unwrap();
+        let mut output = String::new();
+        client.read_to_string(&mut output).await.unwrap();
+        conn.await.unwrap().unwrap();
 
-            if count == 0 || buffer.ends_with(terminator) {
-                break;
-            }
-        }
-        Ok(())
+        (output, mail_rx.try_iter().collect())
+    }
+
+    fn mail_commands(data: &str) -> Vec<u8> {
+        format!(
+            "EHLO client.test\r\n\
+             MAIL FROM:<sender@example.test>\r\n\
+             RCPT TO:<rcpt@example.test>\r\n\
+             DATA\r\n\
+             {data}\
+             QUIT\r\n"
+        )
+        .into_bytes()
+    }
+
+    fn last_replies(output: &str, count: usize) -> Vec<&str> {
+        let lines = output.lines().collect::<Vec<_>>();
+        lines[lines.len() - count..].to_vec()
+    }
+
+    #[tokio::test]
+    async fn dot_unstuffing() {
+        let input = mail_commands("Subject: dots\r\n\r\n..\r\n..leading dot\r\n...\r\n.\r\n");
+        let (output, mail) = exchange(Server::builder(), &input).await;
+        assert_eq!(
+            last_replies(&output, 2)[0],
+            "250 requested mail action okay"
+        );
+        assert_eq!(
+            mail[0].data,
+            b"Subject: dots\r\n\r\n.\r\n.leading dot\r\n..\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn normalize_bare_line_endings() {
+        let input = b"EHLO client.test\n\
+            MAIL FROM:<sender@example.test>\n\
+            RCPT TO:<rcpt@example.test>\r\n\
+            DATA\n\
+            Subject: bare\n\nbare LF\nbare\rCR\r\n.\n\
+            QUIT\n";
+        let (output, mail) = exchange(Server::builder(), input).await;
+        assert_eq!(
+            last_replies(&output, 3),
+            [
+                "354 start mail input",
+                "250 requested mail action okay",
+                "221 service closing transmission channel"
+            ]
+        );
+        assert_eq!(
+            mail[0].data,
+            b"Subject: bare\r\n\r\nbare LF\r\nbare\r\nCR\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn reject_bare_line_endings() {
+        let builder = || Server::builder().bare_line_endings(BareLineEndings::Reject);
+        let (output, mail) = exchange(builder(), b"NOOP\nQUIT\r\n").await;
+        assert!(
+            output.contains("\r\n500 bare CR or LF not allowed\r\n"),
+            "{output}"
+        );
+        assert!(mail.is_empty());
+
+        let input = mail_commands("Subject: bare\r\n\r\nbare LF\n.\r\n");
+        let (output, mail) = exchange(builder(), &input).await;
+        assert_eq!(
+            last_replies(&output, 2)[0],
+            "554 message contains bare CR or LF"
+        );
+        assert!(mail.is_empty());
+    }
+
+    #[tokio::test]
+    async fn line_too_long() {
+        let input = format!("NOOP {}\r\nQUIT\r\n", "x".repeat(600));
+        let (output, _) = exchange(Server::builder(), input.as_bytes()).await;
+        assert_eq!(
+            last_replies(&output, 2),
+            [
+                "500 line too long",
+                "221 service closing transmission channel"
+            ]
+        );
+
+        let line = "x".repeat(998);
+        let input = mail_commands(&format!("{line}\r\n.\r\n"));
+        let (_, mail) = exchange(Server::builder(), &input).await;
+        assert_eq!(mail[0].data, format!("{line}\r\n").as_bytes());
+
+        let input = mail_commands(&format!("{line}x\r\n.\r\n"));
+        let (output, mail) = exchange(Server::builder(), &input).await;
+        assert_eq!(last_replies(&output, 2)[0], "500 line too long");
+        assert!(mail.is_empty());
+
+        let builder = Server::builder().max_line_length(80);
+        let input = mail_commands(&format!("{}\r\n.\r\n", "x".repeat(79)));
+        let (output, mail) = exchange(builder, &input).await;
+        assert_eq!(last_replies(&output, 2)[0], "500 line too long");
+        assert!(mail.is_empty());
+    }
+
+    #[tokio::test]
+    async fn message_too_large() {
+        let body = "x".repeat(78) + "\r\n";
+        let input = mail_commands(&format!("{}.\r\nNOOP\r\n", body.repeat(1000)));
+        let builder = Server::builder().max_message_size(10_000);
+        let (output, mail) = exchange(builder, &input).await;
+        assert_eq!(
+            last_replies(&output, 3),
+            [
+                "552 message exceeds the maximum message size",
+                "250 requested mail action okay",
+                "221 service closing transmission channel"
+            ]
+        );
+        assert!(mail.is_empty());
+    }
+
+    #[tokio::test]
+    async fn bdat() {
+        let data = b"Subject: binary\r\n\r\n\0\xff\n.\r\n.\r\n\r\nend";
+        let (first, second) = data.split_at(20);
+        let mut input = b"EHLO client.test\r\n\
+            MAIL FROM:<sender@example.test> BODY=BINARYMIME\r\n\
+            RCPT TO:<rcpt@example.test>\r\n\
+            BDAT 20\r\n"
+            .to_vec();
+        input.extend(first);
+        input.extend(format!("BDAT {} LAST\r\n", second.len()).as_bytes());
+        input.extend(second);
+        input.extend(b"QUIT\r\n");
+
+        let builder = Server::builder().extension(Chunking).extension(BinaryMime);
+        let (output, mail) = exchange(builder, &input).await;
+        assert_eq!(
+            last_replies(&output, 3),
+            [
+                "250 20 octets received",
+                "250 requested mail action okay",
+                "221 service closing transmission channel"
+            ]
+        );
+        assert_eq!(mail[0].data, data);
+    }
+
+    #[tokio::test]
+    async fn bdat_too_large() {
+        let chunk = vec![b'x'; 200_000];
+        let mut input = b"EHLO client.test\r\n\
+            MAIL FROM:<sender@example.test>\r\n\
+            RCPT TO:<rcpt@example.test>\r\n\
+            BDAT 200000\r\n"
+            .to_vec();
+        input.extend(&chunk);
+        input.extend(b"BDAT 200000 LAST\r\n");
+        input.extend(&chunk);
+        input.extend(b"NOOP\r\nQUIT\r\n");
+
+        let builder = Server::builder()
+            .extension(Chunking)
+            .max_message_size(100_000);
+        let (output, mail) = exchange(builder, &input).await;
+        assert_eq!(
+            last_replies(&output, 4),
+            [
+                "552 message exceeds the maximum message size",
+                "503 no mail transaction",
+                "250 requested mail action okay",
+                "221 service closing transmission channel"
+            ]
+        );
+        assert!(mail.is_empty());
+    }
+
+    #[tokio::test]
+    async fn invalid_utf8_commands() {
+        let input = b"EHLO client.test\r\n\
+            MAIL FROM:<j\xf6hn@example.test>\r\n\
+            VRFY \"\xff\"\r\n\
+            NOOP \xc3\r\n\
+            QUIT\r\n";
+        let (output, _) = exchange(Server::builder(), input).await;
+        assert_eq!(
+            last_replies(&output, 4),
+            [
+                "501 syntax error in parameters or arguments",
+                "501 syntax error in parameters or arguments",
+                "500 syntax error, command unrecognized",
+                "221 service closing transmission channel"
+            ]
+        );
     }
 }
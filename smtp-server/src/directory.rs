@@ -0,0 +1,177 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! Known mailboxes and mailing lists for the VRFY and EXPN commands (RFC 5321
+//! §3.5).
+
+use std::collections::HashMap;
+
+use crate::session::reply::{Code, Reply};
+
+#[derive(Debug, Clone)]
+pub struct Mailbox {
+    /// The full name of the user, if known.
+    pub name: Option<String>,
+    pub address: String,
+}
+
+impl Mailbox {
+    fn matches(&self, query: &str) -> bool {
+        let local_part = self
+            .address
+            .rsplit_once('@')
+            .map_or(&self.address[..], |(local_part, _)| local_part);
+        self.address.eq_ignore_ascii_case(query)
+            || local_part.eq_ignore_ascii_case(query)
+            || self
+                .name
+                .as_ref()
+                .is_some_and(|name| name.to_lowercase().contains(&query.to_lowercase()))
+    }
+
+    fn reply_line(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{} <{}>", name, self.address),
+            None => format!("<{}>", self.address),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Directory {
+    mailboxes: Vec<Mailbox>,
+    /// Members of the mailing lists by lowercase list name.
+    lists: HashMap<String, Vec<String>>,
+}
+
+impl Directory {
+    pub(crate) fn add_mailbox(&mut self, mailbox: Mailbox) {
+        self.mailboxes.push(mailbox);
+    }
+
+    pub(crate) fn add_list(&mut self, name: &str, members: Vec<String>) {
+        self.lists.insert(name.to_lowercase(), members);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.mailboxes.is_empty() && self.lists.is_empty()
+    }
+
+    /// Answers a VRFY command. Without any known mailboxes the server can't
+    /// verify users, but accepts mail for anyone.
+    pub(crate) fn verify(&self, query: &str, reply: &mut Reply) {
+        if self.is_empty() {
+            reply.code(Code::CANNOT_VRFY_ACCEPT);
+            return;
+        }
+
+        let matches = self
+            .mailboxes
+            .iter()
+            .filter(|mailbox| mailbox.matches(query))
+            .collect::<Vec<_>>();
+        match &matches[..] {
+            [] => {
+                reply.code(Code::MAILBOX_UNAVAILABLE);
+                reply.line("user unknown");
+            }
+            [mailbox] => {
+                reply.code(Code::MAIL_ACTION_OKAY);
+                reply.line(mailbox.reply_line());
+            }
+            ambiguous => {
+                reply.code(Code::MAILBOX_NAME_NOT_ALLOWED);
+                reply.line("user ambiguous, possibilities are");
+                for mailbox in ambiguous {
+                    reply.line(mailbox.reply_line());
+                }
+            }
+        }
+    }
+
+    /// Answers an EXPN command with the members of a mailing list.
+    pub(crate) fn expand(&self, query: &str, reply: &mut Reply) {
+        if self.is_empty() {
+            reply.code(Code::CANNOT_VRFY_ACCEPT);
+            return;
+        }
+
+        match self.lists.get(&query.to_lowercase()) {
+            Some(members) if !members.is_empty() => {
+                reply.code(Code::MAIL_ACTION_OKAY);
+                for member in members {
+                    let known = self
+                        .mailboxes
+                        .iter()
+                        .find(|mailbox| mailbox.address.eq_ignore_ascii_case(member));
+                    match known {
+                        Some(mailbox) => reply.line(mailbox.reply_line()),
+                        None => reply.line(format!("<{member}>")),
+                    }
+                }
+            }
+            _ => {
+                reply.code(Code::MAILBOX_UNAVAILABLE);
+                reply.line("mailing list unknown");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn directory() -> Directory {
+        let mut directory = Directory::default();
+        directory.add_mailbox(Mailbox {
+            name: Some("Jane Smith".to_owned()),
+            address: "jane@example.test".to_owned(),
+        });
+        directory.add_mailbox(Mailbox {
+            name: Some("John Smith".to_owned()),
+            address: "john@example.test".to_owned(),
+        });
+        directory.add_list(
+            "Team",
+            vec![
+                "jane@example.test".to_owned(),
+                "bob@example.test".to_owned(),
+            ],
+        );
+        directory
+    }
+
+    fn reply(f: impl FnOnce(&mut Reply)) -> String {
+        let mut reply = Reply::default();
+        f(&mut reply);
+        reply.finish();
+        String::from_utf8(reply.data().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn verify() {
+        let directory = directory();
+        assert_eq!(
+            reply(|r| directory.verify("JANE", r)),
+            "250 Jane Smith <jane@example.test>\r\n"
+        );
+        assert_eq!(
+            reply(|r| directory.verify("smith", r)),
+            "553-user ambiguous, possibilities are\r\n\
+             553-Jane Smith <jane@example.test>\r\n\
+             553 John Smith <john@example.test>\r\n"
+        );
+        assert!(reply(|r| directory.verify("bob", r)).starts_with("550 "));
+        assert!(reply(|r| Directory::default().verify("bob", r)).starts_with("252 "));
+    }
+
+    #[test]
+    fn expand() {
+        let directory = directory();
+        assert_eq!(
+            reply(|r| directory.expand("team", r)),
+            "250-Jane Smith <jane@example.test>\r\n250 <bob@example.test>\r\n"
+        );
+        assert!(reply(|r| directory.expand("jane", r)).starts_with("550 "));
+    }
+}
@@ -16,4 +16,19 @@ pub enum Error {
 
     #[error("write timeout")]
     WriteTimeout,
+
+    #[error("TLS configuration error")]
+    Tls(#[from] tokio_rustls::rustls::Error),
+
+    #[error("certificate generation error")]
+    Certificate(#[from] rcgen::RcgenError),
+
+    #[error("no private key found")]
+    NoPrivateKey,
+
+    #[error("implicit TLS requires a TLS configuration")]
+    MissingTlsConfig,
+
+    #[error("TLS is already active")]
+    TlsActive,
 }
@@ -0,0 +1,268 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! ESMTP service extensions (RFC 5321 §2.2).
+//!
+//! Extensions are registered with [`ServerBuilder::extension`](crate::ServerBuilder::extension),
+//! advertised in the reply to EHLO and validate the MAIL and RCPT parameters
+//! that belong to them. A parameter that is not claimed by any extension is
+//! rejected with `555`.
+
+use crate::session::reply::Code;
+
+/// The result of validating a MAIL or RCPT parameter.
+///
+/// `None` means that the parameter does not belong to the extension.
+pub type ParamResult = Option<Result<(), Code>>;
+
+pub trait Extension: Send + Sync {
+    /// The EHLO keyword of the extension, e.g. `SIZE`.
+    fn keyword(&self) -> &str;
+
+    /// Parameters advertised after the keyword in the EHLO reply.
+    fn params(&self) -> Option<String> {
+        None
+    }
+
+    /// Validates a MAIL parameter. `keyword` is always uppercase and `value` is
+    /// empty if the parameter has no value.
+    fn mail_param(&self, _keyword: &str, _value: &str) -> ParamResult {
+        None
+    }
+
+    /// Validates a RCPT parameter, see [`Extension::mail_param`].
+    fn rcpt_param(&self, _keyword: &str, _value: &str) -> ParamResult {
+        None
+    }
+}
+
+macro_rules! keyword_only_extensions {
+    ( $( $(#[$meta:meta])* $name:ident => $keyword:literal ),+ $(,)? ) => {
+        $(
+            $(#[$meta])*
+            #[derive(Debug, Clone, Copy, Default)]
+            pub struct $name;
+
+            impl Extension for $name {
+                fn keyword(&self) -> &str {
+                    $keyword
+                }
+            }
+        )+
+    };
+}
+
+keyword_only_extensions! {
+    /// Command pipelining (RFC 2920).
+    Pipelining => "PIPELINING",
+    /// Enhanced status codes in replies (RFC 2034).
+    EnhancedStatusCodes => "ENHANCEDSTATUSCODES",
+    /// The BDAT command (RFC 3030).
+    Chunking => "CHUNKING",
+    /// The STARTTLS command (RFC 3207).
+    StartTls => "STARTTLS",
+}
+
+/// Message size declaration (RFC 1870). The maximum message size is advertised
+/// if it is set, and MAIL with a larger declared size is rejected with `552`.
+/// The size of the received data is limited by
+/// [`ServerBuilder::max_message_size`](crate::ServerBuilder::max_message_size).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Size(pub Option<u64>);
+
+impl Extension for Size {
+    fn keyword(&self) -> &str {
+        "SIZE"
+    }
+
+    fn params(&self) -> Option<String> {
+        self.0.map(|size| size.to_string())
+    }
+
+    fn mail_param(&self, keyword: &str, value: &str) -> ParamResult {
+        (keyword == "SIZE").then(|| match value.parse::<u64>() {
+            Ok(size) if self.0.is_some_and(|max| size > max) => {
+                Err(Code::EXCEEDED_STORAGE_ALLOCATION)
+            }
+            Ok(_) => Ok(()),
+            Err(_) => Err(Code::BAD_PARAMETER),
+        })
+    }
+}
+
+/// 8bit MIME transport (RFC 6152).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EightBitMime;
+
+impl Extension for EightBitMime {
+    fn keyword(&self) -> &str {
+        "8BITMIME"
+    }
+
+    fn mail_param(&self, keyword: &str, value: &str) -> ParamResult {
+        let body = value.to_ascii_uppercase();
+        (keyword == "BODY" && matches!(&body[..], "7BIT" | "8BITMIME")).then_some(Ok(()))
+    }
+}
+
+/// Binary MIME content (RFC 3030). Mail with `BODY=BINARYMIME` must be sent
+/// with BDAT, so [`Chunking`] is required as well.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BinaryMime;
+
+impl Extension for BinaryMime {
+    fn keyword(&self) -> &str {
+        "BINARYMIME"
+    }
+
+    fn mail_param(&self, keyword: &str, value: &str) -> ParamResult {
+        (keyword == "BODY" && value.eq_ignore_ascii_case("BINARYMIME")).then_some(Ok(()))
+    }
+}
+
+/// Internationalized email addresses and headers (RFC 6531).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SmtpUtf8;
+
+impl Extension for SmtpUtf8 {
+    fn keyword(&self) -> &str {
+        "SMTPUTF8"
+    }
+
+    fn mail_param(&self, keyword: &str, value: &str) -> ParamResult {
+        (keyword == "SMTPUTF8").then_some(match value {
+            "" => Ok(()),
+            _ => Err(Code::BAD_PARAMETER),
+        })
+    }
+}
+
+/// Delivery status notifications (RFC 3461).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Dsn;
+
+impl Extension for Dsn {
+    fn keyword(&self) -> &str {
+        "DSN"
+    }
+
+    fn mail_param(&self, keyword: &str, value: &str) -> ParamResult {
+        let valid = match keyword {
+            "RET" => matches!(&value.to_ascii_uppercase()[..], "FULL" | "HDRS"),
+            "ENVID" => !value.is_empty(),
+            _ => return None,
+        };
+        Some(if valid {
+            Ok(())
+        } else {
+            Err(Code::BAD_PARAMETER)
+        })
+    }
+
+    fn rcpt_param(&self, keyword: &str, value: &str) -> ParamResult {
+        let valid = match keyword {
+            "NOTIFY" => {
+                let value = value.to_ascii_uppercase();
+                value == "NEVER"
+                    || value
+                        .split(',')
+                        .all(|v| matches!(v, "SUCCESS" | "FAILURE" | "DELAY"))
+            }
+            "ORCPT" => value.split_once(';').is_some(),
+            _ => return None,
+        };
+        Some(if valid {
+            Ok(())
+        } else {
+            Err(Code::BAD_PARAMETER)
+        })
+    }
+}
+
+/// SMTP authentication (RFC 4954) with the given SASL mechanisms.
+#[derive(Debug, Clone, Default)]
+pub struct Auth(pub Vec<String>);
+
+impl Extension for Auth {
+    fn keyword(&self) -> &str {
+        "AUTH"
+    }
+
+    fn params(&self) -> Option<String> {
+        (!self.0.is_empty()).then(|| self.0.join(" "))
+    }
+
+    fn mail_param(&self, keyword: &str, value: &str) -> ParamResult {
+        (keyword == "AUTH").then_some(match value {
+            "" => Err(Code::BAD_PARAMETER),
+            _ => Ok(()),
+        })
+    }
+}
+
+/// Returns the EHLO line advertising an extension.
+pub(crate) fn ehlo_line(extension: &dyn Extension) -> String {
+    match extension.params() {
+        Some(params) => format!("{} {}", extension.keyword(), params),
+        None => extension.keyword().to_owned(),
+    }
+}
+
+/// Validates a parameter with the first extension that claims it.
+pub(crate) fn validate_param<F>(extensions: &[Box<dyn Extension>], f: F) -> Result<(), Code>
+where
+    F: Fn(&dyn Extension) -> ParamResult,
+{
+    extensions
+        .iter()
+        .find_map(|extension| f(extension.as_ref()))
+        .unwrap_or(Err(Code::MAIL_FROM_RCPT_TO_NOT_IMPLEMENTED))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn validate_params() {
+        let extensions: Vec<Box<dyn Extension>> = vec![
+            Box::new(Size(Some(1000))),
+            Box::new(EightBitMime),
+            Box::new(Dsn),
+        ];
+        let mail = |keyword: &str, value: &str| {
+            validate_param(&extensions, |ext| ext.mail_param(keyword, value))
+        };
+
+        assert_eq!(mail("SIZE", "100"), Ok(()));
+        assert_eq!(mail("SIZE", "x"), Err(Code::BAD_PARAMETER));
+        assert_eq!(mail("BODY", "8bitmime"), Ok(()));
+        assert_eq!(
+            mail("BODY", "BINARYMIME"),
+            Err(Code::MAIL_FROM_RCPT_TO_NOT_IMPLEMENTED)
+        );
+        assert_eq!(
+            validate_param(&[Box::new(BinaryMime) as _], |ext| ext
+                .mail_param("BODY", "binarymime")),
+            Ok(())
+        );
+        assert_eq!(mail("RET", "HDRS"), Ok(()));
+        assert_eq!(
+            mail("SMTPUTF8", ""),
+            Err(Code::MAIL_FROM_RCPT_TO_NOT_IMPLEMENTED)
+        );
+        assert_eq!(
+            validate_param(&extensions, |ext| ext.rcpt_param("NOTIFY", "success,delay")),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn ehlo_lines() {
+        assert_eq!(ehlo_line(&Size(Some(1024))), "SIZE 1024");
+        assert_eq!(ehlo_line(&Size(None)), "SIZE");
+        assert_eq!(
+            ehlo_line(&Auth(vec!["PLAIN".into(), "LOGIN".into()])),
+            "AUTH PLAIN LOGIN"
+        );
+    }
+}
@@ -1,14 +1,22 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+mod auth;
 mod conn;
+mod directory;
 mod error;
+pub mod extension;
 mod session;
+mod tls;
 
+pub use auth::Credentials;
+pub use directory::Mailbox;
 pub use error::Error;
-pub use session::Session;
+pub use session::{reply::Code, Session};
+pub use tls::{TlsConfig, TlsInfo};
 
 use error::Result;
 use std::{
+    collections::HashMap,
     net::{SocketAddr, ToSocketAddrs},
     sync::Arc,
 };
@@ -16,15 +24,16 @@ use tokio::net::{TcpListener, TcpStream};
 use tracing::trace;
 use tracing_futures::Instrument as _;
 
-use crate::conn::Connection;
+use crate::{conn::Connection, directory::Directory, extension::Extension};
 
 type OnConnErr = dyn Fn(Error) + Send + Sync;
 type OnNewMail = dyn Fn(RawMail) + Send + Sync;
 
 pub struct Server {
     socket_addr: Vec<SocketAddr>,
+    tls_socket_addr: Vec<SocketAddr>,
     on_conn_err: Arc<OnConnErr>,
-    on_new_mail: Arc<OnNewMail>,
+    config: Arc<Config>,
 
     handle_tx: tokio::sync::mpsc::Sender<bool>,
     handle_rx: tokio::sync::mpsc::Receiver<bool>,
@@ -39,11 +48,32 @@ impl Server {
         let listener = TcpListener::bind(&self.socket_addr[..]).await?;
         let local_addr = listener.local_addr().expect("no TCP listener local addr");
         tracing::info!(addr = display(local_addr), "starting SMTP server");
+
+        let tls_listener = match self.tls_socket_addr.is_empty() {
+            true => None,
+            false => {
+                let listener = TcpListener::bind(&self.tls_socket_addr[..]).await?;
+                let addr = listener.local_addr().expect("no TCP listener local addr");
+                tracing::info!(addr = display(addr), "starting implicit TLS SMTP server");
+                Some(listener)
+            }
+        };
+        let tls_accept = || async {
+            match &tls_listener {
+                Some(listener) => listener.accept().await,
+                None => std::future::pending().await,
+            }
+        };
+
         'main_loop: loop {
             tokio::select! {
                 accepted = listener.accept() => {
                     let (stream, addr) = accepted?;
-                    self.accept(stream, addr);
+                    self.accept(stream, addr, false);
+                },
+                accepted = tls_accept() => {
+                    let (stream, addr) = accepted?;
+                    self.accept(stream, addr, true);
                 },
                 Some(true) = self.handle_rx.recv() => break 'main_loop,
             };
@@ -52,11 +82,14 @@ impl Server {
         Ok(())
     }
 
-    fn accept(&mut self, stream: TcpStream, addr: SocketAddr) {
+    fn accept(&mut self, stream: TcpStream, addr: SocketAddr, implicit_tls: bool) {
         trace!("accepted connection from {}", addr);
 
-        let sess = Session::new(self.on_new_mail.clone());
-        let conn = Connection::new(stream, sess);
+        let sess = Session::new(self.config.clone(), addr);
+        let conn = match implicit_tls {
+            true => Connection::new_implicit_tls(stream, sess),
+            false => Connection::new(stream, sess),
+        };
         let span = tracing::trace_span!("connection", addr = display(addr));
         let on_conn_err = self.on_conn_err.clone();
         let task = async move {
@@ -93,8 +126,18 @@ impl ServerHandle {
 
 pub struct ServerBuilder {
     socket_addr: Result<Vec<SocketAddr>>,
+    tls_socket_addr: Result<Vec<SocketAddr>>,
+    tls: Option<TlsConfig>,
+    credentials: Option<Credentials>,
+    strict: bool,
+    bare_line_endings: BareLineEndings,
+    max_line_length: usize,
+    max_message_size: Option<usize>,
+    directory: Directory,
     on_conn_err: Option<Arc<OnConnErr>>,
     on_new_mail: Option<Arc<OnNewMail>>,
+    hostname: String,
+    extensions: Vec<Box<dyn Extension>>,
 }
 
 impl ServerBuilder {
@@ -114,6 +157,83 @@ impl ServerBuilder {
         self
     }
 
+    /// The name the server uses to identify itself in the reply to EHLO.
+    pub fn hostname<S: Into<String>>(mut self, hostname: S) -> Self {
+        self.hostname = hostname.into();
+        self
+    }
+
+    /// Adds an ESMTP extension that is advertised in the reply to EHLO. See
+    /// [`extension`] for the built-in extensions.
+    pub fn extension<E>(mut self, extension: E) -> Self
+    where
+        E: 'static + Extension,
+    {
+        self.extensions.push(Box::new(extension));
+        self
+    }
+
+    /// Sets the certificate used for STARTTLS and implicit TLS connections.
+    /// STARTTLS is only offered if the [`StartTls`](extension::StartTls)
+    /// extension is added as well.
+    pub fn tls(mut self, config: TlsConfig) -> Self {
+        self.tls = Some(config);
+        self
+    }
+
+    /// Enforces the command sequence of RFC 5321 strictly: MAIL is rejected
+    /// before HELO/EHLO and while a transaction is in progress, instead of
+    /// implicitly starting a new one. RCPT without MAIL and DATA without
+    /// recipients are always rejected.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Sets how lines ending with a bare LF or containing a bare CR are
+    /// handled. By default they are normalized to CRLF.
+    pub fn bare_line_endings(mut self, bare_line_endings: BareLineEndings) -> Self {
+        self.bare_line_endings = bare_line_endings;
+        self
+    }
+
+    /// Sets the maximum length of a line of the mail data including CRLF,
+    /// `1000` by default (RFC 5321 §4.5.3.1.6). Mail with longer lines is
+    /// rejected.
+    pub fn max_line_length(mut self, max_line_length: usize) -> Self {
+        self.max_line_length = max_line_length;
+        self
+    }
+
+    /// Limits the size of the mail data. Larger mail is received up to the end
+    /// of the data, without storing it, and rejected with `552`. The limit
+    /// should be advertised with [`Size`](extension::Size) as well.
+    pub fn max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = Some(max_message_size);
+        self
+    }
+
+    /// Adds a mailbox that VRFY can verify. As long as neither mailboxes nor
+    /// mailing lists are added, VRFY and EXPN reply with `252`.
+    pub fn mailbox(mut self, mailbox: Mailbox) -> Self {
+        self.directory.add_mailbox(mailbox);
+        self
+    }
+
+    /// Adds a mailing list with the addresses of its members that EXPN can
+    /// expand.
+    pub fn mailing_list<S: AsRef<str>>(mut self, name: S, members: Vec<String>) -> Self {
+        self.directory.add_list(name.as_ref(), members);
+        self
+    }
+
+    /// Enables the AUTH command with the PLAIN, LOGIN and CRAM-MD5 mechanisms
+    /// and advertises the [`Auth`](extension::Auth) extension.
+    pub fn auth(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self.extension(extension::Auth(auth::MECHANISMS.map(String::from).to_vec()))
+    }
+
     pub fn bind<S>(mut self, addr: S) -> Self
     where
         S: 'static + ToSocketAddrs,
@@ -125,17 +245,64 @@ impl ServerBuilder {
         self
     }
 
+    /// Listens for implicit TLS connections (RFC 8314), which start with the TLS
+    /// handshake. Requires [`ServerBuilder::tls`].
+    pub fn bind_tls<S>(mut self, addr: S) -> Self
+    where
+        S: 'static + ToSocketAddrs,
+    {
+        self.tls_socket_addr = addr
+            .to_socket_addrs()
+            .map(|addrs| addrs.collect())
+            .map_err(Error::from);
+        self
+    }
+
     pub fn build(self) -> Result<Server> {
         let on_conn_err = self.on_conn_err.unwrap_or_else(|| Arc::new(|_| {}));
         let on_new_mail = self.on_new_mail.unwrap_or_else(|| Arc::new(|_| {}));
         let socket_addr = self.socket_addr?;
+        let tls_socket_addr = self.tls_socket_addr?;
+        if !tls_socket_addr.is_empty() && self.tls.is_none() {
+            return Err(Error::MissingTlsConfig);
+        }
 
         let (handle_tx, handle_rx) = tokio::sync::mpsc::channel(1);
 
+        let enhanced_status_codes = self
+            .extensions
+            .iter()
+            .any(|ext| ext.keyword().eq_ignore_ascii_case("ENHANCEDSTATUSCODES"));
+        let chunking = self
+            .extensions
+            .iter()
+            .any(|ext| ext.keyword().eq_ignore_ascii_case("CHUNKING"));
+        let starttls = self.tls.is_some()
+            && self
+                .extensions
+                .iter()
+                .any(|ext| ext.keyword().eq_ignore_ascii_case("STARTTLS"));
+        let config = Config {
+            on_new_mail,
+            hostname: self.hostname,
+            extensions: self.extensions,
+            enhanced_status_codes,
+            chunking,
+            tls: self.tls,
+            starttls,
+            credentials: self.credentials,
+            strict: self.strict,
+            bare_line_endings: self.bare_line_endings,
+            max_line_length: self.max_line_length,
+            max_message_size: self.max_message_size,
+            directory: self.directory,
+        };
+
         Ok(Server {
             socket_addr,
+            tls_socket_addr,
             on_conn_err,
-            on_new_mail,
+            config: Arc::new(config),
 
             handle_tx,
             handle_rx,
@@ -147,24 +314,69 @@ impl Default for ServerBuilder {
     fn default() -> Self {
         ServerBuilder {
             socket_addr: Ok(Vec::new()),
+            tls_socket_addr: Ok(Vec::new()),
+            tls: None,
+            credentials: None,
+            strict: false,
+            bare_line_endings: BareLineEndings::default(),
+            max_line_length: 1000,
+            max_message_size: None,
+            directory: Directory::default(),
             on_conn_err: None,
             on_new_mail: None,
+            hostname: "localhost".to_owned(),
+            extensions: Vec::new(),
         }
     }
 }
 
+/// Settings shared by all sessions of a server.
+pub(crate) struct Config {
+    on_new_mail: Arc<OnNewMail>,
+    hostname: String,
+    extensions: Vec<Box<dyn Extension>>,
+    enhanced_status_codes: bool,
+    /// Whether BDAT is accepted, i.e. the CHUNKING extension is added.
+    chunking: bool,
+    tls: Option<TlsConfig>,
+    /// Whether STARTTLS is offered, i.e. TLS is configured and the extension is added.
+    starttls: bool,
+    credentials: Option<Credentials>,
+    strict: bool,
+    bare_line_endings: BareLineEndings,
+    /// The maximum length of a line of the mail data including CRLF.
+    max_line_length: usize,
+    /// The maximum size of the mail data.
+    max_message_size: Option<usize>,
+    directory: Directory,
+}
+
+/// How lines ending with a bare LF or containing a bare CR are handled. RFC
+/// 5321 §2.3.8 only allows CRLF, but some clients send bare line endings anyway.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BareLineEndings {
+    /// Bare LF and CR are treated as CRLF.
+    #[default]
+    Normalize,
+    /// Commands with bare line endings are rejected with `500` and mail
+    /// containing them with `554`.
+    Reject,
+}
+
 pub struct RawMail {
+    /// Address of the client that sent the mail.
+    pub client_addr: SocketAddr,
+    /// Domain (or address literal) given by the client in its HELO/EHLO command.
+    pub helo: Option<String>,
     pub reverse_path: String,
+    /// ESMTP parameters of the MAIL command.
+    pub mail_parameters: HashMap<String, String>,
     pub forward_path: Vec<String>,
+    /// ESMTP parameters of each RCPT command, in the same order as `forward_path`.
+    pub rcpt_parameters: Vec<HashMap<String, String>>,
+    /// The TLS parameters if the mail was received over an encrypted connection.
+    pub tls: Option<TlsInfo>,
+    /// The username the client authenticated with.
+    pub auth_username: Option<String>,
     pub data: Vec<u8>,
 }
-
-impl RawMail {
-    pub fn new(reverse_path: String, forward_path: Vec<String>, data: Vec<u8>) -> Self {
-        Self {
-            reverse_path,
-            forward_path,
-            data,
-        }
-    }
-}
@@ -1,10 +1,15 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use std::{collections::HashMap, sync::Arc};
+use std::{borrow::Cow, collections::HashMap, net::SocketAddr, sync::Arc};
 
 use tracing::debug;
 
-use crate::{OnNewMail, RawMail};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+
+use crate::auth::{Exchange, Step};
+use crate::{extension, BareLineEndings, Config, RawMail, TlsConfig, TlsInfo};
+
+use crate::extension::{Extension, ParamResult};
 
 use self::{
     cmd::Command,
@@ -18,30 +23,65 @@ pub struct Session {
     mode: Mode,
     line_buffer: Vec<u8>,
     data_buffer: Vec<u8>,
+    client_addr: SocketAddr,
+    helo: Option<String>,
     reverse_path: String,
+    mail_parameters: HashMap<String, String>,
     forward_path: Vec<String>,
+    rcpt_parameters: Vec<HashMap<String, String>>,
+    transaction: Transaction,
+    /// Whether the client greeted with EHLO.
+    esmtp: bool,
+    tls: Option<TlsInfo>,
+    /// Whether the connection has to be upgraded to TLS after the current reply.
+    starttls_pending: bool,
+    /// The authentication exchange in progress while in [`Mode::Auth`].
+    auth_exchange: Option<Exchange>,
+    auth_username: Option<String>,
+    /// Whether the last line was longer than [`Session::max_line_length`].
+    overlong_line: bool,
+    /// The reply for a message that is rejected once all data is received.
+    data_error: Option<(Code, &'static str)>,
+    /// The BDAT chunk that is being received while in [`Mode::Chunk`].
+    chunk: Option<Chunk>,
     closed: bool,
-    on_new_mail: Arc<OnNewMail>,
+    config: Arc<Config>,
 }
 
 impl Session {
-    pub fn new(on_new_mail: Arc<OnNewMail>) -> Self {
+    pub(crate) fn new(config: Arc<Config>, client_addr: SocketAddr) -> Self {
         Session {
             mode: Mode::Open,
             line_buffer: Vec::with_capacity(64),
             data_buffer: Vec::new(),
+            client_addr,
+            helo: None,
             reverse_path: String::new(),
+            mail_parameters: HashMap::new(),
             forward_path: Vec::with_capacity(1),
+            rcpt_parameters: Vec::with_capacity(1),
+            transaction: Transaction::None,
+            esmtp: false,
+            tls: None,
+            starttls_pending: false,
+            auth_exchange: None,
+            auth_username: None,
+            overlong_line: false,
+            data_error: None,
+            chunk: None,
             closed: false,
-            on_new_mail,
+            config,
         }
     }
 
     pub fn on_recv(&mut self, reply: &mut Reply) {
+        reply.enhanced_status_codes(self.esmtp && self.config.enhanced_status_codes);
         match self.mode {
             Mode::Open => self.on_open(reply),
             Mode::Line => self.on_line(reply),
             Mode::Data => self.on_data(reply),
+            Mode::Auth => self.on_auth(reply),
+            Mode::Chunk => self.on_chunk(reply),
         }
     }
 
@@ -51,30 +91,225 @@ impl Session {
     }
 
     fn on_line(&mut self, reply: &mut Reply) {
-        match Command::parse(&self.line_buffer) {
-            Ok(cmd) => self.handle_command(reply, cmd),
-            Err(code) => reply.code(code),
+        let line = std::mem::take(&mut self.line_buffer);
+        match self.command_line(&line) {
+            Ok(line) => match Command::parse(line) {
+                Ok(cmd) => self.handle_command(reply, cmd),
+                Err(code) => reply.code(code),
+            },
+            Err(text) => {
+                reply.code(Code::UNRECOGNIZED_COMMAND);
+                reply.line(text);
+            }
         }
+        self.line_buffer = line;
         self.line_buffer.clear();
     }
 
+    /// Checks the length and line ending of a command line. Bare line endings
+    /// are replaced with CRLF if allowed.
+    fn command_line<'a>(&mut self, line: &'a [u8]) -> Result<Cow<'a, [u8]>, &'static str> {
+        if std::mem::take(&mut self.overlong_line) {
+            return Err("line too long");
+        }
+        match self.split_lines(line).as_deref() {
+            Some([_]) if line.ends_with(LINE_TERMINATOR) => Ok(Cow::Borrowed(line)),
+            Some([content]) => Ok(Cow::Owned([content, LINE_TERMINATOR].concat())),
+            _ => Err("bare CR or LF not allowed"),
+        }
+    }
+
+    /// Handles a line of the mail data (RFC 5321 §4.1.1.4). The mail is
+    /// delivered once the final line with a single dot is received.
     fn on_data(&mut self, reply: &mut Reply) {
+        let line = std::mem::take(&mut self.line_buffer);
+
+        let bare_lf_allowed = self.config.bare_line_endings == BareLineEndings::Normalize;
+        let end_of_data = line == b".\r\n" || (bare_lf_allowed && line == b".\n");
+        if end_of_data {
+            self.on_end_of_data(reply);
+        } else if std::mem::take(&mut self.overlong_line) {
+            self.data_error
+                .get_or_insert((Code::UNRECOGNIZED_COMMAND, "line too long"));
+        } else if self.data_error.is_none() {
+            match self.split_lines(&line) {
+                Some(lines) => {
+                    for (i, mut content) in lines.into_iter().enumerate() {
+                        // transparency: the client doubles dots at the start of lines
+                        if i == 0 && content.starts_with(b".") {
+                            content = &content[1..];
+                        }
+                        self.data_buffer.extend(content);
+                        self.data_buffer.extend(LINE_TERMINATOR);
+                    }
+                    self.check_message_size();
+                }
+                None => {
+                    self.data_error.get_or_insert((
+                        Code::TRANSACTION_FAILED,
+                        "message contains bare CR or LF",
+                    ));
+                }
+            }
+        }
+
+        self.line_buffer = line;
+        self.line_buffer.clear();
+    }
+
+    fn on_end_of_data(&mut self, reply: &mut Reply) {
         debug!(size = self.data_buffer.len(), "received data");
+        self.mode = Mode::Line;
 
-        let mut mail = RawMail::new(
-            std::mem::take(&mut self.reverse_path),
-            std::mem::take(&mut self.forward_path),
-            std::mem::take(&mut self.data_buffer),
-        );
+        if let Some((code, text)) = self.data_error.take() {
+            debug!(code = u16::from(code), text, "rejected mail");
+            self.reset_transaction();
+            reply.code(code);
+            reply.line(text);
+            return;
+        }
+
+        let mail = RawMail {
+            client_addr: self.client_addr,
+            helo: self.helo.clone(),
+            reverse_path: std::mem::take(&mut self.reverse_path),
+            mail_parameters: std::mem::take(&mut self.mail_parameters),
+            forward_path: std::mem::take(&mut self.forward_path),
+            rcpt_parameters: std::mem::take(&mut self.rcpt_parameters),
+            tls: self.tls.clone(),
+            auth_username: self.auth_username.clone(),
+            data: std::mem::take(&mut self.data_buffer),
+        };
+        (self.config.on_new_mail)(mail);
+
+        self.transaction = Transaction::None;
+        reply.code(Code::MAIL_ACTION_OKAY);
+    }
+
+    /// Handles a part of a BDAT chunk (RFC 3030 §2). The chunk is answered once
+    /// it is received completely.
+    fn on_chunk(&mut self, reply: &mut Reply) {
+        let chunk = self
+            .chunk
+            .as_mut()
+            .expect("chunk must be in progress in chunk mode");
+        chunk.remaining -= self.line_buffer.len();
+        let (accepted, complete) = (chunk.error.is_none(), chunk.remaining == 0);
+
+        if accepted && self.data_error.is_none() {
+            self.data_buffer.extend(&self.line_buffer);
+            self.check_message_size();
+        }
+        self.line_buffer.clear();
 
-        if mail.data.ends_with(DATA_TERMINATOR) {
-            mail.data.truncate(mail.data.len() - DATA_TERMINATOR.len());
+        if complete {
+            self.on_end_of_chunk(reply);
         }
+    }
+
+    fn on_end_of_chunk(&mut self, reply: &mut Reply) {
+        let chunk = self.chunk.take().expect("chunk must be in progress");
+        self.mode = Mode::Line;
+        debug!(size = chunk.size, last = chunk.last, "received chunk");
+
+        if let Some((code, text)) = chunk.error {
+            reply.code(code);
+            reply.line(text);
+        } else if chunk.last {
+            self.on_end_of_data(reply);
+        } else if let Some((code, text)) = self.data_error.take() {
+            self.reset_transaction();
+            reply.code(code);
+            reply.line(text);
+        } else {
+            self.transaction = Transaction::Chunks;
+            reply.code(Code::MAIL_ACTION_OKAY);
+            reply.line(format!("{} octets received", chunk.size));
+        }
+    }
 
-        (self.on_new_mail)(mail);
+    /// Aborts a mail that exceeds the maximum message size. The rest of the
+    /// data is still received, but discarded.
+    fn check_message_size(&mut self) {
+        let exceeded = self
+            .config
+            .max_message_size
+            .is_some_and(|max| self.data_buffer.len() > max);
+        if exceeded {
+            debug!(
+                size = self.data_buffer.len(),
+                "maximum message size exceeded"
+            );
+            self.data_buffer = Vec::new();
+            self.data_error.get_or_insert((
+                Code::EXCEEDED_STORAGE_ALLOCATION,
+                "message exceeds the maximum message size",
+            ));
+        }
+    }
+
+    /// Removes the line ending of a received line and splits it at bare CRs.
+    /// Returns `None` if it contains a bare CR or ends with a bare LF and those
+    /// are rejected.
+    fn split_lines<'a>(&self, line: &'a [u8]) -> Option<Vec<&'a [u8]>> {
+        let (content, bare_lf) = match line.strip_suffix(LINE_TERMINATOR) {
+            Some(content) => (content, false),
+            None => match line.strip_suffix(b"\n") {
+                Some(content) => (content, true),
+                // the connection was closed before the line ending
+                None => (line, false),
+            },
+        };
+        let lines = content.split(|&ch| ch == b'\r').collect::<Vec<_>>();
+
+        let bare_line_endings = bare_lf || lines.len() > 1;
+        match self.config.bare_line_endings {
+            BareLineEndings::Reject if bare_line_endings => None,
+            _ => Some(lines),
+        }
+    }
 
+    fn on_auth(&mut self, reply: &mut Reply) {
+        let line = std::mem::take(&mut self.line_buffer);
+        let exchange = self
+            .auth_exchange
+            .take()
+            .expect("authentication exchange must be in progress in auth mode");
+        let line = match self.command_line(&line) {
+            Ok(line) => line,
+            Err(text) => {
+                self.mode = Mode::Line;
+                reply.code(Code::UNRECOGNIZED_COMMAND);
+                reply.line(text);
+                return;
+            }
+        };
+        let response = String::from_utf8_lossy(&line[..line.len() - LINE_TERMINATOR.len()]);
+        let credentials = self
+            .config
+            .credentials
+            .as_ref()
+            .expect("authentication must be enabled in auth mode");
+        let step = exchange.respond(response.trim(), credentials);
+        self.on_auth_step(reply, step);
+    }
+
+    fn on_auth_step(&mut self, reply: &mut Reply, step: Step) {
         self.mode = Mode::Line;
-        reply.code(Code::MAIL_ACTION_OKAY);
+        match step {
+            Step::Challenge(exchange, challenge) => {
+                self.auth_exchange = Some(exchange);
+                self.mode = Mode::Auth;
+                reply.code(Code::AUTH_CHALLENGE);
+                reply.line(STANDARD.encode(challenge));
+            }
+            Step::Success(username) => {
+                debug!(username = debug(&username), "authenticated");
+                self.auth_username = Some(username);
+                reply.code(Code::AUTH_SUCCEEDED);
+            }
+            Step::Failure(code) => reply.code(code),
+        }
     }
 
     fn handle_command(&mut self, reply: &mut Reply, cmd: Command) {
@@ -91,50 +326,241 @@ impl Session {
             } => self.handle_rcpt(reply, forward_path, rcpt_parameters),
             Command::DATA => self.handle_data(reply),
             Command::RSET => self.handle_rset(reply),
+            Command::VRFY { string } => self.handle_vrfy(reply, string),
+            Command::EXPN { string } => self.handle_expn(reply, string),
+            Command::HELP { string } => self.handle_help(reply, string),
             Command::NOOP { string } => self.handle_noop(reply, string),
             Command::QUIT => self.handle_quit(reply),
-
-            // TODO implement remaining commands: VRFY, EXPN, HELP
-            _ => reply.code(Code::COMMAND_NOT_IMPLEMENTED),
+            Command::STARTTLS => self.handle_starttls(reply),
+            Command::AUTH {
+                mechanism,
+                initial_response,
+            } => self.handle_auth(reply, mechanism, initial_response),
+            Command::BDAT { size, last } => self.handle_bdat(reply, size, last),
         }
     }
 
     fn handle_ehlo(&mut self, reply: &mut Reply, domain: String) {
-        debug!(domain = debug(domain), "EHLO");
+        debug!(domain = debug(&domain), "EHLO");
+        reply.enhanced_status_codes(false);
         reply.code(Code::MAIL_ACTION_OKAY);
+        reply.line(format!("{} greets {}", self.config.hostname, domain));
+        for ext in &self.config.extensions {
+            // STARTTLS is only offered while it can be used
+            if ext.keyword().eq_ignore_ascii_case("STARTTLS") && !self.starttls_available() {
+                continue;
+            }
+            reply.line(extension::ehlo_line(ext.as_ref()));
+        }
+
+        self.helo = Some(domain);
+        self.esmtp = true;
+        self.reset_transaction();
     }
 
     fn handle_helo(&mut self, reply: &mut Reply, domain: String) {
-        debug!(domain = debug(domain), "HELO");
+        debug!(domain = debug(&domain), "HELO");
+        self.helo = Some(domain);
+        self.esmtp = false;
+        self.reset_transaction();
         reply.code(Code::MAIL_ACTION_OKAY);
     }
 
     fn handle_mail(&mut self, reply: &mut Reply, path: String, params: HashMap<String, String>) {
         debug!(path = debug(&path), params = debug(&params), "MAIL");
-        self.forward_path.clear();
-        self.data_buffer.clear();
+        // a strict server requires the greeting and doesn't implicitly abort a
+        // transaction that is in progress
+        let out_of_sequence =
+            self.config.strict && (self.helo.is_none() || self.transaction != Transaction::None);
+        if out_of_sequence {
+            reply.code(Code::BAD_SEQUENCE_OF_COMMANDS);
+            return;
+        }
+        if let Err(code) = self.validate_params(&params, |ext, k, v| ext.mail_param(k, v)) {
+            reply.code(code);
+            return;
+        }
+        if !path.is_ascii() && !smtputf8(&params) {
+            reply.code(Code::MAILBOX_NAME_NOT_ALLOWED);
+            reply.line("non-ASCII address requires SMTPUTF8");
+            return;
+        }
+
+        self.reset_transaction();
         self.reverse_path = path;
+        self.mail_parameters = params;
+        self.transaction = Transaction::Mail;
         reply.code(Code::MAIL_ACTION_OKAY);
     }
 
     fn handle_rcpt(&mut self, reply: &mut Reply, path: String, params: HashMap<String, String>) {
         debug!(path = debug(&path), params = debug(&params), "RCPT");
+        if matches!(self.transaction, Transaction::None | Transaction::Chunks) {
+            reply.code(Code::BAD_SEQUENCE_OF_COMMANDS);
+            return;
+        }
+        if let Err(code) = self.validate_params(&params, |ext, k, v| ext.rcpt_param(k, v)) {
+            reply.code(code);
+            return;
+        }
+        if !path.is_ascii() && !smtputf8(&self.mail_parameters) {
+            reply.code(Code::MAILBOX_NAME_NOT_ALLOWED);
+            reply.line("non-ASCII address requires SMTPUTF8");
+            return;
+        }
+
         self.forward_path.push(path);
+        self.rcpt_parameters.push(params);
+        self.transaction = Transaction::Rcpt;
         reply.code(Code::MAIL_ACTION_OKAY);
     }
 
     fn handle_data(&mut self, reply: &mut Reply) {
         debug!("DATA");
-        self.mode = Mode::Data;
-        reply.code(Code::START_MAIL_INPUT);
+        match self.transaction {
+            Transaction::None => reply.code(Code::BAD_SEQUENCE_OF_COMMANDS),
+            Transaction::Mail => {
+                reply.code(Code::TRANSACTION_FAILED);
+                reply.line("no valid recipients");
+            }
+            // binary content can't be sent with DATA (RFC 3030 §3)
+            Transaction::Rcpt if self.binary_mime() => {
+                reply.code(Code::BAD_SEQUENCE_OF_COMMANDS);
+                reply.line("BODY=BINARYMIME requires BDAT");
+            }
+            Transaction::Rcpt => {
+                self.mode = Mode::Data;
+                reply.code(Code::START_MAIL_INPUT);
+            }
+            Transaction::Chunks => reply.code(Code::BAD_SEQUENCE_OF_COMMANDS),
+        }
+    }
+
+    fn binary_mime(&self) -> bool {
+        self.mail_parameters.iter().any(|(keyword, value)| {
+            keyword.eq_ignore_ascii_case("BODY") && value.eq_ignore_ascii_case("BINARYMIME")
+        })
+    }
+
+    /// Starts receiving a chunk of the mail data. The chunk is always read,
+    /// even if it is rejected, so it isn't mistaken for commands.
+    fn handle_bdat(&mut self, reply: &mut Reply, size: usize, last: bool) {
+        debug!(size, last, "BDAT");
+        if !self.config.chunking {
+            reply.code(Code::COMMAND_NOT_IMPLEMENTED);
+            return;
+        }
+
+        let error = match self.transaction {
+            Transaction::None => Some((Code::BAD_SEQUENCE_OF_COMMANDS, "no mail transaction")),
+            Transaction::Mail => Some((Code::TRANSACTION_FAILED, "no valid recipients")),
+            Transaction::Rcpt | Transaction::Chunks => None,
+        };
+        self.chunk = Some(Chunk {
+            size,
+            remaining: size,
+            last,
+            error,
+        });
+        self.mode = Mode::Chunk;
+        if size == 0 {
+            self.on_end_of_chunk(reply);
+        }
     }
 
     fn handle_rset(&mut self, reply: &mut Reply) {
         debug!("RSET");
+        self.reset_transaction();
+        reply.code(Code::MAIL_ACTION_OKAY);
+    }
+
+    fn reset_transaction(&mut self) {
         self.reverse_path.clear();
+        self.mail_parameters.clear();
         self.forward_path.clear();
+        self.rcpt_parameters.clear();
         self.data_buffer.clear();
-        reply.code(Code::MAIL_ACTION_OKAY);
+        self.data_error = None;
+        self.transaction = Transaction::None;
+    }
+
+    /// Validates MAIL or RCPT parameters with the registered extensions. Without
+    /// EHLO no extensions are in effect, so any parameter is rejected.
+    fn validate_params<F>(&self, params: &HashMap<String, String>, f: F) -> Result<(), Code>
+    where
+        F: Fn(&dyn Extension, &str, &str) -> ParamResult,
+    {
+        let extensions = match self.esmtp {
+            true => &self.config.extensions[..],
+            false => &[],
+        };
+        for (keyword, value) in params {
+            // keywords are case-insensitive
+            let keyword = keyword.to_ascii_uppercase();
+            extension::validate_param(extensions, |ext| f(ext, &keyword, value))?;
+        }
+        Ok(())
+    }
+
+    fn handle_vrfy(&mut self, reply: &mut Reply, string: String) {
+        debug!(string = debug(&string), "VRFY");
+        self.config.directory.verify(&string, reply);
+    }
+
+    fn handle_expn(&mut self, reply: &mut Reply, string: String) {
+        debug!(string = debug(&string), "EXPN");
+        self.config.directory.expand(&string, reply);
+    }
+
+    fn handle_help(&mut self, reply: &mut Reply, string: String) {
+        debug!(string = debug(&string), "HELP");
+        if string.is_empty() {
+            let mut commands = vec![
+                "HELO", "EHLO", "MAIL", "RCPT", "DATA", "RSET", "NOOP", "QUIT", "VRFY", "EXPN",
+                "HELP",
+            ];
+            if self.config.starttls {
+                commands.push("STARTTLS");
+            }
+            if self.config.credentials.is_some() {
+                commands.push("AUTH");
+            }
+            if self.config.chunking {
+                commands.push("BDAT");
+            }
+
+            reply.code(Code::HELP_MESSAGE);
+            reply.line(format!("{} supports the commands:", self.config.hostname));
+            for chunk in commands.chunks(6) {
+                reply.line(format!("  {}", chunk.join(" ")));
+            }
+            reply.line("use HELP <command> for details");
+            return;
+        }
+
+        let usage = match &string.to_ascii_uppercase()[..] {
+            "HELO" => "HELO <domain>",
+            "EHLO" => "EHLO <domain>",
+            "MAIL" => "MAIL FROM:<reverse-path> [parameters]",
+            "RCPT" => "RCPT TO:<forward-path> [parameters]",
+            "DATA" => "DATA, end the message with <CRLF>.<CRLF>",
+            "BDAT" if self.config.chunking => "BDAT <size> [LAST], followed by <size> octets",
+            "RSET" => "RSET, aborts the current mail transaction",
+            "NOOP" => "NOOP [string]",
+            "QUIT" => "QUIT",
+            "VRFY" => "VRFY <user or mailbox>",
+            "EXPN" => "EXPN <mailing list>",
+            "HELP" => "HELP [command]",
+            "STARTTLS" if self.config.starttls => "STARTTLS",
+            "AUTH" if self.config.credentials.is_some() => "AUTH <mechanism> [initial-response]",
+            _ => {
+                reply.code(Code::PARAMETER_NOT_IMPLEMENTED);
+                reply.line("unknown HELP topic");
+                return;
+            }
+        };
+        reply.code(Code::HELP_MESSAGE);
+        reply.line(usage);
     }
 
     fn handle_noop(&mut self, reply: &mut Reply, string: String) {
@@ -142,25 +568,107 @@ impl Session {
         reply.code(Code::MAIL_ACTION_OKAY);
     }
 
+    fn handle_starttls(&mut self, reply: &mut Reply) {
+        debug!("STARTTLS");
+        if !self.config.starttls {
+            reply.code(Code::COMMAND_NOT_IMPLEMENTED);
+        } else if !self.starttls_available() {
+            reply.code(Code::BAD_SEQUENCE_OF_COMMANDS);
+        } else {
+            self.starttls_pending = true;
+            reply.code(Code::SERVICE_READY);
+            reply.line("ready to start TLS");
+        }
+    }
+
+    fn handle_auth(
+        &mut self,
+        reply: &mut Reply,
+        mechanism: String,
+        initial_response: Option<String>,
+    ) {
+        debug!(mechanism = debug(&mechanism), "AUTH");
+        let credentials = match &self.config.credentials {
+            Some(credentials) => credentials,
+            None => {
+                reply.code(Code::COMMAND_NOT_IMPLEMENTED);
+                return;
+            }
+        };
+        // AUTH is only allowed once per session, after EHLO and outside of a mail
+        // transaction (RFC 4954 §4)
+        if !self.esmtp || self.auth_username.is_some() || self.transaction != Transaction::None {
+            reply.code(Code::BAD_SEQUENCE_OF_COMMANDS);
+            return;
+        }
+
+        let step = Exchange::start(
+            &mechanism,
+            initial_response.as_deref(),
+            credentials,
+            &self.config.hostname,
+        );
+        self.on_auth_step(reply, step);
+    }
+
+    fn starttls_available(&self) -> bool {
+        self.config.starttls && self.tls.is_none()
+    }
+
+    /// Called once the TLS handshake is done. The client has to start over
+    /// with EHLO, as required by RFC 3207 §4.2.
+    pub(crate) fn on_tls(&mut self, info: TlsInfo) {
+        self.tls = Some(info);
+        self.starttls_pending = false;
+        self.helo = None;
+        self.esmtp = false;
+        self.auth_username = None;
+        self.line_buffer.clear();
+        self.reset_transaction();
+    }
+
+    pub(crate) fn tls_config(&self) -> Option<&TlsConfig> {
+        self.config.tls.as_ref()
+    }
+
+    pub(crate) fn starttls_pending(&self) -> bool {
+        self.starttls_pending
+    }
+
     fn handle_quit(&mut self, reply: &mut Reply) {
         debug!("QUIT");
         self.closed = true;
         reply.code(Code::SERVICE_CLOSING);
     }
 
-    pub fn terminator(&self) -> &'static [u8] {
+    /// The number of bytes of a BDAT chunk that are still to be received. The
+    /// chunk is read instead of a line as long as this is `Some`.
+    pub fn chunk_remaining(&self) -> Option<usize> {
+        self.chunk.as_ref().map(|chunk| chunk.remaining)
+    }
+
+    /// The maximum length of the next line including the line ending (RFC
+    /// 5321 §4.5.3.1).
+    pub fn max_line_length(&self) -> usize {
         match self.mode {
-            Mode::Open => unreachable!("no terminator while in open mode"),
-            Mode::Line => LINE_TERMINATOR,
-            Mode::Data => DATA_TERMINATOR,
+            Mode::Open => unreachable!("no line length while in open mode"),
+            Mode::Data => self.config.max_line_length,
+            Mode::Chunk => unreachable!("no line length while receiving a chunk"),
+            // AUTH responses may be much longer than commands (RFC 4954 §4)
+            Mode::Line | Mode::Auth if self.config.credentials.is_some() => AUTH_LINE_LENGTH,
+            Mode::Line | Mode::Auth => COMMAND_LINE_LENGTH,
         }
     }
 
+    /// Called instead of receiving a line that exceeds [`Session::max_line_length`].
+    pub fn line_too_long(&mut self) {
+        self.overlong_line = true;
+    }
+
     pub fn buffer_mut(&mut self) -> &mut Vec<u8> {
         match self.mode {
             Mode::Open => unreachable!("no buffer while in open mode"),
-            Mode::Line => &mut self.line_buffer,
-            Mode::Data => &mut self.data_buffer,
+            Mode::Line | Mode::Data | Mode::Auth | Mode::Chunk => &mut self.line_buffer,
         }
     }
 
@@ -175,7 +683,395 @@ pub enum Mode {
     Open,
     Line,
     Data,
+    /// Waiting for the client's response to an authentication challenge.
+    Auth,
+    /// Receiving a BDAT chunk.
+    Chunk,
+}
+
+/// Progress of the mail transaction (RFC 5321 §3.3).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Transaction {
+    /// No transaction, MAIL starts one.
+    #[default]
+    None,
+    /// MAIL was accepted, RCPT may follow.
+    Mail,
+    /// At least one recipient was accepted, DATA may follow.
+    Rcpt,
+    /// At least one BDAT chunk was accepted, only BDAT may follow.
+    Chunks,
+}
+
+/// A BDAT chunk that is being received.
+#[derive(Debug)]
+struct Chunk {
+    size: usize,
+    remaining: usize,
+    last: bool,
+    /// The reply if the chunk is rejected once it is received.
+    error: Option<(Code, &'static str)>,
+}
+
+/// Whether the mail is internationalized (RFC 6531 §3.4), which allows non-ASCII
+/// addresses.
+fn smtputf8(mail_parameters: &HashMap<String, String>) -> bool {
+    mail_parameters
+        .keys()
+        .any(|keyword| keyword.eq_ignore_ascii_case("SMTPUTF8"))
 }
 
 const LINE_TERMINATOR: &[u8] = b"\r\n";
-const DATA_TERMINATOR: &[u8] = b"\r\n.\r\n";
+const COMMAND_LINE_LENGTH: usize = 512;
+const AUTH_LINE_LENGTH: usize = 12288;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::extension::{
+        BinaryMime, Chunking, EightBitMime, EnhancedStatusCodes, Pipelining, Size, SmtpUtf8,
+        StartTls,
+    };
+    use crate::Credentials;
+
+    fn auth_session(credentials: Credentials) -> Session {
+        let mut config = config(vec![Box::new(extension::Auth(vec!["PLAIN".into()]))]);
+        config.credentials = Some(credentials);
+        let mut session = start(config);
+        send(&mut session, "EHLO client.test\r\n");
+        session
+    }
+
+    fn config(extensions: Vec<Box<dyn Extension>>) -> Config {
+        let enhanced_status_codes = extensions
+            .iter()
+            .any(|ext| ext.keyword() == "ENHANCEDSTATUSCODES");
+        let chunking = extensions.iter().any(|ext| ext.keyword() == "CHUNKING");
+        Config {
+            on_new_mail: Arc::new(|_| {}),
+            hostname: "mercury.test".to_owned(),
+            extensions,
+            enhanced_status_codes,
+            chunking,
+            tls: None,
+            starttls: false,
+            credentials: None,
+            strict: false,
+            bare_line_endings: BareLineEndings::Normalize,
+            max_line_length: 1000,
+            max_message_size: None,
+            directory: Default::default(),
+        }
+    }
+
+    fn start(config: Config) -> Session {
+        let mut session = Session::new(Arc::new(config), "127.0.0.1:2525".parse().unwrap());
+        assert_eq!(recv(&mut session), "220 service ready\r\n");
+        session
+    }
+
+    fn session(extensions: Vec<Box<dyn Extension>>) -> Session {
+        start(config(extensions))
+    }
+
+    fn recv(session: &mut Session) -> String {
+        let mut reply = Reply::default();
+        session.on_recv(&mut reply);
+        if reply.is_empty() {
+            return String::new();
+        }
+        reply.finish();
+        String::from_utf8(reply.data().to_vec()).unwrap()
+    }
+
+    /// Sends the lines one by one, like the connection does, and returns all
+    /// replies.
+    fn send(session: &mut Session, lines: &str) -> String {
+        lines
+            .split_inclusive('\n')
+            .map(|line| {
+                session.buffer_mut().extend(line.as_bytes());
+                recv(session)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ehlo_advertises_extensions() {
+        let mut session = session(vec![
+            Box::new(Size(Some(1024))),
+            Box::new(Pipelining),
+            Box::new(EightBitMime),
+        ]);
+        assert_eq!(
+            send(&mut session, "EHLO client.test\r\n"),
+            "250-mercury.test greets client.test\r\n\
+             250-SIZE 1024\r\n\
+             250-PIPELINING\r\n\
+             250 8BITMIME\r\n"
+        );
+    }
+
+    #[test]
+    fn ehlo_without_extensions() {
+        let mut session = session(Vec::new());
+        assert_eq!(
+            send(&mut session, "EHLO client.test\r\n"),
+            "250 mercury.test greets client.test\r\n"
+        );
+    }
+
+    #[test]
+    fn validate_mail_parameters() {
+        let mut session = session(vec![Box::new(Size(None)), Box::new(EightBitMime)]);
+        send(&mut session, "EHLO client.test\r\n");
+        assert!(send(
+            &mut session,
+            "MAIL FROM:<sender@example.test> size=10 BODY=8BITMIME\r\n"
+        )
+        .starts_with("250 "));
+        assert!(
+            send(&mut session, "MAIL FROM:<sender@example.test> SIZE=ten\r\n").starts_with("501 ")
+        );
+        assert!(
+            send(&mut session, "MAIL FROM:<sender@example.test> SMTPUTF8\r\n").starts_with("555 ")
+        );
+        assert!(
+            send(&mut session, "RCPT TO:<rcpt@example.test> NOTIFY=NEVER\r\n").starts_with("555 ")
+        );
+    }
+
+    #[test]
+    fn max_message_size() {
+        let mut config = config(vec![Box::new(Size(Some(100)))]);
+        config.max_message_size = Some(100);
+        let mut session = start(config);
+        assert!(send(&mut session, "EHLO client.test\r\n").contains("250 SIZE 100\r\n"));
+        assert!(
+            send(&mut session, "MAIL FROM:<sender@example.test> SIZE=101\r\n").starts_with("552 ")
+        );
+        send(&mut session, "MAIL FROM:<sender@example.test> SIZE=100\r\n");
+        send(&mut session, "RCPT TO:<rcpt@example.test>\r\n");
+        send(&mut session, "DATA\r\n");
+        let line = format!("{}\r\n", "x".repeat(98));
+        assert_eq!(send(&mut session, &line), "");
+        assert_eq!(send(&mut session, &line), "");
+        assert!(session.data_buffer.is_empty());
+        assert_eq!(
+            send(&mut session, ".\r\n"),
+            "552 message exceeds the maximum message size\r\n"
+        );
+        // the transaction is aborted
+        assert!(send(&mut session, "DATA\r\n").starts_with("503 "));
+    }
+
+    #[test]
+    fn bdat_sequence() {
+        let mut without_chunking = session(Vec::new());
+        assert!(send(&mut without_chunking, "BDAT 3\r\n").starts_with("502 "));
+
+        let mut session = session(vec![Box::new(Chunking) as _, Box::new(BinaryMime)]);
+        send(&mut session, "EHLO client.test\r\n");
+        // a rejected chunk is still received
+        assert_eq!(send(&mut session, "BDAT 6\r\n"), "");
+        assert_eq!(send(&mut session, "MAIL"), "");
+        assert_eq!(send(&mut session, "\r\n"), "503 no mail transaction\r\n");
+
+        send(
+            &mut session,
+            "MAIL FROM:<sender@example.test> BODY=BINARYMIME\r\n",
+        );
+        send(&mut session, "RCPT TO:<rcpt@example.test>\r\n");
+        assert!(send(&mut session, "DATA\r\n").starts_with("503 "));
+        assert_eq!(send(&mut session, "BDAT 3\r\n"), "");
+        assert_eq!(send(&mut session, "abc"), "250 3 octets received\r\n");
+        assert!(send(&mut session, "DATA\r\n").starts_with("503 "));
+        assert!(send(&mut session, "RCPT TO:<rcpt@example.test>\r\n").starts_with("503 "));
+        assert_eq!(
+            send(&mut session, "BDAT 0 LAST\r\n"),
+            "250 requested mail action okay\r\n"
+        );
+        assert!(send(&mut session, "BDAT 0 LAST\r\n").starts_with("503 "));
+    }
+
+    #[test]
+    fn smtputf8_addresses() {
+        let mut session = session(vec![Box::new(SmtpUtf8)]);
+        send(&mut session, "EHLO client.test\r\n");
+        assert_eq!(
+            send(&mut session, "MAIL FROM:<jöhn@example.test>\r\n"),
+            "553 non-ASCII address requires SMTPUTF8\r\n"
+        );
+        send(&mut session, "MAIL FROM:<sender@example.test>\r\n");
+        assert!(send(&mut session, "RCPT TO:<用户@例子.广告>\r\n").starts_with("553 "));
+
+        assert!(
+            send(&mut session, "MAIL FROM:<jöhn@example.test> SMTPUTF8\r\n").starts_with("250 ")
+        );
+        assert!(send(&mut session, "RCPT TO:<用户@例子.广告>\r\n").starts_with("250 "));
+        assert!(send(&mut session, "MAIL FROM:<j\u{fffd}hn@example.test>\r\n").starts_with("553 "));
+    }
+
+    #[test]
+    fn reject_parameters_without_ehlo() {
+        let mut session = session(vec![Box::new(Size(None))]);
+        send(&mut session, "HELO client.test\r\n");
+        assert!(
+            send(&mut session, "MAIL FROM:<sender@example.test> SIZE=10\r\n").starts_with("555 ")
+        );
+    }
+
+    #[test]
+    fn enhanced_status_codes() {
+        let mut session = session(vec![Box::new(EnhancedStatusCodes)]);
+        assert_eq!(
+            send(&mut session, "EHLO client.test\r\n"),
+            "250-mercury.test greets client.test\r\n250 ENHANCEDSTATUSCODES\r\n"
+        );
+        assert_eq!(
+            send(&mut session, "NOOP\r\n"),
+            "250 2.0.0 requested mail action okay\r\n"
+        );
+        send(&mut session, "MAIL FROM:<sender@example.test>\r\n");
+        send(&mut session, "RCPT TO:<rcpt@example.test>\r\n");
+        assert_eq!(send(&mut session, "DATA\r\n"), "354 start mail input\r\n");
+    }
+
+    #[test]
+    fn starttls() {
+        let mut config = config(vec![Box::new(StartTls)]);
+        config.tls = Some(TlsConfig::self_signed("mercury.test").unwrap());
+        config.starttls = true;
+        let mut session = start(config);
+        assert_eq!(
+            send(&mut session, "EHLO client.test\r\n"),
+            "250-mercury.test greets client.test\r\n250 STARTTLS\r\n"
+        );
+        send(&mut session, "MAIL FROM:<sender@example.test>\r\n");
+        assert_eq!(
+            send(&mut session, "STARTTLS\r\n"),
+            "220 ready to start TLS\r\n"
+        );
+        assert!(session.starttls_pending());
+
+        session.on_tls(TlsInfo {
+            protocol: "TLSv1_3".to_owned(),
+            cipher: "TLS13_AES_256_GCM_SHA384".to_owned(),
+        });
+        assert!(!session.starttls_pending());
+        assert_eq!(session.helo, None);
+        assert!(session.reverse_path.is_empty());
+        assert_eq!(
+            send(&mut session, "EHLO client.test\r\n"),
+            "250 mercury.test greets client.test\r\n"
+        );
+        assert!(send(&mut session, "STARTTLS\r\n").starts_with("503 "));
+    }
+
+    #[test]
+    fn starttls_without_tls_config() {
+        let mut session = session(vec![Box::new(StartTls)]);
+        assert_eq!(
+            send(&mut session, "EHLO client.test\r\n"),
+            "250 mercury.test greets client.test\r\n"
+        );
+        assert!(send(&mut session, "STARTTLS\r\n").starts_with("502 "));
+    }
+
+    #[test]
+    fn auth_login_records_username() {
+        let mut session = auth_session(Credentials::Any);
+        assert_eq!(send(&mut session, "AUTH LOGIN\r\n"), "334 VXNlcm5hbWU6\r\n");
+        assert_eq!(send(&mut session, "dXNlcg==\r\n"), "334 UGFzc3dvcmQ6\r\n");
+        assert!(send(&mut session, "cGFzcw==\r\n").starts_with("235 "));
+        assert_eq!(session.auth_username.as_deref(), Some("user"));
+        assert!(send(&mut session, "AUTH PLAIN AHVzZXIAcGFzcw==\r\n").starts_with("503 "));
+    }
+
+    #[test]
+    fn auth_rejects_unknown_user() {
+        let users = [("user".to_owned(), "pass".to_owned())].into();
+        let mut session = auth_session(Credentials::Users(users));
+        assert_eq!(send(&mut session, "AUTH PLAIN\r\n"), "334 \r\n");
+        // "\0other\0pass"
+        assert!(send(&mut session, "AG90aGVyAHBhc3M=\r\n").starts_with("535 "));
+        assert_eq!(session.auth_username, None);
+        assert!(send(&mut session, "AUTH PLAIN AHVzZXIAcGFzcw==\r\n").starts_with("235 "));
+    }
+
+    #[test]
+    fn auth_sequence() {
+        let mut session = session(Vec::new());
+        send(&mut session, "EHLO client.test\r\n");
+        assert!(send(&mut session, "AUTH PLAIN\r\n").starts_with("502 "));
+
+        let mut session = auth_session(Credentials::Any);
+        send(&mut session, "HELO client.test\r\n");
+        assert!(send(&mut session, "AUTH PLAIN\r\n").starts_with("503 "));
+        send(&mut session, "EHLO client.test\r\n");
+        assert!(send(&mut session, "AUTH GSSAPI\r\n").starts_with("504 "));
+        send(&mut session, "AUTH LOGIN\r\n");
+        assert!(send(&mut session, "*\r\n").starts_with("501 "));
+        assert!(send(&mut session, "NOOP\r\n").starts_with("250 "));
+    }
+
+    #[test]
+    fn command_sequence() {
+        let mut session = session(Vec::new());
+        assert!(send(&mut session, "RCPT TO:<rcpt@example.test>\r\n").starts_with("503 "));
+        assert!(send(&mut session, "DATA\r\n").starts_with("503 "));
+        // a lenient server accepts MAIL without greeting
+        assert!(send(&mut session, "MAIL FROM:<sender@example.test>\r\n").starts_with("250 "));
+        assert_eq!(
+            send(&mut session, "DATA\r\n"),
+            "554 no valid recipients\r\n"
+        );
+        // and MAIL implicitly starts a new transaction
+        assert!(send(&mut session, "MAIL FROM:<other@example.test>\r\n").starts_with("250 "));
+        assert!(send(&mut session, "RCPT TO:<rcpt@example.test>\r\n").starts_with("250 "));
+        assert!(send(&mut session, "RSET\r\n").starts_with("250 "));
+        assert!(send(&mut session, "DATA\r\n").starts_with("503 "));
+    }
+
+    #[test]
+    fn strict_command_sequence() {
+        let mut config = config(Vec::new());
+        config.strict = true;
+        let mut session = start(config);
+        assert!(send(&mut session, "MAIL FROM:<sender@example.test>\r\n").starts_with("503 "));
+        send(&mut session, "HELO client.test\r\n");
+        assert!(send(&mut session, "MAIL FROM:<sender@example.test>\r\n").starts_with("250 "));
+        assert!(send(&mut session, "MAIL FROM:<sender@example.test>\r\n").starts_with("503 "));
+        assert!(send(&mut session, "RCPT TO:<rcpt@example.test>\r\n").starts_with("250 "));
+        assert_eq!(send(&mut session, "DATA\r\n"), "354 start mail input\r\n");
+        assert_eq!(
+            send(&mut session, "Subject: test\r\n\r\ntest\r\n.\r\n"),
+            "250 requested mail action okay\r\n"
+        );
+        assert!(send(&mut session, "MAIL FROM:<sender@example.test>\r\n").starts_with("250 "));
+    }
+
+    #[test]
+    fn help() {
+        let mut session = session(Vec::new());
+        assert_eq!(
+            send(&mut session, "HELP\r\n"),
+            "214-mercury.test supports the commands:\r\n\
+             214-  HELO EHLO MAIL RCPT DATA RSET\r\n\
+             214-  NOOP QUIT VRFY EXPN HELP\r\n\
+             214 use HELP <command> for details\r\n"
+        );
+        assert_eq!(
+            send(&mut session, "HELP mail\r\n"),
+            "214 MAIL FROM:<reverse-path> [parameters]\r\n"
+        );
+        assert!(send(&mut session, "HELP STARTTLS\r\n").starts_with("504 "));
+    }
+
+    #[test]
+    fn vrfy_without_directory() {
+        let mut session = session(Vec::new());
+        assert!(send(&mut session, "VRFY <user@example.test>\r\n").starts_with("252 "));
+        assert!(send(&mut session, "EXPN staff\r\n").starts_with("252 "));
+    }
+}
@@ -6,10 +6,10 @@ use nom::{
     branch::alt,
     bytes::complete::{tag, tag_no_case, take_while, take_while1, take_while_m_n},
     character::{
-        complete::{alphanumeric1, char, satisfy},
+        complete::{alphanumeric1, char, digit1, satisfy},
         is_digit, is_hex_digit,
     },
-    combinator::{eof, opt, recognize, value},
+    combinator::{eof, map_res, opt, recognize, value},
     multi::{many0_count, many1_count, separated_list1},
     sequence::{delimited, pair, preceded, separated_pair, terminated, tuple},
     IResult,
@@ -32,6 +32,9 @@ pub enum Command {
     HELP { string: String, },
     NOOP { string: String, },
     QUIT,
+    STARTTLS,
+    AUTH { mechanism: String, initial_response: Option<String>, },
+    BDAT { size: usize, last: bool, },
 }
 
 impl Command {
@@ -54,6 +57,9 @@ enum CommandKind {
     HELP,
     NOOP,
     QUIT,
+    STARTTLS,
+    AUTH,
+    BDAT,
 }
 
 fn command(i: &[u8]) -> Result<Command, Code> {
@@ -69,6 +75,9 @@ fn command(i: &[u8]) -> Result<Command, Code> {
         (i, CommandKind::HELP) => help(i),
         (i, CommandKind::NOOP) => noop(i),
         (i, CommandKind::QUIT) => quit(i),
+        (i, CommandKind::STARTTLS) => starttls(i),
+        (i, CommandKind::AUTH) => auth(i),
+        (i, CommandKind::BDAT) => bdat(i),
     };
     let (i, cmd) = res.map_err(|_| Code::BAD_PARAMETER)?;
     let (i, _) = take_while::<_, _, nom::error::Error<&[u8]>>(|ch: u8| {
@@ -83,33 +92,30 @@ fn command(i: &[u8]) -> Result<Command, Code> {
 }
 
 fn ehlo(i: &[u8]) -> IResult<&[u8], Command> {
-    let (i, domain) = preceded(char(' '), alt((domain, address_literal)))(i)?;
-    let domain = std::str::from_utf8(domain)
-        .expect("domain not valid UTF-8")
-        .to_owned();
+    let (i, domain) = preceded(char(' '), utf8(alt((domain, address_literal))))(i)?;
+    let domain = domain.to_owned();
     Ok((i, Command::EHLO { domain }))
 }
 
 fn helo(i: &[u8]) -> IResult<&[u8], Command> {
-    let (i, domain) = preceded(char(' '), domain)(i)?;
-    let domain = std::str::from_utf8(domain)
-        .expect("domain not valid UTF-8")
-        .to_owned();
+    let (i, domain) = preceded(char(' '), utf8(domain))(i)?;
+    let domain = domain.to_owned();
     Ok((i, Command::HELO { domain }))
 }
 
 fn mail(i: &[u8]) -> IResult<&[u8], Command> {
     let (i, (rp, mp)) = preceded(
         tag_no_case(" FROM:"),
-        pair(reverse_path, opt(preceded(char(' '), mail_parameters))),
+        pair(
+            utf8(reverse_path),
+            opt(preceded(char(' '), mail_parameters)),
+        ),
     )(i)?;
 
     Ok((
         i,
         Command::MAIL {
-            reverse_path: std::str::from_utf8(rp)
-                .expect("reverse-path not valid UTF-8")
-                .to_owned(),
+            reverse_path: rp.to_owned(),
             mail_parameters: mp.unwrap_or_default(),
         },
     ))
@@ -126,15 +132,16 @@ fn rcpt(i: &[u8]) -> IResult<&[u8], Command> {
 
     let (i, (fp, rp)) = preceded(
         tag_no_case(" TO:"),
-        pair(forward_path_ext, opt(preceded(char(' '), rcpt_parameters))),
+        pair(
+            utf8(forward_path_ext),
+            opt(preceded(char(' '), rcpt_parameters)),
+        ),
     )(i)?;
 
     Ok((
         i,
         Command::RCPT {
-            forward_path: std::str::from_utf8(fp)
-                .expect("reverse-path not valid UTF-8")
-                .to_owned(),
+            forward_path: fp.to_owned(),
             rcpt_parameters: rp.unwrap_or_default(),
         },
     ))
@@ -149,50 +156,40 @@ fn rset(i: &[u8]) -> IResult<&[u8], Command> {
 }
 
 fn vrfy(i: &[u8]) -> IResult<&[u8], Command> {
-    let (i, o) = preceded(char(' '), string)(i)?;
+    let (i, o) = preceded(char(' '), utf8(vrfy_string))(i)?;
     Ok((
         i,
         Command::VRFY {
-            string: std::str::from_utf8(o)
-                .expect("vrfy string not valid UTF-8")
-                .to_owned(),
+            string: o.to_owned(),
         },
     ))
 }
 
 fn expn(i: &[u8]) -> IResult<&[u8], Command> {
-    let (i, o) = preceded(char(' '), string)(i)?;
+    let (i, o) = preceded(char(' '), utf8(vrfy_string))(i)?;
     Ok((
         i,
         Command::EXPN {
-            string: std::str::from_utf8(o)
-                .expect("expn string not valid UTF-8")
-                .to_owned(),
+            string: o.to_owned(),
         },
     ))
 }
 
+/// The argument of VRFY and EXPN. RFC 5321 only allows a `String`, but clients
+/// commonly send a mailbox or path, so those are accepted as well.
+fn vrfy_string(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    alt((path, mailbox, string))(i)
+}
+
 fn help(i: &[u8]) -> IResult<&[u8], Command> {
-    let (i, o) = opt(preceded(char(' '), string))(i)?;
-    let string = o
-        .map(|o| {
-            std::str::from_utf8(o)
-                .expect("help string not valid UTF-8")
-                .to_owned()
-        })
-        .unwrap_or_else(String::new);
+    let (i, o) = opt(preceded(char(' '), utf8(string)))(i)?;
+    let string = o.map(str::to_owned).unwrap_or_else(String::new);
     Ok((i, Command::HELP { string }))
 }
 
 fn noop(i: &[u8]) -> IResult<&[u8], Command> {
-    let (i, o) = opt(preceded(char(' '), string))(i)?;
-    let string = o
-        .map(|o| {
-            std::str::from_utf8(o)
-                .expect("noop string not valid UTF-8")
-                .to_owned()
-        })
-        .unwrap_or_else(String::new);
+    let (i, o) = opt(preceded(char(' '), utf8(string)))(i)?;
+    let string = o.map(str::to_owned).unwrap_or_else(String::new);
     Ok((i, Command::NOOP { string }))
 }
 
@@ -200,6 +197,44 @@ fn quit(i: &[u8]) -> IResult<&[u8], Command> {
     Ok((i, Command::QUIT))
 }
 
+fn starttls(i: &[u8]) -> IResult<&[u8], Command> {
+    Ok((i, Command::STARTTLS))
+}
+
+fn auth(i: &[u8]) -> IResult<&[u8], Command> {
+    let (i, (mechanism, initial_response)) = preceded(
+        char(' '),
+        pair(
+            utf8(sasl_mech),
+            opt(preceded(char(' '), utf8(initial_response))),
+        ),
+    )(i)?;
+
+    Ok((
+        i,
+        Command::AUTH {
+            mechanism: mechanism.to_ascii_uppercase(),
+            initial_response: initial_response.map(str::to_owned),
+        },
+    ))
+}
+
+fn bdat(i: &[u8]) -> IResult<&[u8], Command> {
+    let chunk_size = map_res(utf8(digit1), str::parse::<usize>);
+    let (i, (size, last)) = preceded(
+        char(' '),
+        pair(chunk_size, opt(preceded(char(' '), tag_no_case("LAST")))),
+    )(i)?;
+
+    Ok((
+        i,
+        Command::BDAT {
+            size,
+            last: last.is_some(),
+        },
+    ))
+}
+
 fn command_name(i: &[u8]) -> IResult<&[u8], CommandKind> {
     alt((
         value(CommandKind::EHLO, tag_no_case("EHLO")),
@@ -213,6 +248,9 @@ fn command_name(i: &[u8]) -> IResult<&[u8], CommandKind> {
         value(CommandKind::HELP, tag_no_case("HELP")),
         value(CommandKind::NOOP, tag_no_case("NOOP")),
         value(CommandKind::QUIT, tag_no_case("QUIT")),
+        value(CommandKind::STARTTLS, tag_no_case("STARTTLS")),
+        value(CommandKind::AUTH, tag_no_case("AUTH")),
+        value(CommandKind::BDAT, tag_no_case("BDAT")),
     ))(i)
 }
 
@@ -234,15 +272,7 @@ fn mail_parameters(i: &[u8]) -> IResult<&[u8], HashMap<String, String>> {
         (
             i,
             p.into_iter()
-                .map(|(k, v)| {
-                    let k = std::str::from_utf8(k)
-                        .expect("esmtp-value invalid utf-8")
-                        .to_owned();
-                    let v = std::str::from_utf8(v)
-                        .expect("esmtp-value invalid utf-8")
-                        .to_owned();
-                    (k, v)
-                })
+                .map(|(k, v)| (k.to_owned(), v.unwrap_or_default().to_owned()))
                 .collect(),
         )
     })
@@ -252,8 +282,12 @@ fn rcpt_parameters(i: &[u8]) -> IResult<&[u8], HashMap<String, String>> {
     mail_parameters(i)
 }
 
-fn esmtp_param(i: &[u8]) -> IResult<&[u8], (&[u8], &[u8])> {
-    separated_pair(esmtp_keyword, char('='), esmtp_value)(i)
+/// esmtp-param := esmtp-keyword ["=" esmtp-value]
+fn esmtp_param(i: &[u8]) -> IResult<&[u8], (&str, Option<&str>)> {
+    pair(
+        utf8(esmtp_keyword),
+        opt(preceded(char('='), utf8(esmtp_value))),
+    )(i)
 }
 
 fn esmtp_keyword(i: &[u8]) -> IResult<&[u8], &[u8]> {
@@ -262,8 +296,26 @@ fn esmtp_keyword(i: &[u8]) -> IResult<&[u8], &[u8]> {
     recognize(pair(first, rem))(i)
 }
 
+/// esmtp-value := 1*(%d33-60 / %d62-126 / UTF8-non-ascii)
 fn esmtp_value(i: &[u8]) -> IResult<&[u8], &[u8]> {
-    take_while1(|ch| matches!(ch, 33..=60 | 62..=126))(i)
+    recognize(many1_count(alt((
+        take_while1(|ch| matches!(ch, 33..=60 | 62..=126)),
+        utf8_non_ascii,
+    ))))(i)
+}
+
+/// sasl-mech := 1*20(UPPER-ALPHA / DIGIT / "-" / "_")
+///
+/// Lowercase letters are accepted as well, mechanism names are case-insensitive.
+fn sasl_mech(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    take_while_m_n(1, 20, |ch: u8| {
+        ch.is_ascii_alphanumeric() || ch == b'-' || ch == b'_'
+    })(i)
+}
+
+/// initial-response := base64 / "="
+fn initial_response(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    take_while1(|ch: u8| ch.is_ascii_alphanumeric() || matches!(ch, b'+' | b'/' | b'='))(i)
 }
 
 fn path(i: &[u8]) -> IResult<&[u8], &[u8]> {
@@ -294,8 +346,20 @@ fn domain(i: &[u8]) -> IResult<&[u8], &[u8]> {
     recognize(pair(subdomain, many0_count(pair(char('.'), subdomain))))(i)
 }
 
+/// sub-domain := Let-dig [Ldh-str] / U-label
 fn subdomain(i: &[u8]) -> IResult<&[u8], &[u8]> {
-    recognize(tuple((let_dig, ldh_str)))(i)
+    alt((u_label, recognize(pair(let_dig, opt(ldh_str)))))(i)
+}
+
+/// A label with at least one non-ASCII character (RFC 6531 §3.3). It isn't
+/// checked whether it is a valid IDNA label.
+fn u_label(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    let ldh = || alt((alphanumeric1, tag("-")));
+    recognize(tuple((
+        many0_count(ldh()),
+        utf8_non_ascii,
+        many0_count(alt((ldh(), utf8_non_ascii))),
+    )))(i)
 }
 
 fn address_literal(i: &[u8]) -> IResult<&[u8], &[u8]> {
@@ -377,20 +441,26 @@ fn string(i: &[u8]) -> IResult<&[u8], &[u8]> {
     alt((atom, quoted_string))(i)
 }
 
+/// Dot-string := Atom *("."  Atom)
 fn dot_string(i: &[u8]) -> IResult<&[u8], &[u8]> {
-    recognize(pair(atom, many0_count(atom)))(i)
+    recognize(pair(atom, many0_count(pair(char('.'), atom))))(i)
 }
 
+/// Atom := 1*atext, where atext includes UTF8-non-ascii (RFC 6531 §3.3)
 fn atom(i: &[u8]) -> IResult<&[u8], &[u8]> {
-    take_while1(is_atext)(i)
+    recognize(many1_count(alt((take_while1(is_atext), utf8_non_ascii))))(i)
 }
 
 fn quoted_string(i: &[u8]) -> IResult<&[u8], &[u8]> {
     delimited(char('"'), recognize(many0_count(qcontent_smtp)), char('"'))(i)
 }
 
-fn qcontent_smtp(i: &[u8]) -> IResult<&[u8], char> {
-    alt((qtext_smtp, quoted_pair_smtp))(i)
+fn qcontent_smtp(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    alt((
+        recognize(qtext_smtp),
+        recognize(quoted_pair_smtp),
+        utf8_non_ascii,
+    ))(i)
 }
 
 fn qtext_smtp(i: &[u8]) -> IResult<&[u8], char> {
@@ -404,6 +474,32 @@ fn quoted_pair_smtp(i: &[u8]) -> IResult<&[u8], char> {
     )(i)
 }
 
+/// A single non-ASCII character encoded as valid UTF-8.
+fn utf8_non_ascii(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    let len = match i.first() {
+        Some(0xC2..=0xDF) => 2,
+        Some(0xE0..=0xEF) => 3,
+        Some(0xF0..=0xF4) => 4,
+        _ => 0,
+    };
+    match i.get(..len) {
+        Some(ch) if len > 0 && std::str::from_utf8(ch).is_ok() => Ok((&i[len..], ch)),
+        _ => Err(nom::Err::Error(nom::error::Error::new(
+            i,
+            nom::error::ErrorKind::Satisfy,
+        ))),
+    }
+}
+
+/// Converts the input recognized by `f` to a string, which fails instead of
+/// panicking if it isn't valid UTF-8.
+fn utf8<'a, F>(f: F) -> impl FnMut(&'a [u8]) -> IResult<&'a [u8], &'a str>
+where
+    F: nom::Parser<&'a [u8], &'a [u8], nom::error::Error<&'a [u8]>>,
+{
+    map_res(f, std::str::from_utf8)
+}
+
 fn is_dcontent(ch: u8) -> bool {
     matches!(ch, 33..=90)
 }
@@ -545,6 +641,24 @@ mod test {
         );
     }
 
+    #[test]
+    fn parse_mail_with_keyword_only_parameter() {
+        let params = [
+            ("SMTPUTF8".to_owned(), String::new()),
+            ("BODY".to_owned(), "8BITMIME".to_owned()),
+        ]
+        .into_iter()
+        .collect::<HashMap<String, String>>();
+
+        assert_eq!(
+            Command::parse("MAIL FROM:<no-reply@rust-lang.org> SMTPUTF8 BODY=8BITMIME\r\n"),
+            Ok(Command::MAIL {
+                reverse_path: "no-reply@rust-lang.org".to_owned(),
+                mail_parameters: params,
+            })
+        );
+    }
+
     #[test]
     fn parse_rcpt_simple() {
         assert_eq!(
@@ -701,4 +815,122 @@ mod test {
     fn parse_quit() {
         assert_eq!(Command::parse("QUIT\r\n"), Ok(Command::QUIT));
     }
+
+    #[test]
+    fn parse_vrfy() {
+        for (line, string) in [
+            ("VRFY smith\r\n", "smith"),
+            ("VRFY \"John Smith\"\r\n", "John Smith"),
+            ("VRFY john@example.test\r\n", "john@example.test"),
+            ("VRFY <john@example.test>\r\n", "john@example.test"),
+        ] {
+            assert_eq!(
+                Command::parse(line),
+                Ok(Command::VRFY {
+                    string: string.to_owned()
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn parse_auth() {
+        assert_eq!(
+            Command::parse("AUTH plain AHVzZXIAcGFzcw==\r\n"),
+            Ok(Command::AUTH {
+                mechanism: "PLAIN".to_owned(),
+                initial_response: Some("AHVzZXIAcGFzcw==".to_owned()),
+            })
+        );
+        assert_eq!(
+            Command::parse("AUTH CRAM-MD5\r\n"),
+            Ok(Command::AUTH {
+                mechanism: "CRAM-MD5".to_owned(),
+                initial_response: None,
+            })
+        );
+        assert_eq!(Command::parse("AUTH\r\n"), Err(Code::BAD_PARAMETER));
+    }
+
+    #[test]
+    fn parse_starttls() {
+        assert_eq!(Command::parse("StartTLS\r\n"), Ok(Command::STARTTLS));
+        assert_eq!(
+            Command::parse("STARTTLS now\r\n"),
+            Err(Code::UNRECOGNIZED_COMMAND)
+        );
+    }
+
+    #[test]
+    fn parse_bdat() {
+        assert_eq!(
+            Command::parse("BDAT 1000\r\n"),
+            Ok(Command::BDAT {
+                size: 1000,
+                last: false,
+            })
+        );
+        assert_eq!(
+            Command::parse("bdat 0 last\r\n"),
+            Ok(Command::BDAT {
+                size: 0,
+                last: true,
+            })
+        );
+        assert_eq!(Command::parse("BDAT\r\n"), Err(Code::BAD_PARAMETER));
+        assert_eq!(
+            Command::parse("BDAT 99999999999999999999999\r\n"),
+            Err(Code::BAD_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn parse_dotted_local_part() {
+        assert_eq!(
+            Command::parse("MAIL FROM:<first.last@a.example.test>\r\n"),
+            Ok(Command::MAIL {
+                reverse_path: "first.last@a.example.test".to_owned(),
+                mail_parameters: HashMap::new(),
+            })
+        );
+        assert_eq!(
+            Command::parse("MAIL FROM:<first..last@example.test>\r\n"),
+            Err(Code::BAD_PARAMETER)
+        );
+    }
+
+    #[test]
+    fn parse_utf8_addresses() {
+        assert_eq!(
+            Command::parse("RCPT TO:<用户@例子.广告> ORCPT=utf-8;用户@例子.广告\r\n"),
+            Ok(Command::RCPT {
+                forward_path: "用户@例子.广告".to_owned(),
+                rcpt_parameters: [("ORCPT".to_owned(), "utf-8;用户@例子.广告".to_owned())].into(),
+            })
+        );
+        assert_eq!(
+            Command::parse("MAIL FROM:<\"Jöhn Dœ\"@bücher.example> SMTPUTF8\r\n"),
+            Ok(Command::MAIL {
+                reverse_path: "\"Jöhn Dœ\"@bücher.example".to_owned(),
+                mail_parameters: [("SMTPUTF8".to_owned(), String::new())].into(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_invalid_utf8() {
+        for line in [
+            &b"MAIL FROM:<j\xf6hn@example.test>\r\n"[..],
+            b"MAIL FROM:<\"\xff\"@example.test>\r\n",
+            b"RCPT TO:<rcpt@example.test> NOTIFY=\xc3\r\n",
+            b"EHLO b\xfccher.example\r\n",
+            b"NOOP \xed\xa0\x80\r\n",
+        ] {
+            let code = Command::parse(line).expect_err("invalid UTF-8 accepted");
+            assert!(
+                matches!(code, Code::UNRECOGNIZED_COMMAND | Code::BAD_PARAMETER),
+                "{line:?}"
+            );
+        }
+    }
 }
@@ -7,6 +7,7 @@ pub struct Reply {
     code: Option<Code>,
     data: Vec<u8>,
     dash: Option<NonZeroUsize>,
+    enhanced: bool,
 }
 
 impl Reply {
@@ -26,12 +27,19 @@ impl Reply {
         use std::io::Write as _;
 
         let code = self.code.expect("must provide a code before reply text");
-        write!(self.data, "{} ", u16::from(code)).expect("failed to write code to reply");
-
         if let Some(dash) = self.dash.take().map(NonZeroUsize::get) {
             self.data[dash] = b'-';
         }
+
+        write!(self.data, "{} ", u16::from(code)).expect("failed to write code to reply");
+        // the separator between the code and the text turns into a dash if
+        // another line follows
         self.dash = NonZeroUsize::new(self.data.len() - 1);
+        if self.enhanced {
+            if let Some(class) = code.enhanced_class() {
+                write!(self.data, "{}.0.0 ", class).expect("failed to write code to reply");
+            }
+        }
         self.data.extend(data.as_ref());
         self.data.extend(b"\r\n");
     }
@@ -40,6 +48,12 @@ impl Reply {
         self.code = Some(code);
     }
 
+    /// Prefixes all following lines with an enhanced status code (RFC 2034). The
+    /// generic `X.0.0` code of the reply's class is used.
+    pub fn enhanced_status_codes(&mut self, enabled: bool) {
+        self.enhanced = enabled;
+    }
+
     pub fn clear(&mut self) {
         self.code = None;
         self.dash = None;
@@ -59,6 +73,15 @@ impl Code {
         let internal = self.text_internal();
         (!internal.is_empty()).then_some(internal)
     }
+
+    /// The class of the enhanced status code for this code. Intermediate (3yz)
+    /// replies have no enhanced status code.
+    fn enhanced_class(&self) -> Option<u16> {
+        match self.0.get() / 100 {
+            class @ (2 | 4 | 5) => Some(class),
+            _ => None,
+        }
+    }
 }
 
 impl From<Code> for u16 {
@@ -119,6 +142,9 @@ define_codes! {
     /// 221 Service closing transmissiong channel
     (221, SERVICE_CLOSING, "service closing transmission channel")
 
+    /// 235 Authentication succeeded
+    (235, AUTH_SUCCEEDED, "authentication succeeded")
+
     /// 250 Requested mail action okay, completed
     (250, MAIL_ACTION_OKAY, "requested mail action okay")
 
@@ -128,6 +154,9 @@ define_codes! {
     /// 252 Cannot VRFY user, but will accept message and attempt delivery
     (252, CANNOT_VRFY_ACCEPT, "cannot VRFY user, will attempt delivery")
 
+    /// 334 Server challenge for an authentication exchange
+    (334, AUTH_CHALLENGE, "")
+
     /// 354 Start mail input; end with `<CRLF>.<CRLF>`
     (354, START_MAIL_INPUT, "start mail input")
 
@@ -168,6 +197,9 @@ define_codes! {
     /// 504 Command parameter not implemented
     (504, PARAMETER_NOT_IMPLEMENTED, "command parameter not implemented")
 
+    /// 535 Authentication credentials invalid
+    (535, AUTH_CREDENTIALS_INVALID, "authentication credentials invalid")
+
     /// 550 Requested action not taken: mailbox unavailable
     ///
     /// e.g. mailbox not found, no access, or command rejected for policy reasons.
@@ -192,3 +224,19 @@ define_codes! {
     /// 555 MAIL FROM/RCPT TO parameters not recognized or not implemented
     (555, MAIL_FROM_RCPT_TO_NOT_IMPLEMENTED, "MAIL FROM/RCPT TO parameters not recognized or not implemented")
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn multiline_enhanced_status_codes() {
+        let mut reply = Reply::default();
+        reply.enhanced_status_codes(true);
+        reply.code(Code::MAIL_ACTION_OKAY);
+        reply.line("first");
+        reply.line("second");
+        reply.finish();
+        assert_eq!(reply.data(), b"250-2.0.0 first\r\n250 2.0.0 second\r\n");
+    }
+}
@@ -0,0 +1,171 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+//! TLS for STARTTLS (RFC 3207) and implicit TLS (RFC 8314) connections.
+
+use std::{
+    io::BufReader,
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    rustls::{self, Certificate, PrivateKey, ServerConfig},
+    server::TlsStream,
+    TlsAcceptor,
+};
+
+use crate::error::{Error, Result};
+
+/// The certificate and key used for TLS connections.
+#[derive(Clone)]
+pub struct TlsConfig {
+    acceptor: TlsAcceptor,
+}
+
+impl TlsConfig {
+    /// Loads a PEM encoded certificate chain and private key (PKCS #8, RSA or
+    /// SEC1).
+    pub fn from_pem_files<P, Q>(cert_path: P, key_path: Q) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let mut reader = BufReader::new(std::fs::File::open(cert_path)?);
+        let certs = rustls_pemfile::certs(&mut reader)?
+            .into_iter()
+            .map(Certificate)
+            .collect();
+
+        let mut reader = BufReader::new(std::fs::File::open(key_path)?);
+        let key = loop {
+            match rustls_pemfile::read_one(&mut reader)? {
+                Some(
+                    rustls_pemfile::Item::PKCS8Key(key)
+                    | rustls_pemfile::Item::RSAKey(key)
+                    | rustls_pemfile::Item::ECKey(key),
+                ) => break PrivateKey(key),
+                Some(_) => continue,
+                None => return Err(Error::NoPrivateKey),
+            }
+        };
+
+        Self::new(certs, key)
+    }
+
+    /// Generates a self-signed certificate for `hostname`.
+    pub fn self_signed(hostname: &str) -> Result<Self> {
+        let cert = rcgen::generate_simple_self_signed(vec![hostname.to_owned()])?;
+        let key = PrivateKey(cert.serialize_private_key_der());
+        let cert = Certificate(cert.serialize_der()?);
+        Self::new(vec![cert], key)
+    }
+
+    fn new(certs: Vec<Certificate>, key: PrivateKey) -> Result<Self> {
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+        Ok(TlsConfig {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+        })
+    }
+
+    pub(crate) fn acceptor(&self) -> &TlsAcceptor {
+        &self.acceptor
+    }
+}
+
+/// The parameters negotiated for a TLS connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsInfo {
+    /// The protocol version, e.g. `TLSv1_3`.
+    pub protocol: String,
+    /// The IANA name of the cipher suite, e.g. `TLS13_AES_256_GCM_SHA384`.
+    pub cipher: String,
+}
+
+impl TlsInfo {
+    fn from_connection(conn: &rustls::ServerConnection) -> Self {
+        let protocol = conn
+            .protocol_version()
+            .map(|version| match version.as_str() {
+                Some(name) => name.to_owned(),
+                None => format!("{version:?}"),
+            })
+            .unwrap_or_default();
+        let cipher = conn
+            .negotiated_cipher_suite()
+            .map(|suite| match suite.suite().as_str() {
+                Some(name) => name.to_owned(),
+                None => format!("{:?}", suite.suite()),
+            })
+            .unwrap_or_default();
+        TlsInfo { protocol, cipher }
+    }
+}
+
+/// A client connection, which is upgraded to TLS either right away or after
+/// STARTTLS.
+pub(crate) enum Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl Stream {
+    /// Performs the TLS handshake on a plain connection.
+    pub(crate) async fn accept(self, config: &TlsConfig) -> Result<(Self, TlsInfo)> {
+        let tcp = match self {
+            Stream::Plain(tcp) => tcp,
+            Stream::Tls(_) => return Err(Error::TlsActive),
+        };
+        let tls = config.acceptor().accept(tcp).await?;
+        let info = TlsInfo::from_connection(tls.get_ref().1);
+        Ok((Stream::Tls(Box::new(tls)), info))
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}